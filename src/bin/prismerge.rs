@@ -85,842 +85,3766 @@
     databases.
 */
 
-use prismerge::data::{Column, Model, Schema};
+use prismerge::config::{Config, Input};
+use prismerge::conflicts::{self, Aggregate, Conflict, ConflictPolicy, ConflictTable};
+use prismerge::data::{Column, ColumnType, IgnorePolicy, Model, OrphanPolicy, Passthrough, PassthroughPolicy, Schema};
+use prismerge::ddl::{self, OutputSchema};
+use prismerge::extract::{self, Seed};
 use prismerge::insert_manager::InsertManager;
-use prismerge::prisma_parser;
+use prismerge::inspect;
+use prismerge::introspect;
+use prismerge::lint;
+use prismerge::migrations::{self, read_migrations, MigratedInputs};
+use prismerge::normalize::{self, normalize_typed};
+use prismerge::preflight::{self, Severity};
+use prismerge::prisma_parser::{self, SchemaFile};
 use prismerge::progress::ProgressIndicator;
+use prismerge::rejects::{RejectLog, RejectSink};
+use prismerge::report::{MergeReport, ModelReport};
 use prismerge::utils::format_duration;
-use std::{fs, time::SystemTime};
-use rusqlite::{Connection, Result};
+use prismerge::verify;
+use sha2::{Digest, Sha256};
+use std::{collections::{HashMap, HashSet}, fs, path::{Path, PathBuf}, time::{Instant, SystemTime}};
+use rusqlite::{Connection, OpenFlags, Result};
 use uuid::Uuid;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
+use glob::glob;
 
 #[derive(Parser, Debug)]
 #[command(
     name="prismerge",
     author="Cameron C. Dutro",
     version="1.0.0",
-    about="Merge SQLite databases together using their shared Prisma schema."
+    about="Merge SQLite databases together using their shared Prisma schema.",
+    args_conflicts_with_subcommands=true
 )]
-struct CLI {
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        long,
+        value_name="PATH",
+        help="The path to a prismerge.toml configuration file. Defaults to ./prismerge.toml if it exists. Command-line options take precedence over the file."
+    )]
+    config: Option<String>,
+
     #[arg(
         long,
         short,
         value_name="PATH",
-        help="The path to the Prisma schema file."
+        action=ArgAction::Append,
+        help="The path to the Prisma schema file, or to a directory of .prisma files. May be given multiple times for schemas split across several files. If not given, the schema is introspected from the first input database."
     )]
-    schema_path: String,
+    schema_path: Vec<String>,
 
     #[arg(
         long,
         short,
         value_name="PATH",
-        default_value="./merged.db",
-        help="The path of the merged database file."
+        help="The path of the merged database file. Defaults to the file the schema's datasource url points to if it doesn't exist yet, otherwise ./merged.db."
     )]
-    output_path: String,
+    output_path: Option<String>,
 
     #[arg(
         long,
         short,
         action=ArgAction::SetTrue,
+        overrides_with="no_keep_id_maps",
         help="After merging is complete, don't drop the temporary tables prismerge creates to keep track of old -> new foreign key mappings."
     )]
     keep_id_maps: bool,
 
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="keep_id_maps",
+        help="Drop the ID mapping tables after merging, even if the config file sets keep_id_maps."
+    )]
+    no_keep_id_maps: bool,
+
     #[arg(
         long,
         short,
         value_name="NUMBER",
-        default_value="1000",
-        help="The minimum number of rows to insert at a time."
+        help="The minimum number of rows to insert at a time. Defaults to 1000."
     )]
-    min_inserts: u64,
+    min_inserts: Option<u64>,
+
+    #[arg(
+        long,
+        value_name="SOURCE",
+        help="Where to get the output database's tables and indices from: input (copied from the input that best matches the schema, the default), migrations (applied from the Prisma migrations folder), or prisma (generated from the Prisma schema)."
+    )]
+    output_schema: Option<String>,
+
+    #[arg(
+        long,
+        value_name="PATH",
+        help="Path to the Prisma migrations folder used by --output-schema migrations. Defaults to the migrations folder next to the schema file."
+    )]
+    migrations_path: Option<String>,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="no_migrate",
+        help="Bring inputs created by older versions of the schema up to date by applying pending migrations from the migrations folder to temporary copies of them before merging."
+    )]
+    migrate: bool,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="migrate",
+        help="Merge older inputs as-is, even if the config file sets migrate."
+    )]
+    no_migrate: bool,
+
+    #[arg(
+        long,
+        value_name="PATH",
+        help="Write a JSON report containing per-model merge statistics to the given path."
+    )]
+    report: Option<String>,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="no_record_conflicts",
+        help="Record rows that match an existing row's unique key but differ in other columns in the _prismerge_conflicts table."
+    )]
+    record_conflicts: bool,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="record_conflicts",
+        help="Don't record conflicting duplicate rows, even if the config file sets record_conflicts."
+    )]
+    no_record_conflicts: bool,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="no_exclude_broken_inputs",
+        help="Leave out inputs with dangling foreign keys, duplicate unique keys, or corruption instead of merging them. These problems are always reported before merging."
+    )]
+    exclude_broken_inputs: bool,
+
+    #[arg(
+        long,
+        action=ArgAction::SetTrue,
+        overrides_with="exclude_broken_inputs",
+        help="Merge inputs with integrity problems, even if the config file sets exclude_broken_inputs."
+    )]
+    no_exclude_broken_inputs: bool,
+
+    #[arg(
+        long,
+        value_name="NUMBER",
+        help="Abort the merge once more than the given number of rows have been rejected because they couldn't be read or inserted. By default, every rejected row is recorded and the merge carries on."
+    )]
+    max_errors: Option<u64>,
+
+    #[arg(
+        long,
+        value_name="PATH",
+        help="Write rejected rows to the given JSON Lines file instead of the _prismerge_rejects table in the output database."
+    )]
+    rejects: Option<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL=POLICY",
+        action=ArgAction::Append,
+        help="How to resolve duplicate rows for the given model, one of keep-first, keep-last, newest:<column>, or coalesce. Use MODEL.COLUMN=AGGREGATE to combine numeric columns with max, min, or sum. May be given multiple times."
+    )]
+    conflict_policy: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL",
+        value_delimiter=',',
+        action=ArgAction::Append,
+        help="Detect duplicate rows of the given model, which has no unique key, by hashing the contents of each row. Identical rows are mapped to the row that was inserted first. May be given multiple times or as a comma-separated list."
+    )]
+    dedupe_by_hash: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL=POLICY",
+        action=ArgAction::Append,
+        help="What to do with the given model if it's marked @@ignore, and with its @ignore fields: skip (the default), raw-copy, or dedup. May be given multiple times."
+    )]
+    ignore_policy: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL.RELATION=POLICY",
+        action=ArgAction::Append,
+        help="What to do with rows of the given model whose foreign key for the given relation field doesn't match any parent row: drop (the default for required relations), null (the default for optional relations), keep (keep the untranslated key), or abort. May be given multiple times."
+    )]
+    orphans: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL",
+        value_delimiter=',',
+        action=ArgAction::Append,
+        help="Merge only the given models, along with the models they require via non-nullable relations. May be given multiple times or as a comma-separated list."
+    )]
+    only: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL",
+        value_delimiter=',',
+        action=ArgAction::Append,
+        help="Don't merge the given models, or any models that require them via non-nullable relations. May be given multiple times or as a comma-separated list."
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="TABLE=POLICY",
+        action=ArgAction::Append,
+        help="How to copy the given model without a primary key, or table that isn't in the schema: skip (the default), primary (copy rows from the input with the most rows only), or union (copy rows from every input). Append :dedup to drop identical rows, eg. union:dedup. May be given multiple times."
+    )]
+    passthrough: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL=PREDICATE",
+        action=ArgAction::Append,
+        help="Only merge rows of the given model that satisfy the given SQL predicate, eg. \"ComponentUsage=version >= '35'\". May be given multiple times."
+    )]
+    filter: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL=PREDICATE",
+        action=ArgAction::Append,
+        help="Extract mode: merge only the rows of the given model that satisfy the given SQL predicate, along with every row that references them and every row they reference, eg. \"Repository=name = 'prismerge'\". May be given multiple times."
+    )]
+    seed: Vec<String>,
+
+    #[arg(
+        long,
+        value_name="MODEL.COLUMN=OLD_NAME[@MIGRATION]",
+        action=ArgAction::Append,
+        help="Read the given column from a column called OLD_NAME in inputs created by an older version of the schema. With @MIGRATION, only inputs that haven't applied the given Prisma migration are read this way. May be given multiple times."
+    )]
+    rename: Vec<String>,
 
     #[arg(
         value_name="INPUT PATHS",
         num_args=1..,
-        required=true,
-        help="Paths to the SQLite database files to merge."
+        help="Paths to the SQLite database files to merge. Required unless inputs are listed in the config file."
     )]
     input_paths: Vec<String>,
 }
 
-fn main() -> Result<(), String> {
-    let start_time = SystemTime::now();
-    let options = CLI::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about="Generate a Prisma schema from the tables in a SQLite database.")]
+    Introspect {
+        #[arg(value_name="DATABASE PATH")]
+        database_path: String,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            help="Write the Prisma schema to the given path instead of printing it."
+        )]
+        output_path: Option<String>
+    },
+
+    #[command(about="Show how the Prisma schema is understood, as JSON or as a relation graph.")]
+    Inspect {
+        #[arg(
+            value_name="SCHEMA PATH",
+            help="The paths to the Prisma schema files, or to a directory of .prisma files. Defaults to the schema_path in the config file."
+        )]
+        schema_paths: Vec<String>,
+
+        #[arg(
+            long,
+            short,
+            default_value="json",
+            value_name="FORMAT",
+            help="One of json, dot (Graphviz), or mermaid."
+        )]
+        format: inspect::Format,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            help="The path to the config file. Defaults to prismerge.toml in the current directory, if it exists."
+        )]
+        config: Option<String>,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            help="Write the output to the given path instead of printing it."
+        )]
+        output_path: Option<String>
+    },
+
+    #[command(about="Check the Prisma schema for shapes that can't be merged safely. Exits with a non-zero status if there are errors.")]
+    Lint {
+        #[arg(
+            value_name="SCHEMA PATH",
+            help="The paths to the Prisma schema files, or to a directory of .prisma files. Defaults to the schema_path in the config file."
+        )]
+        schema_paths: Vec<String>,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            help="The path to the config file. Defaults to prismerge.toml in the current directory, if it exists."
+        )]
+        config: Option<String>,
+
+        #[arg(
+            long,
+            action=ArgAction::SetTrue,
+            help="Treat warnings as errors."
+        )]
+        deny_warnings: bool
+    },
+
+    #[command(about="Check a SQLite database for dangling foreign keys, duplicate unique keys, and corruption.")]
+    Verify {
+        #[arg(value_name="DATABASE PATH")]
+        database_path: String,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            action=ArgAction::Append,
+            help="The path to the Prisma schema file, or to a directory of .prisma files, whose unique keys to check. Defaults to the schema_path in the config file. Unique keys aren't checked without a schema."
+        )]
+        schema_path: Vec<String>,
+
+        #[arg(
+            long,
+            short,
+            value_name="PATH",
+            help="The path to the config file. Defaults to prismerge.toml in the current directory, if it exists."
+        )]
+        config: Option<String>,
+
+        #[arg(
+            long,
+            action=ArgAction::SetTrue,
+            help="Exit with a non-zero status if any problems are found."
+        )]
+        strict: bool
+    }
+}
 
-    // Load and parse the Prisma schema.
-    let source_code_str = fs::read_to_string(options.schema_path).unwrap();
-    let source_code = source_code_str.as_str();
-    let schema = prisma_parser::parse(source_code).unwrap();
+// Parse the Prisma schema and apply the config file's settings to it, eg. dedupe_by,
+// since they change how the schema is merged.
+fn load_schema(schema_paths: &[String], config_path: &Option<String>) -> Result<Schema, String> {
+    let config = Config::discover(config_path.as_deref())?.unwrap_or_default();
 
-    // Open all input databases.
-    let connections: Vec<Connection> = options.input_paths[1..]
-        .iter()
-        .map(|path| Connection::open(path).unwrap())
-        .collect();
+    let schema_paths = if schema_paths.is_empty() {
+        let schema_path = config.schema_path
+            .as_ref()
+            .ok_or("No schema path given. Pass it on the command line or set schema_path in the config file.")?;
 
-    // Open output database.
-    let merged = Connection::open(options.output_path.clone()).unwrap();
+        vec![config.resolve_path(schema_path)]
+    } else {
+        schema_paths.to_vec()
+    };
 
-    prismerge(&schema, &connections, &merged, options.min_inserts, true);
+    let mut schema = read_schema(&schema_paths)?;
+    config.apply_to(&mut schema)?;
 
-    // Make sure there are no foreign key integrity problems. If there are,
-    // print out warnings so the user knows what's up.
-    for (_, current_model) in &schema.models {
-        match current_model.verify_integrity(&merged) {
-            Err(count) => println!("Table {} has {} foreign key integrity problems", current_model.name, count),
-            _ => ()
-        }
+    Ok(schema)
+}
+
+// Return the path of the SQLite database the schema's datasource url points to. Like
+// Prisma, relative paths are resolved relative to the schema file. Returns None if the
+// url doesn't point to a file, or if the file already exists, eg. because it's one of
+// the inputs, since existing databases must never be written to.
+fn datasource_path(schema: &Schema, schema_paths: &[String]) -> Option<String> {
+    let path = schema.datasource.as_ref()?.file_path()?;
+
+    let path = match schema_dir(schema_paths) {
+        Some(schema_dir) => schema_dir.join(path),
+        None => PathBuf::from(path)
+    };
+
+    if path.exists() {
+        return None;
     }
 
-    // Clean up after ourselves by dropping all the map tables.
-    if !options.keep_id_maps {
-        for (_, current_model) in &schema.models {
-            current_model.map_table.drop_from(&merged);
-        }
+    Some(path.to_string_lossy().to_string())
+}
+
+// The directory the schema lives in, i.e. the first schema path if it's a directory,
+// or the directory containing it otherwise.
+fn schema_dir(schema_paths: &[String]) -> Option<PathBuf> {
+    let path = Path::new(schema_paths.first()?);
+
+    if path.is_dir() {
+        Some(path.to_path_buf())
+    } else {
+        path.parent().map(|dir| dir.to_path_buf())
     }
+}
 
-    // Reclaim space from deleted tables, etc.
-    vacuum(&merged);
+// Expand directories into the .prisma files they contain, including those in
+// subdirectories, in name order.
+fn schema_files(schema_paths: &[String]) -> Result<Vec<String>, String> {
+    let mut files = vec![];
 
-    // Report how long the whole merge process took.
-    match start_time.elapsed() {
-        Ok(elapsed) => {
-            println!("Finished in {}", format_duration(&elapsed));
+    for schema_path in schema_paths {
+        if !Path::new(schema_path).is_dir() {
+            files.push(schema_path.clone());
+            continue;
+        }
+
+        let pattern = Path::new(schema_path).join("**").join("*.prisma");
+
+        let mut dir_files = glob(&pattern.to_string_lossy())
+            .map_err(|e| e.to_string())?
+            .map(|entry| entry.map(|path| path.to_string_lossy().to_string()).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<String>, String>>()?;
+
+        if dir_files.is_empty() {
+            return Err(format!("No .prisma files found in {}", schema_path));
         }
 
-        Err(_) => {}
+        dir_files.sort();
+        files.append(&mut dir_files);
     }
 
-    Ok(())
+    Ok(files)
 }
 
-fn prismerge(schema: &Schema, connections: &Vec<Connection>, merged: &Connection, min_inserts: u64, show_progress: bool)  {
-    // Get a list of Model objects, sorted topologically so parent records are
-    // created before children.
-    let order = schema.sorted();
+// Read and parse the Prisma schema files, listing every problem found with its
+// location.
+fn read_schema(schema_paths: &[String]) -> Result<Schema, String> {
+    let files = schema_files(schema_paths)?
+        .into_iter()
+        .map(|path| {
+            let source = fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+            Ok(SchemaFile { path, source })
+        })
+        .collect::<Result<Vec<SchemaFile>, String>>()?;
+
+    prisma_parser::parse_files(&files).map_err(|parse_errors| {
+        format!("Unable to parse the schema ({} errors):\n\n{}", parse_errors.errors.len(), parse_errors)
+    })
+}
 
-    // Turn off a lot of important stuff so inserting is fast.
-    merged.execute_batch(r#"
-        PRAGMA synchronous = OFF;
-        PRAGMA journal_mode = OFF;
-        PRAGMA temp_store = MEMORY;
-        PRAGMA cache_size = -16000;
-        PRAGMA foreign_keys = OFF;
-    "#).unwrap();
+// Write the given contents to the output path, or print them if there isn't one.
+fn write_output(output_path: &Option<String>, contents: &str) -> Result<(), String> {
+    match output_path {
+        Some(output_path) => fs::write(output_path, contents).map_err(|e| format!("Unable to write {}: {}", output_path, e)),
+        None => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
 
-    // Set up the merged database by copying over the schema. Each row here is a
-    // CREATE TABLE or CREATE INDEX statement that we can execute directly on the
-    // merged database connection.
-    let mut schema_query = connections[0].prepare("SELECT sql FROM sqlite_master;").unwrap();
-    let mut schema_rows = schema_query.query(()).unwrap();
+impl Command {
+    fn run(self: &Self) -> Result<(), String> {
+        match self {
+            Command::Introspect { database_path, output_path } => {
+                let conn = Connection::open(database_path).map_err(|e| e.to_string())?;
+                let introspection = introspect::introspect(&conn)?;
 
-    loop {
-        match schema_rows.next() {
-            Ok(Some(row)) => {
-                let stmt = row.get::<usize, String>(0);
+                for warning in introspection.warnings.iter() {
+                    eprintln!("{}", warning);
+                }
 
-                match stmt {
-                    Ok(stmt) => {
-                        merged.execute(stmt.as_str(), ()).unwrap();
-                    }
+                write_output(output_path, &introspect::to_prisma(&introspection.schema))
+            }
+
+            Command::Inspect { schema_paths, format, config, output_path } => {
+                let schema = load_schema(schema_paths, config)?;
+                write_output(output_path, &inspect::render(&inspect::inspect(&schema), *format)?)
+            }
+
+            Command::Lint { schema_paths, config, deny_warnings } => {
+                let schema = load_schema(schema_paths, config)?;
+                let lints = lint::lint(&schema);
+
+                for lint in lints.iter() {
+                    println!("{}", lint);
+                }
 
-                    Err(_) => ()
+                let errors = lints.iter().filter(|lint| lint.severity == Severity::Error).count();
+                let warnings = lints.len() - errors;
+
+                println!("{} errors, {} warnings", errors, warnings);
+
+                if errors > 0 || (*deny_warnings && warnings > 0) {
+                    std::process::exit(1);
                 }
+
+                Ok(())
             }
 
-            Ok(None) => break,
-            Err(_) => break
+            Command::Verify { database_path, schema_path, config, strict } => {
+                let has_schema = !schema_path.is_empty() ||
+                    Config::discover(config.as_deref())?.is_some_and(|config| config.schema_path.is_some());
+
+                let schema = if has_schema {
+                    Some(load_schema(schema_path, config)?)
+                } else {
+                    None
+                };
+
+                // Existing databases must never be written to.
+                let conn = Connection::open_with_flags(database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                    .map_err(|e| format!("Unable to open {}: {}", database_path, e))?;
+
+                let verification = verify::verify(&conn, schema.as_ref())?;
+
+                for violation in verification.foreign_keys.iter() {
+                    println!("{}", violation);
+                }
+
+                for violation in verification.unique_keys.iter() {
+                    println!("{}", violation);
+                }
+
+                for message in verification.integrity.iter() {
+                    println!("integrity: {}", message);
+                }
+
+                println!(
+                    "{} foreign key problems, {} duplicate unique keys, {} integrity problems",
+                    verification.foreign_keys.len(),
+                    verification.unique_keys.len(),
+                    verification.integrity.len()
+                );
+
+                if *strict && !verification.is_ok() {
+                    std::process::exit(1);
+                }
+
+                Ok(())
+            }
         }
     }
+}
 
-    // Merge each model.
-    for current_model in &order {
-        merge_model(current_model, &schema, &connections, &merged, min_inserts, show_progress);
+const DEFAULT_OUTPUT_PATH: &str = "./merged.db";
+const DEFAULT_MIN_INSERTS: u64 = 1000;
+const MAX_LISTED_VIOLATIONS: usize = 5;
+
+impl Cli {
+    // Fill in any options not given on the command line from the config file.
+    fn merge_config(self: &mut Self, config: &Config) {
+        let resolve = |path: &Option<String>| path.as_ref().map(|path| config.resolve_path(path));
+
+        if self.schema_path.is_empty() {
+            self.schema_path = resolve(&config.schema_path).into_iter().collect();
+        }
+
+        self.output_path = self.output_path.take().or_else(|| resolve(&config.output_path));
+        self.report = self.report.take().or_else(|| resolve(&config.report));
+        self.output_schema = self.output_schema.take().or_else(|| config.output_schema.clone());
+        self.migrations_path = self.migrations_path.take().or_else(|| resolve(&config.migrations_path));
+        self.min_inserts = self.min_inserts.or(config.min_inserts);
+        self.max_errors = self.max_errors.or(config.max_errors);
+        self.rejects = self.rejects.take().or_else(|| resolve(&config.rejects));
+        self.keep_id_maps = enabled(self.keep_id_maps, self.no_keep_id_maps, config.keep_id_maps);
+        self.record_conflicts = enabled(self.record_conflicts, self.no_record_conflicts, config.record_conflicts);
+        self.migrate = enabled(self.migrate, self.no_migrate, config.migrate);
+        self.exclude_broken_inputs = enabled(self.exclude_broken_inputs, self.no_exclude_broken_inputs, config.exclude_broken_inputs);
+
+        if self.only.is_empty() {
+            self.only = config.only.clone();
+        }
+
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude.clone();
+        }
+
+        if self.seed.is_empty() {
+            self.seed = config.seed.clone();
+        }
     }
 
-    // Turn important things back on to ensure integrity, etc.
-    merged.execute_batch(r#"
-        PRAGMA synchronous = ON;
-        PRAGMA journal_mode = DELETE;
-        PRAGMA foreign_keys = ON;
-    "#).unwrap();
+    // Input paths given on the command line replace the ones in the config file.
+    fn inputs(self: &Self, config: &Config) -> Result<Vec<Input>, String> {
+        let inputs = if self.input_paths.is_empty() {
+            config.inputs()?
+        } else {
+            self.input_paths.iter().map(|path| Input::from_path(path)).collect()
+        };
+
+        if inputs.is_empty() {
+            return Err("No input databases given. Pass them on the command line or list them in the config file.".to_string());
+        }
+
+        Ok(inputs)
+    }
 }
 
-// Runs the SQLite VACUUM command which reclaims space from deleted tables, indices, etc.
-fn vacuum(conn: &Connection) {
-    conn.execute("VACUUM;", ()).unwrap();
+// Resolve a boolean option that can be turned on with --flag and off with --no-flag.
+// Either one overrides the config file.
+fn enabled(on: bool, off: bool, config: Option<bool>) -> bool {
+    on || (!off && config.unwrap_or(false))
 }
 
-// This is where most of the magic happens. This function merges the records for the
-// given Model, copying records from the databases in `connections` into the database
-// in `merged`. The min_inserts argument specifies how many INSERTs to batch up before
-// inserting in bulk.
-fn merge_model(model: &Model, schema: &Schema, connections: &Vec<Connection>, merged: &Connection, min_inserts: u64, show_progress: bool) {
-    model.map_table.create_into(&merged);
+// Settings that control how prismerge() merges the input databases.
+#[derive(Debug)]
+struct MergeOptions {
+    // The minimum number of rows to insert at a time.
+    min_inserts: u64,
+    show_progress: bool,
+    record_conflicts: bool,
 
-    let mut inserter = InsertManager::new(merged, min_inserts);
-    let primary_key = model.primary_key().unwrap();
-    let mut cols_to_copy: Vec<&Column> = vec![];
+    // The DDL to create the output database's tables and indices with. If None, the DDL
+    // is copied from the input that best matches the schema.
+    output_ddl: Option<Vec<String>>,
 
-    // Enumerate columns that will be copied wholesale, i.e. without any translation.
-    // In other words, all columns that aren't foreign keys.
-    for column in model.columns.iter() {
-        if column.is_regular(schema) {
-            cols_to_copy.push(column);
+    // Labels identifying each input in conflict records, in the same order as the
+    // connections. Connections without a label are identified by their path.
+    input_labels: Vec<String>,
+
+    // How to copy tables that aren't in the schema, by table name. Tables not listed
+    // are skipped.
+    passthrough: HashMap<String, Passthrough>,
+
+    // Where to record rows that couldn't be read or inserted, and how many of them to
+    // put up with before giving up.
+    rejects: RejectSink,
+    max_errors: Option<u64>
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            min_inserts: DEFAULT_MIN_INSERTS,
+            show_progress: false,
+            record_conflicts: false,
+            output_ddl: None,
+            input_labels: vec![],
+            passthrough: HashMap::new(),
+            rejects: RejectSink::default(),
+            max_errors: None
         }
     }
+}
 
-    let count_query = format!(
-        "SELECT COUNT({primary_key}) FROM \"{table}\" WHERE 1",
-        primary_key = primary_key.name,
-        table = model.name
-    );
+// Errors often list several problems on their own lines, so they're printed as-is
+// rather than returned from main, which would print them escaped.
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
 
-    // This is the query that will be used to iterate over all the rows in each of the
-    // input databases. We select two versions of the primary key, one quoted and one
-    // unquoted. It's important to select both because they are used in different contexts
-    // during the merge process.
-    //
-    // We also select quoted versions of all the other columns as well so they can be
-    // directly interpolated into INSERT statements without having to know what data type
-    // they are. It would be quite tedious to quote things or not depending on the type, so
-    // we let SQLite do the work for us.
-    let select_query = format!(
-        "SELECT \"{primary_key}\" AS unquoted_pk, quote(\"{primary_key}\") AS \"{primary_key}\", {quoted_columns} FROM \"{table}\" WHERE 1;",
-        quoted_columns = cols_to_copy
-            .iter()
-            .map(|col| format!("{} AS {}", col.quoted(&model.name), col.name))
-            .collect::<Vec<String>>()
-            .join(", "),
-        primary_key = primary_key.name,
-        table = model.name
-    );
+fn run() -> Result<(), String> {
+    let start_time = SystemTime::now();
+    let mut options = Cli::parse();
 
-    let mut check_sql_template: Option<String> = None;
+    if let Some(command) = &options.command {
+        return command.run();
+    }
 
-    // If the model has a unique index, we want to use it to query for existing records.
-    // We enumerate all of its columns here and build up a SELECT query. This query not
-    // only has to check existing "regular" columns (i.e. columns that are not foreign
-    // keys), but also foreign keys that will have been translated into new keys via one
-    // of the mapping tables. The resulting query includes a a JOIN clause for each of the
-    // foreign keys, as well as a WHERE clause containing normal comparisons for the
-    // regular columns and comparisons to the mapped old ID for all foreign keys.
-    if let Some(unique) = &model.unique {
-        let mut check_wheres: Vec<String> = vec![];
-        let mut check_joins: Vec<String> = vec![];
+    // Load the config file, if any, and use it to fill in options that weren't given
+    // on the command line.
+    let config = Config::discover(options.config.as_deref())?.unwrap_or_default();
+    options.merge_config(&config);
 
-        for (idx, name) in unique.column_names.iter().enumerate() {
-            let col = model.get_col(name).unwrap();
+    let mut inputs = options.inputs(&config)?;
 
-            // Check if the current column holds a foreign key by attempting to find the
-            // @relation associated with it. The Column struct returned by the
-            // `get_related_column()` method will return the column with the @relation
-            // annotation, which isn't an actual database column. That column's type points
-            // at the associated table, which allows us to construct the right JOIN clause.
-            if let Some(related_column) = col.get_related_column(&model) {
-                check_joins.push(
-                    format!(
-                        "JOIN {table}_id_map ON {key} = {foreign_key}",
-                        table = related_column.ty.name,
-                        key = format!("\"{}\".\"{}\"", model.name, col.name),
-                        foreign_key = format!("{}_id_map.new_id", related_column.ty.name)
-                    )
-                );
+    // Load and parse the Prisma schema, or introspect it from the first input if there
+    // isn't one, then apply per-model settings from the config file and the command
+    // line, in that order.
+    let mut schema = if !options.schema_path.is_empty() {
+        read_schema(&options.schema_path)?
+    } else {
+        println!("No schema path given, introspecting the schema from {}", inputs[0].label);
 
-                check_wheres.push(
-                    format!(
-                        "{table}_id_map.old_id = ?{idx}",
-                        table = related_column.ty.name,
-                        idx = idx + 1
-                    )
-                );
-            } else {
-                // Regular columns only need to have their values compared.
-                check_wheres.push(
-                    format!(
-                        "{col} = ?{idx}",
-                        col = name,
-                        idx = idx + 1
-                    )
-                )
+        let conn = Connection::open(&inputs[0].path).map_err(|e| e.to_string())?;
+        let introspection = introspect::introspect(&conn)?;
+
+        for warning in introspection.warnings.iter() {
+            println!("{}", warning);
+        }
+
+        introspection.schema
+    };
+
+    // Default to the database the schema's datasource points at, if any.
+    let output_path = match &options.output_path {
+        Some(output_path) => output_path.clone(),
+
+        None => match datasource_path(&schema, &options.schema_path) {
+            Some(datasource_path) => {
+                println!("Writing to {}, the schema's datasource url", datasource_path);
+                datasource_path
             }
+
+            None => DEFAULT_OUTPUT_PATH.to_string()
         }
+    };
 
-        check_sql_template = Some(
-            format!(
-            r#"
-                SELECT quote({primary_key}) FROM "{table}"
-                {check_joins}
-                WHERE {where_stmts}
-                LIMIT 1;
-            "#,
-            primary_key = primary_key.name,
-            table = model.name,
-            check_joins = check_joins.join("\n"),
-            where_stmts = check_wheres.join(" AND ")
-        ));
+    // Migrations live next to the schema file by default.
+    let migrations_path = options.migrations_path
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| schema_dir(&options.schema_path).map(|schema_dir| schema_dir.join("migrations")))
+        .unwrap_or_else(|| PathBuf::from("migrations"));
+
+    config.apply_to(&mut schema)?;
+    apply_conflict_policies(&mut schema, &options.conflict_policy)?;
+    apply_ignore_policies(&mut schema, &options.ignore_policy)?;
+    apply_orphan_policies(&mut schema, &options.orphans)?;
+
+    // Passthrough policies from the config file come first so the command line takes
+    // precedence.
+    let passthrough_specs: Vec<String> = config.passthrough
+        .iter()
+        .map(|(table_name, policy)| format!("{}={}", table_name, policy))
+        .chain(options.passthrough.iter().cloned())
+        .collect();
+
+    let passthrough = apply_passthrough(&mut schema, &passthrough_specs)?;
+
+    for model_name in options.dedupe_by_hash.iter() {
+        schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in --dedupe-by-hash", model_name))?
+            .set_dedupe_by_hash(true)?;
     }
 
-    let mut total_rows: u64 = 0;
+    apply_filters(&mut schema, &options.filter)?;
+    apply_renames(&mut schema, &options.rename)?;
 
-    // As described earlier, the "primary" connection is the one that contains the
-    // largest number of rows for the given model. Every other connection is called
-    // a "secondary."
-    let mut primary = &connections[0];
-    let mut primary_count: u64 = 0;
+    for model in schema.models.values() {
+        model.check_dedupe_by_hash()?;
+        model.check_conflict_policy()?;
+    }
 
-    for conn in connections {
-        let mut count_stmt = conn.prepare(count_query.as_str()).unwrap();
-        let mut count_rows = count_stmt.query(()).unwrap();
-        let count: u64 = count_rows.next().unwrap().unwrap().get(0).unwrap();
-        total_rows += count;
+    for (model_name, parent_name) in schema.select(&options.only, &options.exclude)? {
+        println!("Skipping {} because it requires {}, which is not being merged", model_name, parent_name);
+    }
 
-        if count > primary_count {
-            primary_count = count;
-            primary = conn;
-        }
+    for (model_name, parent_name) in schema.cascade_filters() {
+        println!("Filtering {} to the rows whose {} wasn't filtered out", model_name, parent_name);
     }
 
-    // Insert the primary connection first so it's processed first. Copying from the
-    // primary connection first enables us to skip checking for existing records for
-    // the connection with the largest number of rows, which can significantly increase
-    // performance.
-    let mut sorted_connections: Vec<&Connection> = vec![primary];
+    for (model_name, field_name, parent_name) in schema.orphaned_by_filters() {
+        let policy = schema.models[&model_name].get_col(&field_name).unwrap().orphan_policy();
 
-    // Append all secondary connections.
-    for conn in connections {
-        if !core::ptr::eq(conn, primary) {
-            sorted_connections.push(conn);
-        }
+        println!(
+            "Rows of {} whose {} was filtered out of {} are orphans, which are handled by the {} orphan policy",
+            model_name, field_name, parent_name, policy
+        );
     }
 
-    let mut progress = if show_progress {
-        ProgressIndicator::new(model.name.as_str(), total_rows)
+    // If requested, apply pending migrations to temporary copies of the inputs and
+    // merge those instead. The copies are deleted when `migrated` goes out of scope.
+    let migrated = if options.migrate {
+        Some(MigratedInputs::create(&inputs, &read_migrations(&migrations_path)?)?)
     } else {
-        ProgressIndicator::null()
+        None
     };
 
-    // Iterate over each connection and copy all rows to the merged database.
-    for conn in sorted_connections {
-        let is_primary = core::ptr::eq(conn, primary);
-        let is_secondary = !is_primary;
+    let input_paths: Vec<&String> = match &migrated {
+        Some(migrated) => migrated.paths.iter().collect(),
+        None => inputs.iter().map(|input| &input.path).collect()
+    };
 
-        // Execute a query for iterating over all existing rows in the current input database.
-        let mut stmt = conn.prepare(select_query.as_str()).unwrap();
-        let mut rows = stmt.query(()).unwrap();
+    let mut connections = open_inputs(&input_paths);
 
-        loop {
-            match rows.next() {
-                // Successfully fetched the next row
-                Ok(Some(row)) => {
-                    let old_pk: String = row.get(0).unwrap();
-                    let mut existing_pk: Option<String> = None;
+    // Make sure every input matches the schema before writing anything.
+    let preflight_inputs: Vec<(&str, &Connection)> = inputs
+        .iter()
+        .map(|input| input.label.as_str())
+        .zip(connections.iter())
+        .collect();
 
-                    // If we're copying rows from a secondary database, check
-                    // if the current row already exists using the existing
-                    // unique index, if any.
-                    if is_secondary {
-                        if let Some(check_sql_orig) = &check_sql_template {
-                            let mut check_sql = check_sql_orig.clone();
+    for warning in preflight::check_inputs(&schema, &preflight_inputs)? {
+        println!("{}", warning);
+    }
 
-                            // Rather than use rusqlite's mechanism for binding
-                            // values to a query string, we perform a dumb string
-                            // replacement here. Rusqlite expects placeholders of
-                            // the form ?<n>, where <n> is an unsigned integer.
-                            // Since all the columns we're copying have already
-                            // been quoted by SQLite, we want to avoid any extra
-                            // escaping or munging that rusqlite might do, so we
-                            // simply swap in the quoted value and call it a day.
-                            for (idx, col) in model.unique.as_ref().unwrap().column_names.iter().enumerate() {
-                                let value = row.get::<_, String>(col.as_str()).unwrap();
-                                check_sql = check_sql.replace(&format!("?{}", idx + 1), &value);
-                            }
+    // Problems with the rows in an input would otherwise only show up as integrity
+    // problems in the merged database, with no way to tell which input they came from.
+    let mut broken_inputs: Vec<bool> = vec![];
 
-                            match merged.query_row(check_sql.as_str(), (), |row| {
-                                // Found a result, so record the existing primary key for use later.
-                                existing_pk = Some(row.get::<_, String>(0).unwrap());
-                                Ok(())
-                            }) {
-                                Ok(_) => (),
-                                Err(_) => ()
-                            }
-                        }
-                    }
+    for (input, conn) in preflight_inputs.iter() {
+        let problems = preflight::check_input_integrity(&schema, conn, input)?;
 
-                    // An existing row was found, so only insert a map table entry.
-                    if let Some(existing_id) = existing_pk {
-                        let id_map_insert = format!(
-                            "INSERT INTO \"{table}\" (old_id, new_id) VALUES ('{old_pk}', {existing_id})",
-                            table = model.map_table.name,
-                            old_pk = old_pk,
-                            existing_id = existing_id
-                        );
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
 
-                        // Even though this is an INSERT into the ID map table, it
-                        // represents an actual row. We're skipping because it already
-                        // exists, so we call insert() instead of insert_supporting()
-                        // to count it towards merge progress.
-                        progress.inc(inserter.insert(id_map_insert));
+        broken_inputs.push(!problems.is_empty());
+    }
 
-                        continue;
-                    }
+    if options.exclude_broken_inputs && broken_inputs.contains(&true) {
+        for (input, is_broken) in inputs.iter().zip(broken_inputs.iter()) {
+            if *is_broken {
+                println!("Excluding {} because of the problems above", input.label);
+            }
+        }
 
-                    // In the case of the primary, we can use the old primary key. In
-                    // the case of a secondary, we mint a new primary key (mostly to
-                    // avoid confusion when debugging lol).
-                    let new_pk = if is_primary {
-                        old_pk.clone()
-                    } else {
-                        Uuid::new_v4().to_string()
-                    };
+        let mut broken = broken_inputs.iter();
+        inputs.retain(|_| !broken.next().unwrap());
 
-                    // Just as we did with the check_sql_template above, the INSERT
-                    // statement must not only copy over values from the original input
-                    // row, but also translate foreign keys via mapping tables. To
-                    // achieve this, a JOIN statement is included in the INSERT statement
-                    // for each foreign key.
-                    let mut select_values: Vec<String> = vec![format!("'{}'", new_pk)];
-                    let mut select_columns: Vec<&str> = vec![primary_key.name.as_str()];
-                    let mut join_statements: Vec<String> = vec![];
-                    let mut field_index = 2;
+        let mut broken = broken_inputs.iter();
+        connections.retain(|_| !broken.next().unwrap());
 
-                    for column in model.columns.iter() {
-                        if let Some(related_column) = column.get_related_column(&model) {
-                            let old_id: String = row.get(field_index).unwrap();
-                            field_index += 1;
+        if inputs.is_empty() {
+            return Err("Every input has integrity problems, so there's nothing left to merge.".to_string());
+        }
+    }
 
-                            select_values.push(format!(
-                                "{}_id_map.new_id",
-                                related_column.ty.name
-                            ));
+    // In extract mode, only the rows related to the seed rows are merged.
+    if !options.seed.is_empty() {
+        let seeds = options.seed
+            .iter()
+            .map(|spec| spec.parse::<Seed>())
+            .collect::<Result<Vec<Seed>, String>>()?;
 
-                            select_columns.push(column.name.as_str());
-                            join_statements.push(
-                                format!(
-                                    "LEFT JOIN {table}_id_map ON {table}_id_map.old_id = {old_id}",
-                                    table = related_column.ty.name,
-                                    old_id = old_id
-                                )
-                            )
-                        } else if column.is_regular(&schema) {
-                            let value: String = row.get(field_index).unwrap();
-                            field_index += 1;
-                            select_values.push(value);
-                            select_columns.push(column.name.as_str());
-                        }
-                    }
+        for (input, conn) in inputs.iter().zip(connections.iter()) {
+            let count = extract::compute_closure(&schema, conn, &seeds)?;
+            println!("Extracting {} rows from {}", count, input.label);
+        }
 
-                    // Construct the actual INSERT statement.
-                    let insert_sql = format!(
-                        r#"
-                            INSERT INTO "{table}" ({column_names})
-                            SELECT {select_values}
-                            FROM (SELECT 1) AS dummy
-                            {join_statements}
-                            LIMIT 1
-                        "#,
-                        table = model.name,
-                        column_names = select_columns.join(", "),
-                        select_values = select_values.join(", "),
-                        join_statements = join_statements.join("\n")
-                    );
+        extract::apply_filters(&mut schema)?;
+    }
 
-                    progress.inc(inserter.insert(insert_sql));
+    // Determine where the output database's schema comes from.
+    let output_ddl = match options.output_schema.as_deref().unwrap_or("input").parse::<OutputSchema>()? {
+        OutputSchema::Input => None,
+        OutputSchema::Prisma => Some(ddl::prisma_ddl(&schema)),
+        OutputSchema::Migrations => Some(ddl::migrations_ddl(&migrations_path)?)
+    };
 
-                    // Construct the INSERT statement for the map table.
-                    let id_map_insert = format!(
-                        "INSERT INTO \"{table}\" (old_id, new_id) VALUES ('{old_id}', '{new_id}')",
-                        table = model.map_table.name,
-                        old_id = old_pk,
-                        new_id = new_pk
-                    );
+    // Open output database.
+    let merged = Connection::open(&output_path).unwrap();
+
+    let merge_options = MergeOptions {
+        min_inserts: options.min_inserts.unwrap_or(DEFAULT_MIN_INSERTS),
+        show_progress: true,
+        record_conflicts: options.record_conflicts,
+        output_ddl,
+        input_labels: inputs.iter().map(|input| input.label.clone()).collect(),
+        passthrough,
+        rejects: options.rejects.clone().map(RejectSink::File).unwrap_or_default(),
+        max_errors: options.max_errors
+    };
 
-                    progress.inc(inserter.insert_supporting(id_map_insert));
-                }
+    let mut report = MergeReport::new(&output_path, &inputs);
+    report.models = prismerge(&schema, &connections, &merged, &merge_options)?;
 
-                // Occurs when there are no more rows in the result set.
-                Ok(None) => break,
+    // Make sure there are no foreign key integrity problems. If there are,
+    // print out warnings so the user knows what's up, along with the first few
+    // offending rows. `prismerge verify` lists all of them.
+    for current_model in schema.models.values() {
+        if let Err(violations) = current_model.verify_integrity(&merged) {
+            println!("Table {} has {} foreign key integrity problems", current_model.name, violations.len());
+
+            for violation in violations.iter().take(MAX_LISTED_VIOLATIONS) {
+                println!("  {}", violation);
+            }
 
-                // Some SQLite error occurred.
-                Err(_) => continue
+            if let Some(model_report) = report.get_model_mut(&current_model.name) {
+                model_report.integrity_violations = violations.len();
             }
         }
+    }
 
-        // Insert any lingering records.
-        progress.inc(inserter.flush());
+    // Clean up after ourselves by dropping all the map tables.
+    if !options.keep_id_maps {
+        for current_model in schema.models.values() {
+            current_model.map_table.drop_from(&merged);
+        }
     }
 
-    progress.inc(inserter.flush());
+    // Reclaim space from deleted tables, etc.
+    vacuum(&merged);
 
-    // Create several indices on the mapping table. We do this after we're entirely
-    // finished inserting because it's much faster to do it at the end rather than
-    // refresh the index on each individual INSERT.
-    model.map_table.create_indices(&merged);
+    // Report how long the whole merge process took.
+    let elapsed = start_time.elapsed().unwrap_or_default();
+    report.finish(&elapsed);
+    report.print_summary();
 
-    progress.finish();
-}
+    if let Some(report_path) = &options.report {
+        report.write_to(report_path)?;
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-    use prismerge::data::{Column, ColumnType, Model, Relation, Schema, Unique};
-    use lazy_static::lazy_static;
-    use rusqlite::Connection;
-    use tap::prelude::*;
-    use uuid::Uuid;
+    println!("Finished in {}", format_duration(&elapsed));
 
-    lazy_static! {
-        static ref SCHEMA: Schema = Schema::new().tap_mut(|schema| {
-            schema.models.insert(
-                "Owner".to_string(), Model::new(
-                    "Owner".to_string(),
-                    vec![
-                        Column {
-                            name: "id".to_string(),
-                            ty: ColumnType {
-                                name: "String".to_string(),
-                                collection: false,
-                                nullable: false
-                            },
-                            relation: None,
-                            unique: false,
-                            primary_key: true
-                        },
+    Ok(())
+}
 
-                        Column {
-                            name: "name".to_string(),
-                            ty: ColumnType {
-                                name: "String".to_string(),
-                                collection: false,
-                                nullable: false
-                            },
-                            relation: None,
-                            unique: false,
-                            primary_key: false
-                        }
-                    ],
-                    Some(
-                        Unique {
-                            column_names: vec!["name".to_string()]
-                        }
-                    )
-                )
-            );
+// Open all input databases, in the order they were given.
+fn open_inputs(input_paths: &[&String]) -> Vec<Connection> {
+    input_paths
+        .iter()
+        .map(|path| Connection::open(path).unwrap())
+        .collect()
+}
 
-            schema.models.insert(
-                "TodoList".to_string(), Model::new(
-                    "TodoList".to_string(),
-                    vec![
-                        Column {
-                            name: "id".to_string(),
-                            ty: ColumnType {
-                                name: "String".to_string(),
-                                collection: false,
-                                nullable: false
-                            },
-                            relation: None,
-                            unique: false,
-                            primary_key: true
-                        },
+fn prismerge(schema: &Schema, connections: &[Connection], merged: &Connection, options: &MergeOptions) -> Result<Vec<ModelReport>, String> {
+    // Get a list of Model objects, sorted topologically so parent records are
+    // created before children.
+    let order = schema.sorted();
 
-                        Column {
-                            name: "name".to_string(),
-                            ty: ColumnType {
-                                name: "String".to_string(),
-                                collection: false,
-                                nullable: false
-                            },
-                            relation: None,
-                            unique: false,
-                            primary_key: false
-                        },
+    // Turn off a lot of important stuff so inserting is fast. The rollback journal is
+    // kept in memory rather than turned off because rows that fail to insert are rolled
+    // back individually.
+    merged.execute_batch(r#"
+        PRAGMA synchronous = OFF;
+        PRAGMA journal_mode = MEMORY;
+        PRAGMA temp_store = MEMORY;
+        PRAGMA cache_size = -16000;
+        PRAGMA foreign_keys = OFF;
+    "#).unwrap();
 
-                        Column {
-                            name: "ownerId".to_string(),
-                            ty: ColumnType {
-                                name: "String".to_string(),
-                                collection: false,
-                                nullable: false,
-                            },
-                            relation: None,
-                            unique: false,
-                            primary_key: false
-                        },
+    // Set up the merged database's tables and indices. Unless told otherwise, the
+    // schema is copied from the inputs. Inputs may have been created by different
+    // versions of the schema, so it's copied from the input that matches it best.
+    let output_ddl = match &options.output_ddl {
+        Some(output_ddl) => output_ddl.clone(),
+        None => ddl::input_ddl(&connections[schema_source(schema, connections)], schema).unwrap()
+    };
 
-                        Column {
-                            name: "owner".to_string(),
-                            ty: ColumnType {
-                                name: "Owner".to_string(),
-                                collection: false,
-                                nullable: false
-                            },
-                            relation: Some(
-                                Relation {
-                                    fields: vec!["ownerId".to_string()],
-                                    references: vec!["id".to_string()]
-                                }
-                            ),
-                            unique: false,
-                            primary_key: false
-                        }
-                    ],
-                    Some(
-                        Unique {
-                            column_names: vec!["name".to_string(), "ownerId".to_string()]
-                        }
+    for stmt in output_ddl {
+        merged.execute_batch(stmt.as_str()).unwrap();
+    }
+
+    let unmodeled_tables = schema.unmodeled_tables(merged).unwrap();
+
+    if options.record_conflicts {
+        ConflictTable::default().create_into(merged);
+    }
+
+    // Duplicate checks run against the merged database, so that's where normalizers'
+    // SQL functions are needed.
+    normalize::register_functions(merged).unwrap();
+
+    let mut rejects = RejectLog::new(options.rejects.clone(), options.max_errors);
+
+    // Merge each model.
+    let mut model_reports: Vec<ModelReport> = vec![];
+
+    for current_model in &order {
+        // Skipped models still get an (empty) map table so their children's foreign key
+        // JOINs keep working.
+        if current_model.skip {
+            current_model.map_table.create_into(merged);
+            continue;
+        }
+
+        // Models that can't be merged, eg. because they're ignored, are copied as-is.
+        let model_report = match current_model.copy_mode() {
+            Some(mode) if mode.policy == PassthroughPolicy::Skip => {
+                current_model.map_table.create_into(merged);
+                continue;
+            }
+
+            Some(mode) => copy_model(current_model, mode, schema, connections, merged, &mut rejects, options)?,
+            None => {
+                let report = merge_model(current_model, schema, connections, merged, &mut rejects, options);
+
+                // Drop the normalized key index even if merging failed, since it may use
+                // functions that are only registered while merging.
+                merged
+                    .execute(format!("DROP INDEX IF EXISTS \"{}\"", normalized_key_index(current_model)).as_str(), ())
+                    .map_err(|e| e.to_string())?;
+
+                report?
+            }
+        };
+
+        model_reports.push(model_report);
+    }
+
+    // Copy the tables that aren't in the schema, if asked to.
+    for table_name in unmodeled_tables {
+        let mode = options.passthrough.get(&table_name).copied().unwrap_or_default();
+
+        if mode.policy != PassthroughPolicy::Skip {
+            let table_model = table_model(&table_name, merged);
+            model_reports.push(copy_model(&table_model, mode, schema, connections, merged, &mut rejects, options)?);
+        }
+    }
+
+    // Merge the record of applied migrations, if any.
+    migrations::merge_history(connections, merged).unwrap();
+
+    // Turn important things back on to ensure integrity, etc.
+    merged.execute_batch(r#"
+        PRAGMA synchronous = ON;
+        PRAGMA journal_mode = DELETE;
+        PRAGMA foreign_keys = ON;
+    "#).unwrap();
+
+    if rejects.count > 0 && options.show_progress {
+        println!("Rejected {} rows, see {}", rejects.count, rejects.sink.describe());
+    }
+
+    Ok(model_reports)
+}
+
+// Applies conflict policies given on the command line to the models and columns in the
+// schema. Each policy is of the form MODEL=POLICY or MODEL.COLUMN=AGGREGATE.
+fn apply_conflict_policies(schema: &mut Schema, specs: &[String]) -> Result<(), String> {
+    for spec in specs {
+        let (target, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid conflict policy '{}', expected MODEL=POLICY or MODEL.COLUMN=AGGREGATE", spec))?;
+
+        let (model_name, column_name) = match target.split_once('.') {
+            Some((model_name, column_name)) => (model_name, Some(column_name)),
+            None => (target, None)
+        };
+
+        let model = schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in conflict policy '{}'", model_name, spec))?;
+
+        match column_name {
+            Some(column_name) => {
+                let column = model
+                    .get_col_mut(column_name)
+                    .ok_or_else(|| format!("Unknown column '{}.{}' in conflict policy '{}'", model_name, column_name, spec))?;
+
+                column.aggregate = Some(value.parse::<Aggregate>()?);
+            }
+
+            None => model.set_conflict_policy(value.parse::<ConflictPolicy>()?)?
+        }
+    }
+
+    Ok(())
+}
+
+// Applies ignore policies given on the command line. Each policy is of the form
+// MODEL=POLICY.
+fn apply_ignore_policies(schema: &mut Schema, specs: &[String]) -> Result<(), String> {
+    for spec in specs {
+        let (model_name, policy) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid ignore policy '{}', expected MODEL=POLICY", spec))?;
+
+        schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in ignore policy '{}'", model_name, spec))?
+            .ignore_policy = policy.parse::<IgnorePolicy>()?;
+    }
+
+    Ok(())
+}
+
+// Applies orphan policies given on the command line. Each policy is of the form
+// MODEL.RELATION=POLICY, where RELATION is the name of a relation field.
+fn apply_orphan_policies(schema: &mut Schema, specs: &[String]) -> Result<(), String> {
+    for spec in specs {
+        let ((model_name, column_name), policy) = spec
+            .split_once('=')
+            .and_then(|(target, policy)| Some((target.split_once('.')?, policy)))
+            .ok_or_else(|| format!("Invalid orphan policy '{}', expected MODEL.RELATION=POLICY", spec))?;
+
+        schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in orphan policy '{}'", model_name, spec))?
+            .get_col_mut(column_name)
+            .ok_or_else(|| format!("Unknown relation '{}.{}' in orphan policy '{}'", model_name, column_name, spec))?
+            .set_orphan_policy(policy.parse::<OrphanPolicy>()?)?;
+    }
+
+    Ok(())
+}
+
+// Applies passthrough policies, each of the form TABLE=POLICY, to the models without a
+// primary key they name. Returns the policies for the tables that aren't in the schema.
+fn apply_passthrough(schema: &mut Schema, specs: &[String]) -> Result<HashMap<String, Passthrough>, String> {
+    let mut tables: HashMap<String, Passthrough> = HashMap::new();
+
+    for spec in specs {
+        let (table_name, policy) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid passthrough policy '{}', expected TABLE=POLICY", spec))?;
+
+        let passthrough = policy.parse::<Passthrough>()?;
+
+        match schema.models.get_mut(table_name) {
+            Some(model) => model.set_passthrough(passthrough)?,
+            None => { tables.insert(table_name.to_string(), passthrough); }
+        }
+    }
+
+    Ok(tables)
+}
+
+// Applies column renames given on the command line. Each rename has the form
+// MODEL.COLUMN=OLD_NAME or MODEL.COLUMN=OLD_NAME@MIGRATION. Renames are tried in the
+// order they're given.
+fn apply_renames(schema: &mut Schema, specs: &[String]) -> Result<(), String> {
+    for spec in specs {
+        let ((model_name, column_name), old_name) = spec
+            .split_once('=')
+            .and_then(|(target, old_name)| Some((target.split_once('.')?, old_name)))
+            .ok_or_else(|| format!("Invalid rename '{}', expected MODEL.COLUMN=OLD_NAME[@MIGRATION]", spec))?;
+
+        schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in rename '{}'", model_name, spec))?
+            .add_rename(column_name, old_name)?;
+    }
+
+    Ok(())
+}
+
+// Applies row filters given on the command line to the models in the schema. Each filter
+// is of the form MODEL=PREDICATE.
+fn apply_filters(schema: &mut Schema, specs: &[String]) -> Result<(), String> {
+    for spec in specs {
+        let (model_name, predicate) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid filter '{}', expected MODEL=PREDICATE", spec))?;
+
+        let model = schema.models
+            .get_mut(model_name)
+            .ok_or_else(|| format!("Unknown model '{}' in filter '{}'", model_name, spec))?;
+
+        model.filter = Some(predicate.to_string());
+    }
+
+    Ok(())
+}
+
+// Runs the SQLite VACUUM command which reclaims space from deleted tables, indices, etc.
+fn vacuum(conn: &Connection) {
+    conn.execute("VACUUM;", ()).unwrap();
+}
+
+// Return the index of the connection whose tables contain the most columns defined in
+// the schema, i.e. the one created by the newest version of the schema. Ties go to the
+// earliest connection.
+fn schema_source(schema: &Schema, connections: &[Connection]) -> usize {
+    let mut best_idx = 0;
+    let mut best_count = 0;
+
+    for (conn_idx, conn) in connections.iter().enumerate() {
+        let count: usize = schema.models
+            .values()
+            .map(|model| {
+                let existing_columns = model.existing_columns(conn);
+                model.columns.iter().filter(|col| existing_columns.contains(&col.name)).count()
+            })
+            .sum();
+
+        if count > best_count {
+            best_idx = conn_idx;
+            best_count = count;
+        }
+    }
+
+    best_idx
+}
+
+// This is where most of the magic happens. This function merges the records for the
+// given Model, copying records from the databases in `connections` into the database
+// in `merged`. The min_inserts option specifies how many INSERTs to batch up before
+// inserting in bulk. Returns statistics about the rows that were read, inserted, etc.
+fn merge_model(model: &Model, schema: &Schema, connections: &[Connection], merged: &Connection, rejects: &mut RejectLog, options: &MergeOptions) -> Result<ModelReport, String> {
+    let start_time = Instant::now();
+    let mut report = ModelReport::new(&model.name, connections.len());
+
+    model.map_table.create_into(merged);
+
+    let mut inserter = InsertManager::new(merged, options.min_inserts).with_rejects(rejects, &model.name);
+    let conflict_table = ConflictTable::default();
+    let primary_key = model.primary_key().unwrap();
+    let mut cols_to_copy: Vec<&Column> = vec![];
+
+    // Enumerate columns that will be copied wholesale, i.e. without any translation.
+    // In other words, all columns that aren't foreign keys. Ignored columns are left
+    // out if the model's ignore policy says to skip them.
+    for column in model.columns.iter() {
+        if column.is_regular(schema) && model.copies(column) {
+            cols_to_copy.push(column);
+        }
+    }
+
+    // Rows must satisfy the model's filter, if any, in order to be merged.
+    let filter = model.filter
+        .as_ref()
+        .map(|predicate| format!(" AND ({})", predicate))
+        .unwrap_or_default();
+
+    let count_query = format!(
+        "SELECT COUNT({primary_key}) FROM \"{table}\" WHERE 1{filter}",
+        primary_key = primary_key.name,
+        table = model.name
+    );
+
+    // This is the query that will be used to iterate over all the rows in each of the
+    // input databases. We select two versions of the primary key, one quoted and one
+    // unquoted. It's important to select both because they are used in different contexts
+    // during the merge process.
+    //
+    // We also select quoted versions of all the other columns as well so they can be
+    // directly interpolated into INSERT statements without having to know what data type
+    // they are. It would be quite tedious to quote things or not depending on the type, so
+    // we let SQLite do the work for us.
+    //
+    // Inputs may have been created by older versions of the schema, so the query is built
+    // separately for each connection based on the columns that actually exist. Missing
+    // columns are read via their old names or replaced by their default values.
+    let select_query_for = |conn: &Connection| -> Result<String, String> {
+        let existing_columns = model.existing_columns(conn);
+        let applied_migrations = migrations::applied_migrations(conn)?;
+
+        Ok(format!(
+            "SELECT \"{primary_key}\" AS unquoted_pk, quote(\"{primary_key}\") AS \"{primary_key}\", {quoted_columns} FROM \"{table}\" WHERE 1{filter};",
+            quoted_columns = cols_to_copy
+                .iter()
+                .map(|col| format!("{} AS {}", col.quoted_from(&model.name, &existing_columns, applied_migrations.as_ref()), col.name))
+                .collect::<Vec<String>>()
+                .join(", "),
+            primary_key = primary_key.name,
+            table = model.name
+        ))
+    };
+
+    let mut check_sql_template: Option<String> = None;
+
+    // Columns that are compared between an incoming row and the existing row it matches
+    // in order to detect conflicts, i.e. all the regular columns that aren't part of the
+    // unique index or foreign keys. Ignored columns are only compared if the model's
+    // ignore policy is dedup.
+    let compare_cols: Vec<&Column> = cols_to_copy
+        .iter()
+        .filter(|col| {
+            col.get_related_column(model).is_none() &&
+                model.compares(col) &&
+                !model.unique.as_ref().is_some_and(|u| u.column_names.contains(&col.name))
+        })
+        .copied()
+        .collect();
+
+    // Models without a unique key can opt into detecting duplicates by hashing the
+    // contents of each row instead. Keep track of the hashes of the rows inserted so far,
+    // along with their quoted primary keys.
+    let dedupe_by_hash = model.dedupe_by_hash && model.unique.is_none();
+    let mut inserted_hashes: HashMap<Vec<u8>, String> = HashMap::new();
+
+    // Duplicates only require updating the existing row if there's a policy other than
+    // keep-first, or if at least one column needs to be aggregated. Rows with identical
+    // hashes are identical, so there's nothing to update.
+    let needs_update = !dedupe_by_hash && (
+        model.conflict_policy != ConflictPolicy::KeepFirst ||
+            compare_cols.iter().any(|col| col.aggregate.is_some())
+    );
+
+    // The keep-last policy respects input order, but the primary is always processed
+    // first. Keep track of the rows inserted from secondaries so we know which existing
+    // rows may be overwritten by a secondary that comes before the primary.
+    let mut secondary_inserted: HashSet<String> = HashSet::new();
+
+    // The rows above are registered as soon as they're queued up, but may still be
+    // rejected when their batch is inserted. Keep the new primary keys and hashes of
+    // queued rows, by old primary key, so rejected rows can be forgotten again.
+    let mut pending: HashMap<String, (String, Option<Vec<u8>>)> = HashMap::new();
+
+    // If the model has a unique index, we want to use it to query for existing records.
+    // We enumerate all of its columns here and build up a SELECT query. This query not
+    // only has to check existing "regular" columns (i.e. columns that are not foreign
+    // keys), but also foreign keys that will have been translated into new keys via one
+    // of the mapping tables. The resulting query includes a a JOIN clause for each of the
+    // foreign keys, as well as a WHERE clause containing normal comparisons for the
+    // regular columns and comparisons to the mapped old ID for all foreign keys.
+    if let Some(unique) = &model.unique {
+        let mut check_wheres: Vec<String> = vec![];
+        let mut check_joins: Vec<String> = vec![];
+        let mut index_exprs: Vec<String> = vec![];
+
+        for (idx, name) in unique.column_names.iter().enumerate() {
+            let col = model.get_col(name).unwrap();
+
+            // Check if the current column holds a foreign key by attempting to find the
+            // @relation associated with it. The Column struct returned by the
+            // `get_related_column()` method will return the column with the @relation
+            // annotation, which isn't an actual database column. That column's type points
+            // at the associated table, which allows us to construct the right JOIN clause.
+            if let Some(related_column) = col.get_related_column(model) {
+                check_joins.push(
+                    format!(
+                        "JOIN {table}_id_map ON {key} = {foreign_key}",
+                        table = related_column.ty.name,
+                        key = format_args!("\"{}\".\"{}\"", model.name, col.name),
+                        foreign_key = format_args!("{}_id_map.new_id", related_column.ty.name)
+                    )
+                );
+
+                check_wheres.push(
+                    format!(
+                        "{table}_id_map.old_id = ?{idx}",
+                        table = related_column.ty.name,
+                        idx = idx + 1
+                    )
+                );
+
+                index_exprs.push(format!("\"{}\"", col.name));
+            } else {
+                // Regular columns only need to have their values compared, after converting
+                // both sides to a canonical form for the column's type and applying any
+                // normalizers.
+                index_exprs.push(normalize_typed(&col.ty.name, &col.normalizers, &format!("\"{}\"", name)));
+
+                check_wheres.push(
+                    format!(
+                        "{col} = {value}",
+                        col = normalize_typed(&col.ty.name, &col.normalizers, &format!("\"{}\".\"{}\"", model.name, name)),
+                        value = normalize_typed(&col.ty.name, &col.normalizers, &format!("?{}", idx + 1))
                     )
                 )
+            }
+        }
+
+        // The unique index can't be used to look up normalized values, so index them
+        // instead. SQLite uses an index on expressions whenever a query compares the
+        // same expressions, which index columns can't be qualified with a table name in.
+        // The index is dropped once the model has been merged.
+        if index_exprs.iter().zip(unique.column_names.iter()).any(|(expr, name)| *expr != format!("\"{}\"", name)) {
+            let create_index_sql = format!(
+                "CREATE INDEX \"{index}\" ON \"{table}\" ({index_exprs})",
+                index = normalized_key_index(model),
+                table = model.name,
+                index_exprs = index_exprs.join(", ")
             );
-        });
+
+            merged
+                .execute(create_index_sql.as_str(), ())
+                .map_err(|e| format!("Unable to index the normalized unique key of {}: {}", model.name, e))?;
+        }
+
+        // In addition to the primary key, select the existing row's unique key and all the
+        // columns we need to compare in order to detect conflicts.
+        let existing_columns = unique.column_names
+            .iter()
+            .chain(compare_cols.iter().map(|col| &col.name))
+            .map(|name| format!("quote(\"{}\".\"{}\")", model.name, name))
+            .collect::<Vec<String>>();
+
+        check_sql_template = Some(
+            format!(
+            r#"
+                SELECT quote("{table}"."{primary_key}"), "{table}"."{primary_key}", {existing_columns} FROM "{table}"
+                {check_joins}
+                WHERE {where_stmts}
+                LIMIT 1;
+            "#,
+            primary_key = primary_key.name,
+            table = model.name,
+            existing_columns = existing_columns.join(", "),
+            check_joins = check_joins.join("\n"),
+            where_stmts = check_wheres.join(" AND ")
+        ));
     }
 
-    fn apply_schema(conn: &Connection) {
-        Owner::setup(conn);
-        TodoList::setup(conn);
-    }
+    let mut total_rows: u64 = 0;
+    let mut counts: Vec<u64> = vec![];
+
+    // As described earlier, the "primary" connection is the one that contains the
+    // largest number of rows for the given model. Every other connection is called
+    // a "secondary."
+    let mut primary = &connections[0];
+    let mut primary_idx: usize = 0;
+    let mut primary_count: u64 = 0;
+
+    for (conn_idx, conn) in connections.iter().enumerate() {
+        let mut count_stmt = conn.prepare(count_query.as_str()).unwrap();
+        let mut count_rows = count_stmt.query(()).unwrap();
+        let count: u64 = count_rows.next().unwrap().unwrap().get(0).unwrap();
+        total_rows += count;
+        counts.push(count);
+
+        if count > primary_count {
+            primary_count = count;
+            primary = conn;
+            primary_idx = conn_idx;
+        }
+    }
+
+    // Insert the primary connection first so it's processed first. Copying from the
+    // primary connection first enables us to skip checking for existing records for
+    // the connection with the largest number of rows, which can significantly increase
+    // performance. Each connection is paired with its index in `connections` so
+    // statistics can be attributed to the right input.
+    let mut sorted_connections: Vec<(usize, &Connection)> = vec![(primary_idx, primary)];
+
+    // Append all secondary connections.
+    for (conn_idx, conn) in connections.iter().enumerate() {
+        if !core::ptr::eq(conn, primary) {
+            sorted_connections.push((conn_idx, conn));
+        }
+    }
+
+    let mut progress = if options.show_progress {
+        ProgressIndicator::new(model.name.as_str(), total_rows)
+    } else {
+        ProgressIndicator::null()
+    };
+
+    // Iterate over each connection and copy all rows to the merged database.
+    for (conn_idx, conn) in sorted_connections {
+        let is_primary = core::ptr::eq(conn, primary);
+        let is_secondary = !is_primary;
+        let input_name = options.input_labels
+            .get(conn_idx)
+            .map(|label| label.as_str())
+            .unwrap_or_else(|| conn.path().unwrap_or_default())
+            .to_string();
+
+        inserter.set_input(&input_name);
+
+        // Execute a query for iterating over all existing rows in the current input database.
+        let select_query = select_query_for(conn)?;
+        let mut stmt = conn.prepare(select_query.as_str()).unwrap();
+        let mut rows = stmt.query(()).unwrap();
+
+        loop {
+            match rows.next() {
+                // Successfully fetched the next row
+                Ok(Some(row)) => {
+                    report.rows_read[conn_idx] += 1;
+
+                    // Later duplicates of rejected rows are inserted in their place
+                    // rather than mapped to rows that don't exist.
+                    for old_id in inserter.take_rejected_ids() {
+                        if let Some((new_pk, hash)) = pending.remove(&old_id) {
+                            secondary_inserted.remove(&new_pk);
+
+                            if let Some(hash) = hash {
+                                inserted_hashes.remove(&hash);
+                            }
+                        }
+                    }
+
+                    if inserter.is_empty() {
+                        pending.clear();
+                    }
+
+                    let old_pk: String = match row.get(0) {
+                        Ok(old_pk) => old_pk,
+
+                        Err(e) => {
+                            report.rows_failed += 1;
+                            inserter.reject(None, &select_query, &e.to_string())?;
+                            continue;
+                        }
+                    };
+                    let mut existing_pk: Option<String> = None;
+                    let mut merged_id: Option<String> = None;
+                    let mut conflicts: Vec<Conflict> = vec![];
+
+                    // If we're copying rows from a secondary database, check
+                    // if the current row already exists using the existing
+                    // unique index, if any.
+                    if is_secondary {
+                        if let Some(check_sql_orig) = &check_sql_template {
+                            let mut check_sql = check_sql_orig.clone();
+
+                            // Rather than use rusqlite's mechanism for binding
+                            // values to a query string, we perform a dumb string
+                            // replacement here. Rusqlite expects placeholders of
+                            // the form ?<n>, where <n> is an unsigned integer.
+                            // Since all the columns we're copying have already
+                            // been quoted by SQLite, we want to avoid any extra
+                            // escaping or munging that rusqlite might do, so we
+                            // simply swap in the quoted value and call it a day.
+                            let unique_names = &model.unique.as_ref().unwrap().column_names;
+
+                            for (idx, col) in unique_names.iter().enumerate() {
+                                let value = row.get::<_, String>(col.as_str()).unwrap();
+                                check_sql = check_sql.replace(&format!("?{}", idx + 1), &value);
+                            }
+
+                            let _ = merged.query_row(check_sql.as_str(), (), |existing_row| {
+                                // Found a result, so record the existing primary key for use later.
+                                existing_pk = Some(existing_row.get::<_, String>(0).unwrap());
+
+                                // Compare the remaining columns. Any differences are conflicts
+                                // that would otherwise silently be discarded.
+                                let existing_id: String = existing_row.get(1).unwrap();
+                                let key_offset = 2;
+                                let compare_offset = key_offset + unique_names.len();
+
+                                for (idx, col) in compare_cols.iter().enumerate() {
+                                    let merged_value: String = existing_row.get(compare_offset + idx).unwrap();
+                                    let input_value: String = row.get(col.name.as_str()).unwrap();
+
+                                    if merged_value != input_value {
+                                        let key = unique_names
+                                            .iter()
+                                            .enumerate()
+                                            .map(|(key_idx, name)| {
+                                                format!("{}={}", name, existing_row.get::<_, String>(key_offset + key_idx).unwrap())
+                                            })
+                                            .collect::<Vec<String>>()
+                                            .join(", ");
+
+                                        conflicts.push(Conflict {
+                                            key,
+                                            merged_id: existing_id.clone(),
+                                            column_name: col.name.clone(),
+                                            merged_value,
+                                            input_value
+                                        });
+                                    }
+                                }
+
+                                merged_id = Some(existing_id);
+                                Ok(())
+                            });
+                        }
+                    }
+
+                    let hash = if dedupe_by_hash {
+                        Some(content_hash(model, &cols_to_copy, row, merged)?)
+                    } else {
+                        None
+                    };
+
+                    if is_secondary {
+                        if let Some(existing_id) = hash.as_ref().and_then(|hash| inserted_hashes.get(hash)) {
+                            existing_pk = Some(existing_id.clone());
+                        }
+                    }
+
+                    // An existing row was found, so only insert a map table entry.
+                    // The existing row may have been queued up in the same batch and
+                    // rejected, in which case the lookup yields NULL and the entry is
+                    // rejected too.
+                    if let Some(existing_id) = existing_pk {
+                        let id_map_insert = format!(
+                            "INSERT INTO \"{table}\" (old_id, new_id) VALUES ('{old_pk}', (SELECT \"{primary_key}\" FROM \"{model}\" WHERE \"{primary_key}\" = {existing_id}))",
+                            table = model.map_table.name,
+                            model = model.name,
+                            primary_key = primary_key.name,
+                            old_pk = old_pk,
+                            existing_id = existing_id
+                        );
+
+                        // Even though this is an INSERT into the ID map table, it
+                        // represents an actual row. We're skipping because it already
+                        // exists, so we call insert_duplicate() instead of
+                        // insert_supporting() to count it towards merge progress.
+                        progress.inc(inserter.insert_duplicate(id_map_insert, Some(&old_pk))?);
+                        report.rows_deduplicated += 1;
+
+                        if !conflicts.is_empty() {
+                            report.rows_conflicted += 1;
+
+                            if options.record_conflicts {
+                                for conflict in conflicts.iter() {
+                                    let conflict_insert = conflict_table.insert_sql(&model.name, conflict, &input_name);
+                                    progress.inc(inserter.insert_supporting(conflict_insert)?);
+                                }
+                            }
+                        }
+
+                        // Resolve the duplicate according to the model's conflict policy by
+                        // updating the existing row.
+                        if needs_update {
+                            let merged_id = merged_id.unwrap();
+
+                            let policy = if model.conflict_policy == ConflictPolicy::KeepLast &&
+                                conn_idx < primary_idx &&
+                                !secondary_inserted.contains(&merged_id) {
+                                // The existing row came from the primary, which comes after
+                                // the current input, so the primary's values win.
+                                &ConflictPolicy::KeepFirst
+                            } else {
+                                &model.conflict_policy
+                            };
+
+                            let incoming: Vec<(&Column, String)> = compare_cols
+                                .iter()
+                                .map(|col| (*col, row.get::<_, String>(col.name.as_str()).unwrap()))
+                                .collect();
+
+                            if let Some(update) = conflicts::update_sql(&model.name, &primary_key.name, &existing_id, policy, &incoming) {
+                                progress.inc(inserter.insert_supporting(update)?);
+                            }
+                        }
+
+                        continue;
+                    }
+
+                    // In the case of the primary, we can use the old primary key. In
+                    // the case of a secondary, we mint a new primary key (mostly to
+                    // avoid confusion when debugging lol).
+                    let new_pk = if is_primary {
+                        old_pk.clone()
+                    } else {
+                        Uuid::new_v4().to_string()
+                    };
+
+                    // Just as we did with the check_sql_template above, the INSERT
+                    // statement must not only copy over values from the original input
+                    // row, but also translate foreign keys via mapping tables. To
+                    // achieve this, a JOIN statement is included in the INSERT statement
+                    // for each foreign key. Foreign keys that can't be translated are
+                    // handled according to their relation's orphan policy instead.
+                    let mut select_values: Vec<String> = vec![format!("'{}'", new_pk)];
+                    let mut select_columns: Vec<&str> = vec![primary_key.name.as_str()];
+                    let mut join_statements: Vec<String> = vec![];
+                    let mut field_index = 2;
+                    let mut orphaned = false;
+                    let mut dropped = false;
+
+                    for column in cols_to_copy.iter() {
+                        if let Some(related_column) = column.get_related_column(model) {
+                            let old_id: String = row.get(field_index).unwrap();
+                            field_index += 1;
+                            select_columns.push(column.name.as_str());
+
+                            if is_orphan(model, related_column, &old_id, merged) {
+                                orphaned = true;
+
+                                match related_column.orphan_policy() {
+                                    OrphanPolicy::Drop => dropped = true,
+                                    OrphanPolicy::Null => select_values.push("NULL".to_string()),
+                                    OrphanPolicy::Keep => select_values.push(old_id),
+                                    OrphanPolicy::Abort => return Err(orphan_error(model, related_column, &old_pk, &input_name))
+                                }
+
+                                continue;
+                            }
+
+                            select_values.push(format!(
+                                "{}_id_map.new_id",
+                                related_column.ty.name
+                            ));
+
+                            join_statements.push(
+                                format!(
+                                    "LEFT JOIN {table}_id_map ON {table}_id_map.old_id = {old_id}",
+                                    table = related_column.ty.name,
+                                    old_id = old_id
+                                )
+                            )
+                        } else {
+                            let value: String = row.get(field_index).unwrap();
+                            field_index += 1;
+                            select_values.push(value);
+                            select_columns.push(column.name.as_str());
+                        }
+                    }
+
+                    if orphaned {
+                        report.rows_orphaned += 1;
+                    }
+
+                    // Dropped rows don't get a map table entry, so their own children
+                    // are orphaned in turn.
+                    if dropped {
+                        report.rows_dropped += 1;
+                        progress.inc(1);
+                        continue;
+                    }
+
+                    let keep_last_secondary = is_secondary && model.conflict_policy == ConflictPolicy::KeepLast;
+
+                    if keep_last_secondary {
+                        secondary_inserted.insert(new_pk.clone());
+                    }
+
+                    if let Some(hash) = &hash {
+                        inserted_hashes.insert(hash.clone(), format!("'{}'", new_pk));
+                    }
+
+                    if keep_last_secondary || hash.is_some() {
+                        pending.insert(old_pk.clone(), (new_pk.clone(), hash));
+                    }
+
+                    // Construct the actual INSERT statement.
+                    let insert_sql = format!(
+                        r#"
+                            INSERT INTO "{table}" ({column_names})
+                            SELECT {select_values}
+                            FROM (SELECT 1) AS dummy
+                            {join_statements}
+                            LIMIT 1
+                        "#,
+                        table = model.name,
+                        column_names = select_columns.join(", "),
+                        select_values = select_values.join(", "),
+                        join_statements = join_statements.join("\n")
+                    );
+
+                    progress.inc(inserter.insert(insert_sql, Some(&old_pk))?);
+                    report.rows_inserted += 1;
+
+                    // Construct the INSERT statement for the map table.
+                    let id_map_insert = format!(
+                        "INSERT INTO \"{table}\" (old_id, new_id) VALUES ('{old_id}', '{new_id}')",
+                        table = model.map_table.name,
+                        old_id = old_pk,
+                        new_id = new_pk
+                    );
+
+                    progress.inc(inserter.insert_supporting(id_map_insert)?);
+                }
+
+                // Occurs when there are no more rows in the result set.
+                Ok(None) => break,
+
+                // Some SQLite error occurred. SQLite can't carry on reading after an
+                // error, so the rest of this input's rows are left out and counted as
+                // failed.
+                Err(e) => {
+                    let unread = counts[conn_idx].saturating_sub(report.rows_read[conn_idx]).max(1);
+                    report.rows_failed += unread;
+                    progress.inc(unread);
+
+                    let error = format!("{} (skipped the remaining {} rows of this input)", e, unread);
+                    inserter.reject(None, &select_query, &error)?;
+                    break;
+                }
+            }
+        }
+
+        // Insert any lingering records.
+        progress.inc(inserter.flush()?);
+    }
+
+    progress.inc(inserter.flush()?);
+
+    // Rows that failed to insert were counted as inserted or deduplicated when they
+    // were queued up.
+    report.rows_inserted = report.rows_inserted.saturating_sub(inserter.rejected);
+    report.rows_deduplicated = report.rows_deduplicated.saturating_sub(inserter.rejected_duplicates);
+    report.rows_failed += inserter.rejected + inserter.rejected_duplicates;
+
+    // Create several indices on the mapping table. We do this after we're entirely
+    // finished inserting because it's much faster to do it at the end rather than
+    // refresh the index on each individual INSERT.
+    model.map_table.create_indices(merged);
+
+    progress.finish();
+
+    report.elapsed_ms = start_time.elapsed().as_millis() as u64;
+    Ok(report)
+}
+
+// The name of the temporary index merge_model() creates over the normalized values of
+// a model's unique key.
+fn normalized_key_index(model: &Model) -> String {
+    format!("_prismerge_{}_normalized_key", model.name)
+}
+
+// Build a model for a table that isn't in the schema, with a regular column for each
+// of the table's columns.
+fn table_model(table_name: &str, merged: &Connection) -> Model {
+    let mut stmt = merged.prepare("SELECT name, type FROM pragma_table_info(?1)").unwrap();
+
+    let columns = stmt
+        .query_map([table_name], |row| {
+            Ok(Column {
+                name: row.get(0)?,
+                ty: ColumnType { name: row.get(1)?, ..Default::default() },
+                ..Default::default()
+            })
+        })
+        .unwrap()
+        .collect::<Result<Vec<Column>>>()
+        .unwrap();
+
+    Model::new(table_name.to_string(), columns, None)
+}
+
+// Whether the given quoted foreign key, read from a row of the given model, has no
+// entry in the parent's ID map. NULL keys don't point at anything, and keys pointing
+// at the model itself can't be checked until the whole model has been merged.
+fn is_orphan(model: &Model, related_column: &Column, old_id: &str, merged: &Connection) -> bool {
+    if old_id == "NULL" || related_column.ty.name == model.name {
+        return false;
+    }
+
+    let check_sql = format!(
+        "SELECT 1 FROM \"{table}_id_map\" WHERE old_id = {old_id} LIMIT 1",
+        table = related_column.ty.name,
+        old_id = old_id
+    );
+
+    merged.query_row(check_sql.as_str(), (), |_| Ok(())).is_err()
+}
+
+fn orphan_error(model: &Model, related_column: &Column, old_pk: &str, input_name: &str) -> String {
+    format!(
+        "Row {} of {} in {} points to a {} that doesn't exist via {}, and the relation's orphan policy is abort",
+        old_pk,
+        model.name,
+        input_name,
+        related_column.ty.name,
+        related_column.name
+    )
+}
+
+// Hash the contents of the given row, i.e. the values of all the columns that are
+// compared to detect conflicts, plus foreign keys. Foreign keys are translated via the
+// parents' ID maps first, so rows pointing at duplicate parents in different inputs
+// hash the same. Each value is prefixed with its length so the boundaries between
+// values are unambiguous.
+fn content_hash(model: &Model, cols_to_copy: &[&Column], row: &rusqlite::Row, merged: &Connection) -> Result<Vec<u8>, String> {
+    let mut hasher = Sha256::new();
+
+    for column in cols_to_copy.iter().filter(|column| model.compares(column)) {
+        let value: String = row.get(column.name.as_str()).map_err(|e| e.to_string())?;
+
+        // Foreign keys without a parent row translate to NULL, just like they do when
+        // the row is inserted.
+        let value = match column.get_related_column(model) {
+            Some(related_column) => merged
+                .query_row(
+                    format!("SELECT quote(new_id) FROM \"{}_id_map\" WHERE old_id = {} LIMIT 1", related_column.ty.name, value).as_str(),
+                    (),
+                    |parent_row| parent_row.get::<_, String>(0)
+                )
+                .or_else(|e| match e {
+                    rusqlite::Error::QueryReturnedNoRows => Ok("NULL".to_string()),
+                    e => Err(format!("Unable to translate {}.{} while hashing: {}", model.name, column.name, e))
+                })?,
+
+            None => value
+        };
+
+        hasher.update((value.len() as u64).to_le_bytes());
+        hasher.update(value.as_bytes());
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+// Copies the rows of a model that can't be merged, eg. because it's marked @@ignore or
+// has no primary key, as-is according to the given passthrough policy. Primary keys
+// are kept and foreign keys are translated via the parents' ID maps. Rows that collide
+// with a row that's already been copied, eg. because they have the same primary key,
+// are rejected. With dedup, rows identical to an existing row are dropped.
+//
+// Tables that aren't in the schema are copied the same way, via a model built from the
+// table's columns.
+fn copy_model(model: &Model, mode: Passthrough, schema: &Schema, connections: &[Connection], merged: &Connection, rejects: &mut RejectLog, options: &MergeOptions) -> Result<ModelReport, String> {
+    let start_time = Instant::now();
+    let mut report = ModelReport::new(&model.name, connections.len());
+    let in_schema = schema.models.contains_key(&model.name);
+
+    if in_schema {
+        model.map_table.create_into(merged);
+    }
+
+    let mut inserter = InsertManager::new(merged, options.min_inserts).with_rejects(rejects, &model.name);
+    let primary_key = model.primary_key();
+
+    let cols_to_copy: Vec<&Column> = model.columns
+        .iter()
+        .filter(|column| column.primary_key || column.is_regular(schema))
+        .collect();
+
+    let filter = model.filter
+        .as_ref()
+        .map(|predicate| format!(" AND ({})", predicate))
+        .unwrap_or_default();
+
+    let count_query = format!("SELECT COUNT(*) FROM \"{}\" WHERE 1{}", model.name, filter);
+
+    // Inputs that predate the table don't have any rows to copy.
+    let counts: Vec<u64> = connections
+        .iter()
+        .map(|conn| {
+            if model.existing_columns(conn).is_empty() {
+                return Ok(0);
+            }
+
+            conn.query_row(count_query.as_str(), (), |row| row.get::<_, u64>(0))
+                .map_err(|e| format!("Unable to count rows in {}: {}", model.name, e))
+        })
+        .collect::<Result<Vec<u64>, String>>()?;
+
+    // Like when merging, the primary connection is the one with the most rows. Ties go
+    // to the earliest connection.
+    let primary_idx = counts
+        .iter()
+        .enumerate()
+        .fold(0, |best_idx, (conn_idx, count)| if *count > counts[best_idx] { conn_idx } else { best_idx });
+
+    let conn_indices: Vec<usize> = match mode.policy {
+        PassthroughPolicy::Primary => vec![primary_idx],
+        _ => (0..connections.len()).collect()
+    };
+
+    let total_rows: u64 = conn_indices.iter().map(|conn_idx| counts[*conn_idx]).sum();
+
+    let mut progress = if options.show_progress {
+        ProgressIndicator::new(model.name.as_str(), total_rows)
+    } else {
+        ProgressIndicator::null()
+    };
+
+    for conn_idx in conn_indices {
+        let conn = &connections[conn_idx];
+        let existing_columns = model.existing_columns(conn);
+
+        if existing_columns.is_empty() {
+            continue;
+        }
+
+        let input_name = options.input_labels
+            .get(conn_idx)
+            .map(|label| label.as_str())
+            .unwrap_or_else(|| conn.path().unwrap_or_default())
+            .to_string();
+
+        inserter.set_input(&input_name);
+
+        let applied_migrations = migrations::applied_migrations(conn)?;
+
+        let select_query = format!(
+            "SELECT {quoted_columns} FROM \"{table}\" WHERE 1{filter};",
+            quoted_columns = cols_to_copy
+                .iter()
+                .map(|col| col.quoted_from(&model.name, &existing_columns, applied_migrations.as_ref()))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table = model.name
+        );
+
+        let mut stmt = conn.prepare(select_query.as_str()).unwrap();
+        let mut rows = stmt.query(()).unwrap();
+
+        loop {
+            match rows.next() {
+                Ok(Some(row)) => {
+                    report.rows_read[conn_idx] += 1;
+
+                    // The quoted primary key, if any.
+                    let old_pk: Option<String> = cols_to_copy
+                        .iter()
+                        .position(|col| col.primary_key)
+                        .map(|idx| row.get(idx).unwrap());
+
+                    let mut select_values: Vec<String> = vec![];
+                    let mut join_statements: Vec<String> = vec![];
+                    let mut orphaned = false;
+                    let mut dropped = false;
+
+                    for (idx, column) in cols_to_copy.iter().enumerate() {
+                        let value: String = row.get(idx).unwrap();
+
+                        if let Some(related_column) = column.get_related_column(model) {
+                            if is_orphan(model, related_column, &value, merged) {
+                                orphaned = true;
+
+                                match related_column.orphan_policy() {
+                                    OrphanPolicy::Drop => dropped = true,
+                                    OrphanPolicy::Null => select_values.push("NULL".to_string()),
+                                    OrphanPolicy::Keep => select_values.push(value),
+                                    OrphanPolicy::Abort => {
+                                        return Err(orphan_error(model, related_column, old_pk.as_deref().unwrap_or("?"), &input_name));
+                                    }
+                                }
+
+                                continue;
+                            }
+
+                            select_values.push(format!("{}_id_map.new_id", related_column.ty.name));
+                            join_statements.push(
+                                format!(
+                                    "LEFT JOIN {table}_id_map ON {table}_id_map.old_id = {old_id}",
+                                    table = related_column.ty.name,
+                                    old_id = value
+                                )
+                            );
+                        } else {
+                            select_values.push(value);
+                        }
+                    }
+
+                    if orphaned {
+                        report.rows_orphaned += 1;
+                    }
+
+                    if dropped {
+                        report.rows_dropped += 1;
+                        progress.inc(1);
+                        continue;
+                    }
+
+                    // Compare with IS rather than = so NULLs match each other. Primary keys
+                    // are left out, since duplicates from different inputs have different
+                    // keys.
+                    let comparisons = cols_to_copy
+                        .iter()
+                        .zip(select_values.iter())
+                        .filter(|(col, _)| !col.primary_key)
+                        .map(|(col, value)| format!("\"{}\".\"{}\" IS {}", model.name, col.name, value))
+                        .collect::<Vec<String>>()
+                        .join(" AND ");
+
+                    let dedup_clause = if mode.dedup {
+                        format!("WHERE NOT EXISTS (SELECT 1 FROM \"{}\" WHERE {})", model.name, comparisons)
+                    } else {
+                        String::new()
+                    };
+
+                    let insert_sql = format!(
+                        r#"
+                            INSERT INTO "{table}" ({column_names})
+                            SELECT {select_values}
+                            FROM (SELECT 1) AS dummy
+                            {join_statements}
+                            {dedup_clause}
+                            LIMIT 1
+                        "#,
+                        table = model.name,
+                        column_names = cols_to_copy
+                            .iter()
+                            .map(|col| format!("\"{}\"", col.name))
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                        select_values = select_values.join(", "),
+                        join_statements = join_statements.join("\n")
+                    );
+
+                    progress.inc(inserter.insert(insert_sql, old_pk.as_deref())?);
+
+                    // Primary keys are kept, so children can find their parents via an
+                    // identity mapping. Duplicates dropped by dedup are mapped to the row
+                    // they duplicate instead, which may be the row itself.
+                    if let (Some(primary_key), Some(old_pk), true) = (primary_key, &old_pk, in_schema) {
+                        let id_map_insert = if mode.dedup {
+                            format!(
+                                r#"
+                                    INSERT INTO "{table}" (old_id, new_id)
+                                    SELECT {old_pk}, "{model}"."{primary_key}"
+                                    FROM (SELECT 1) AS dummy
+                                    {join_statements}
+                                    JOIN "{model}" ON {comparisons}
+                                    LIMIT 1
+                                "#,
+                                table = model.map_table.name,
+                                model = model.name,
+                                primary_key = primary_key.name,
+                                old_pk = old_pk,
+                                join_statements = join_statements.join("\n")
+                            )
+                        } else {
+                            format!(
+                                "INSERT INTO \"{table}\" (old_id, new_id) SELECT {old_pk}, {old_pk} WHERE EXISTS (SELECT 1 FROM \"{model}\" WHERE \"{primary_key}\" = {old_pk})",
+                                table = model.map_table.name,
+                                model = model.name,
+                                primary_key = primary_key.name,
+                                old_pk = old_pk
+                            )
+                        };
+
+                        progress.inc(inserter.insert_supporting(id_map_insert)?);
+                    }
+                }
+
+                Ok(None) => break,
+
+                // SQLite can't carry on reading after an error, so the rest of this
+                // input's rows are counted as failed too.
+                Err(e) => {
+                    let unread = counts[conn_idx].saturating_sub(report.rows_read[conn_idx]).max(1);
+                    report.rows_failed += unread;
+                    progress.inc(unread);
+
+                    let error = format!("{} (skipped the remaining {} rows of this input)", e, unread);
+                    inserter.reject(None, &select_query, &error)?;
+                    break;
+                }
+            }
+        }
+
+        progress.inc(inserter.flush()?);
+    }
+
+    if in_schema {
+        model.map_table.create_indices(merged);
+    }
+
+    progress.finish();
+
+    // Duplicates are skipped by the insert itself rather than failing, so the number of
+    // rows inserted has to be counted after the fact. The rest were either rejected or,
+    // with dedup, duplicates. Rows that failed to read so far were never counted as
+    // read, so they don't count as attempted either.
+    let rows_attempted: u64 = report.rows_read.iter().sum::<u64>().saturating_sub(report.rows_dropped);
+
+    report.rows_inserted = merged
+        .query_row(format!("SELECT COUNT(*) FROM \"{}\"", model.name).as_str(), (), |row| row.get::<_, u64>(0))
+        .map_err(|e| format!("Unable to count rows in {}: {}", model.name, e))?;
+
+    report.rows_failed += inserter.rejected;
+    report.rows_deduplicated = rows_attempted.saturating_sub(report.rows_inserted + inserter.rejected);
+
+    report.elapsed_ms = start_time.elapsed().as_millis() as u64;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use clap::Parser;
+    use prismerge::config::Config;
+    use prismerge::conflicts::{Aggregate, ConflictPolicy};
+    use prismerge::data::{Column, ColumnType, IgnorePolicy, Model, Relation, Schema, Unique};
+    use prismerge::ddl;
+    use prismerge::introspect;
+    use prismerge::extract::{self, Seed};
+    use prismerge::normalize::Normalizer;
+    use prismerge::prisma_parser;
+    use lazy_static::lazy_static;
+    use rusqlite::Connection;
+    use tap::prelude::*;
+    use uuid::Uuid;
+
+    lazy_static! {
+        static ref SCHEMA: Schema = test_schema();
+    }
+
+    fn test_schema() -> Schema {
+        Schema::new().tap_mut(|schema| {
+            schema.models.insert(
+                "Owner".to_string(), Model::new(
+                    "Owner".to_string(),
+                    vec![
+                        Column {
+                            name: "id".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: true,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "name".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        }
+                    ],
+                    Some(
+                        Unique {
+                            column_names: vec!["name".to_string()]
+                        }
+                    )
+                )
+            );
+
+            schema.models.insert(
+                "TodoList".to_string(), Model::new(
+                    "TodoList".to_string(),
+                    vec![
+                        Column {
+                            name: "id".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: true,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "name".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "ownerId".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false,
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "owner".to_string(),
+                            ty: ColumnType {
+                                name: "Owner".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: Some(
+                                Relation {
+                                    fields: vec!["ownerId".to_string()],
+                                    references: vec!["id".to_string()],
+                                    ..Default::default()
+                                }
+                            ),
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        }
+                    ],
+                    Some(
+                        Unique {
+                            column_names: vec!["name".to_string(), "ownerId".to_string()]
+                        }
+                    )
+                )
+            );
+
+            schema.models.insert(
+                "Toy".to_string(), Model::new(
+                    "Toy".to_string(),
+                    vec![
+                        Column {
+                            name: "id".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: true,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "name".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: false
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "color".to_string(),
+                            ty: ColumnType {
+                                name: "String".to_string(),
+                                collection: false,
+                                nullable: true
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "quantity".to_string(),
+                            ty: ColumnType {
+                                name: "Int".to_string(),
+                                collection: false,
+                                nullable: true
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        },
+
+                        Column {
+                            name: "updatedAt".to_string(),
+                            ty: ColumnType {
+                                name: "DateTime".to_string(),
+                                collection: false,
+                                nullable: true
+                            },
+                            relation: None,
+                            unique: false,
+                            primary_key: false,
+                            ..Default::default()
+                        }
+                    ],
+                    Some(
+                        Unique {
+                            column_names: vec!["name".to_string()]
+                        }
+                    )
+                )
+            );
+        })
+    }
+
+    fn apply_schema(conn: &Connection) {
+        Owner::setup(conn);
+        TodoList::setup(conn);
+        Toy::setup(conn);
+    }
+
+    fn test_options() -> crate::MergeOptions {
+        crate::MergeOptions { min_inserts: 1, ..Default::default() }
+    }
+
+    fn create_connection() -> Connection {
+        Connection::open_in_memory().unwrap()
+    }
+
+    fn create_connections() -> (Connection, Connection, Connection) {
+        let first = create_connection();
+        let second = create_connection();
+        let merged = create_connection();
+
+        apply_schema(&first);
+        apply_schema(&second);
+
+        (first, second, merged)
+    }
+
+    #[derive(Debug)]
+    struct Owner {
+        id: String,
+        name: String,
+    }
+
+    impl Owner {
+        fn setup(conn: &Connection) {
+            conn.execute_batch(
+                r#"
+                    CREATE TABLE IF NOT EXISTS "Owner" (
+                        "id"    TEXT NOT NULL PRIMARY KEY,
+                        "name"  TEXT NOT NULL
+                    );
+
+                    CREATE UNIQUE INDEX IF NOT EXISTS "Owner_name_key"
+                    ON "Owner"("name");
+                "#
+            ).unwrap();
+        }
+
+        fn create(conn: &Connection, name: &str) -> Owner {
+            let id = Uuid::new_v4().to_string();
+
+            conn.execute(
+                "INSERT INTO Owner(\"id\", \"name\") VALUES(?1, ?2)",
+                [id.as_str(), name]
+            ).unwrap();
+
+            Owner {
+                id,
+                name: name.to_string()
+            }
+        }
+
+        fn all_by_name(conn: &Connection) -> HashMap<String, Owner> {
+            let mut result: HashMap<String, Owner> = HashMap::new();
+            let mut stmt = conn.prepare("SELECT * FROM \"Owner\" WHERE 1").unwrap();
+            let mut rows = stmt.query([]).unwrap();
+
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => {
+                        let id: String = row.get("id").unwrap();
+                        let name: String = row.get("name").unwrap();
+                        result.insert(name.clone(), Owner { id, name });
+                    },
+
+                    Ok(None) => break,
+                    Err(_) => break,
+                }
+            }
+
+            result
+        }
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct TodoList {
+        id: String,
+        name: String,
+        owner_id: String
+    }
+
+    impl TodoList {
+        fn setup(conn: &Connection) {
+            conn.execute_batch(
+                r#"
+                    CREATE TABLE IF NOT EXISTS "TodoList" (
+                        "id"      TEXT NOT NULL PRIMARY KEY,
+                        "name"    TEXT NOT NULL,
+                        "ownerId" TEXT NOT NULL,
+                        CONSTRAINT "TodoList_ownerId_fkey"
+                            FOREIGN KEY ("ownerId")
+                            REFERENCES "Owner" ("id")
+                            ON DELETE RESTRICT
+                            ON UPDATE CASCADE
+                    );
+
+                    CREATE UNIQUE INDEX IF NOT EXISTS "TodoList_name_ownerId_key"
+                    ON "TodoList"("name", "ownerId");
+                "#
+            ).unwrap();
+        }
+
+        fn create(conn: &Connection, name: &str, owner_id: &str) -> TodoList {
+            let id = Uuid::new_v4().to_string();
+
+            conn.execute(
+                "INSERT INTO TodoList(\"id\", \"name\", \"ownerId\") VALUES(?1, ?2, ?3)",
+                [id.as_str(), name, owner_id]
+            ).unwrap();
+
+            TodoList {
+                id,
+                name: name.to_string(),
+                owner_id: owner_id.to_string()
+            }
+        }
+
+        fn all_by_name(conn: &Connection) -> HashMap<String, TodoList> {
+            let mut result: HashMap<String, TodoList> = HashMap::new();
+            let mut stmt = conn.prepare("SELECT * FROM \"TodoList\" WHERE 1").unwrap();
+            let mut rows = stmt.query([]).unwrap();
+
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => {
+                        let id: String = row.get("id").unwrap();
+                        let name: String = row.get("name").unwrap();
+                        let owner_id: String = row.get("ownerId").unwrap();
+                        result.insert(name.clone(), TodoList { id, name, owner_id });
+                    },
+
+                    Ok(None) => break,
+                    Err(_) => break,
+                }
+            }
+
+            result
+        }
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    struct Toy {
+        id: String,
+        name: String,
+        color: Option<String>,
+        quantity: Option<i64>,
+        updated_at: Option<i64>
+    }
+
+    impl Toy {
+        fn setup(conn: &Connection) {
+            conn.execute_batch(
+                r#"
+                    CREATE TABLE IF NOT EXISTS "Toy" (
+                        "id"        TEXT NOT NULL PRIMARY KEY,
+                        "name"      TEXT NOT NULL,
+                        "color"     TEXT,
+                        "quantity"  INTEGER,
+                        "updatedAt" DATETIME
+                    );
+
+                    CREATE UNIQUE INDEX IF NOT EXISTS "Toy_name_key"
+                    ON "Toy"("name");
+                "#
+            ).unwrap();
+        }
+
+        fn create(conn: &Connection, name: &str, color: Option<&str>, quantity: Option<i64>, updated_at: Option<i64>) -> Toy {
+            let id = Uuid::new_v4().to_string();
+
+            conn.execute(
+                "INSERT INTO Toy(\"id\", \"name\", \"color\", \"quantity\", \"updatedAt\") VALUES(?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![id, name, color, quantity, updated_at]
+            ).unwrap();
+
+            Toy {
+                id,
+                name: name.to_string(),
+                color: color.map(|c| c.to_string()),
+                quantity,
+                updated_at
+            }
+        }
+
+        fn all_by_name(conn: &Connection) -> HashMap<String, Toy> {
+            let mut result: HashMap<String, Toy> = HashMap::new();
+            let mut stmt = conn.prepare("SELECT * FROM \"Toy\" WHERE 1").unwrap();
+            let mut rows = stmt.query([]).unwrap();
+
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => {
+                        let name: String = row.get("name").unwrap();
+
+                        result.insert(name.clone(), Toy {
+                            id: row.get("id").unwrap(),
+                            name,
+                            color: row.get("color").unwrap(),
+                            quantity: row.get("quantity").unwrap(),
+                            updated_at: row.get("updatedAt").unwrap()
+                        });
+                    },
+
+                    Ok(None) => break,
+                    Err(_) => break,
+                }
+            }
+
+            result
+        }
+    }
+
+    #[test]
+    fn merges_tables_with_no_foreign_keys() {
+        let (first, second, merged) = create_connections();
+
+        let woody = Owner::create(&first, "Woody");
+        let jessie = Owner::create(&second, "Jessie");
+        let bo = Owner::create(&second, "Bo");
+
+        crate::prismerge(
+            &SCHEMA,
+            &[first, second],
+            &merged,
+            &test_options()
+        ).unwrap();
+
+        let records = Owner::all_by_name(&merged);
+        assert!(records.len() == 3);
+
+        // Jessie and Bo are part of the primary because there are more records in
+        // that db (2 vs 1). Because they're in the primary, they retain their old
+        // IDs.
+        assert!(records["Jessie"].name == "Jessie");
+        assert!(records["Jessie"].id == jessie.id);
+
+        assert!(records["Bo"].name == "Bo");
+        assert!(records["Bo"].id == bo.id);
+
+        // Woody is in the secondary DB and therefore gets a new ID.
+        assert!(records["Woody"].name == "Woody");
+        assert!(records["Woody"].id != woody.id);
+    }
+
+    #[test]
+    fn merges_tables_with_foreign_keys() {
+        let (first, second, merged) = create_connections();
+
+        let woody = Owner::create(&first, "Woody");
+        let jessie = Owner::create(&second, "Jessie");
+        let bo = Owner::create(&second, "Bo");
+
+        TodoList::create(&first, "Groceries", woody.id.as_str());
+        TodoList::create(&second, "Chores", jessie.id.as_str());
+        TodoList::create(&second, "Errands", bo.id.as_str());
+
+        crate::prismerge(
+            &SCHEMA,
+            &[first, second],
+            &merged,
+            &test_options()
+        ).unwrap();
+
+        let owners = Owner::all_by_name(&merged);
+        let todo_lists = TodoList::all_by_name(&merged);
+
+        assert!(owners.len() == 3);
+        assert!(todo_lists.len() == 3);
+
+        let woodys_groceries = todo_lists.get("Groceries").unwrap();
+        assert!(woodys_groceries.name == "Groceries");
+        assert!(woodys_groceries.owner_id == owners.get("Woody").unwrap().id);
+
+        let jessies_chores = todo_lists.get("Chores").unwrap();
+        assert!(jessies_chores.name == "Chores");
+        assert!(jessies_chores.owner_id == owners.get("Jessie").unwrap().id);
+
+        let bos_errands = todo_lists.get("Errands").unwrap();
+        assert!(bos_errands.name == "Errands");
+        assert!(bos_errands.owner_id == owners.get("Bo").unwrap().id);
+    }
+
+    #[test]
+    fn merges_duplicate_records() {
+        let (first, second, merged) = create_connections();
+        let first_woody = Owner::create(&first, "Woody");
+        let second_woody = Owner::create(&second, "Woody");
+
+        TodoList::create(&first, "Chores", first_woody.id.as_str());
+        TodoList::create(&second, "Errands", second_woody.id.as_str());
+
+        crate::prismerge(
+            &SCHEMA,
+            &[first, second],
+            &merged,
+            &test_options()
+        ).unwrap();
+
+        let owners = Owner::all_by_name(&merged);
+        let todo_lists = TodoList::all_by_name(&merged);
+
+        assert!(owners.len() == 1);
+        assert!(todo_lists.len() == 2);
+
+        let merged_woody = owners.get("Woody").unwrap();
+        assert!([&first_woody.id, &second_woody.id].contains(&&merged_woody.id));
+
+        for (_, todo_list) in todo_lists.iter() {
+            assert!(todo_list.owner_id == merged_woody.id);
+        }
+    }
+
+    #[test]
+    fn reports_model_statistics() {
+        let (first, second, merged) = create_connections();
+        let first_woody = Owner::create(&first, "Woody");
+        let second_woody = Owner::create(&second, "Woody");
+        Owner::create(&second, "Jessie");
+
+        TodoList::create(&first, "Chores", first_woody.id.as_str());
+        TodoList::create(&second, "Errands", second_woody.id.as_str());
+
+        let reports = crate::prismerge(
+            &SCHEMA,
+            &[first, second],
+            &merged,
+            &test_options()
+        ).unwrap();
+
+        let owner_report = reports.iter().find(|r| r.name == "Owner").unwrap();
+        assert!(owner_report.rows_read == vec![1, 2]);
+        assert!(owner_report.rows_inserted == 2);
+        assert!(owner_report.rows_deduplicated == 1);
+        assert!(owner_report.rows_failed == 0);
+
+        let todo_list_report = reports.iter().find(|r| r.name == "TodoList").unwrap();
+        assert!(todo_list_report.rows_read == vec![1, 1]);
+        assert!(todo_list_report.rows_inserted == 2);
+        assert!(todo_list_report.rows_deduplicated == 0);
+    }
+
+    #[test]
+    fn records_conflicting_duplicates() {
+        let (first, second, merged) = create_connections();
+
+        Toy::create(&first, "Rex", Some("green"), Some(1), None);
+        Toy::create(&first, "Slinky", Some("brown"), Some(1), None);
+        Toy::create(&second, "Rex", Some("purple"), Some(1), None);
+
+        let reports = crate::prismerge(
+            &SCHEMA,
+            &[first, second],
+            &merged,
+            &crate::MergeOptions { record_conflicts: true, ..test_options() }
+        ).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys.len() == 2);
+
+        // The first database is the primary, so its values win.
+        assert!(toys["Rex"].color.as_deref() == Some("green"));
+
+        let toy_report = reports.iter().find(|r| r.name == "Toy").unwrap();
+        assert!(toy_report.rows_deduplicated == 1);
+        assert!(toy_report.rows_conflicted == 1);
+
+        let (column_name, merged_value, input_value): (String, String, String) = merged.query_row(
+            "SELECT column_name, merged_value, input_value FROM _prismerge_conflicts WHERE model = 'Toy'",
+            (),
+            |row| Ok((row.get(0).unwrap(), row.get(1).unwrap(), row.get(2).unwrap()))
+        ).unwrap();
+
+        assert!(column_name == "color");
+        assert!(merged_value == "'green'");
+        assert!(input_value == "'purple'");
+    }
+
+    #[test]
+    fn keep_last_policy_respects_input_order() {
+        let (first, second, merged) = create_connections();
+
+        // The second database has more rows and is therefore the primary, but the
+        // third database comes after it in input order.
+        let third = create_connection();
+        apply_schema(&third);
+
+        Toy::create(&first, "Rex", Some("green"), None, None);
+        Toy::create(&second, "Rex", Some("purple"), None, None);
+        Toy::create(&second, "Slinky", Some("brown"), None, None);
+        Toy::create(&third, "Slinky", Some("gray"), None, None);
+
+        let mut schema = test_schema();
+        schema.models.get_mut("Toy").unwrap().conflict_policy = ConflictPolicy::KeepLast;
+
+        crate::prismerge(&schema, &[first, second, third], &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys["Rex"].color.as_deref() == Some("purple"));
+        assert!(toys["Slinky"].color.as_deref() == Some("gray"));
+    }
+
+    #[test]
+    fn newest_policy_compares_timestamps() {
+        let (first, second, merged) = create_connections();
+
+        Toy::create(&first, "Rex", Some("green"), None, Some(100));
+        Toy::create(&first, "Slinky", Some("brown"), None, Some(200));
+        Toy::create(&second, "Rex", Some("purple"), None, Some(300));
+        Toy::create(&second, "Slinky", Some("gray"), None, Some(150));
+
+        let mut schema = test_schema();
+        schema.models.get_mut("Toy").unwrap().conflict_policy = ConflictPolicy::Newest("updatedAt".to_string());
+
+        crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys["Rex"].color.as_deref() == Some("purple"));
+        assert!(toys["Rex"].updated_at == Some(300));
+        assert!(toys["Slinky"].color.as_deref() == Some("brown"));
+        assert!(toys["Slinky"].updated_at == Some(200));
+    }
+
+    #[test]
+    fn coalesce_policy_and_aggregates() {
+        let (first, second, merged) = create_connections();
+
+        Toy::create(&first, "Rex", None, Some(2), None);
+        Toy::create(&first, "Slinky", Some("brown"), Some(1), None);
+        Toy::create(&second, "Rex", Some("green"), Some(3), None);
+
+        let mut schema = test_schema();
+        let toy = schema.models.get_mut("Toy").unwrap();
+        toy.conflict_policy = ConflictPolicy::Coalesce;
+        toy.columns.iter_mut().find(|col| col.name == "quantity").unwrap().aggregate = Some(Aggregate::Sum);
+
+        crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys["Rex"].color.as_deref() == Some("green"));
+        assert!(toys["Rex"].quantity == Some(5));
+        assert!(toys["Slinky"].quantity == Some(1));
+    }
+
+    #[test]
+    fn applies_conflict_policies_from_command_line() {
+        let mut schema = test_schema();
+
+        crate::apply_conflict_policies(
+            &mut schema,
+            &["Toy=newest:updatedAt".to_string(), "Toy.quantity=max".to_string()]
+        ).unwrap();
+
+        let toy = &schema.models["Toy"];
+        assert!(toy.conflict_policy == ConflictPolicy::Newest("updatedAt".to_string()));
+        assert!(toy.get_col("quantity").unwrap().aggregate == Some(Aggregate::Max));
+
+        assert!(crate::apply_conflict_policies(&mut schema, &["Toy=newest:missing".to_string()]).is_err());
+        assert!(crate::apply_conflict_policies(&mut schema, &["Nope=keep-last".to_string()]).is_err());
+    }
+
+    #[test]
+    fn normalizes_unique_keys() {
+        let (first, second, merged) = create_connections();
+
+        Owner::create(&first, "Woody");
+        Owner::create(&first, "Jessie");
+        Owner::create(&second, " woody ");
+
+        let mut schema = test_schema();
+        let owner = schema.models.get_mut("Owner").unwrap();
+        owner.columns.iter_mut().find(|col| col.name == "name").unwrap().normalizers = vec![Normalizer::Trim, Normalizer::Lowercase];
+
+        crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let owners = Owner::all_by_name(&merged);
+        assert!(owners.len() == 2);
+        assert!(owners.contains_key("Woody"));
+    }
+
+    #[test]
+    fn normalizes_unique_keys_by_type() {
+        // Truncating timestamps to the second makes the third reading a duplicate too.
+        for (normalizers, expected_inserted) in [
+            (vec![], 2),
+            (vec!["truncate:second".parse::<Normalizer>().unwrap()], 1)
+        ] {
+            let first = create_connection();
+            let second = create_connection();
+            let third = create_connection();
+            let merged = create_connection();
+
+            for conn in [&first, &second, &third] {
+                conn.execute("CREATE TABLE \"Reading\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"takenAt\" DATETIME NOT NULL, \"value\" REAL NOT NULL, \"valid\" BOOLEAN NOT NULL)", ()).unwrap();
+            }
+
+            first.execute("INSERT INTO \"Reading\" VALUES ('a', 1704164645678, 1.0, 1)", ()).unwrap();
+            second.execute("INSERT INTO \"Reading\" VALUES ('b', '2024-01-02T03:04:05.678Z', '1', 'true')", ()).unwrap();
+            third.execute("INSERT INTO \"Reading\" VALUES ('c', '2024-01-02T03:04:05.999Z', 1.0, 1)", ()).unwrap();
+
+            let mut schema = prisma_parser::parse(r#"
+                model Reading {
+                  id      String   @id
+                  takenAt DateTime
+                  value   Float
+                  valid   Boolean
+
+                  @@unique([takenAt, value, valid])
+                }
+            "#).unwrap();
+
+            schema.models.get_mut("Reading").unwrap().get_col_mut("takenAt").unwrap().normalizers = normalizers;
+
+            let reports = crate::prismerge(&schema, &[first, second, third], &merged, &test_options()).unwrap();
+            assert!(reports[0].rows_inserted == expected_inserted);
+
+            // The index over the normalized key is only needed while merging.
+            let indices: u64 = merged
+                .query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = '_prismerge_Reading_normalized_key'", (), |row| row.get(0))
+                .unwrap();
+
+            assert!(indices == 0);
+        }
+    }
+
+    #[test]
+    fn dedupes_rows_by_hash() {
+        for (dedupe_by_hash, expected_annotations) in [
+            (false, vec!["Brave:Buzz", "Brave:Woody", "Brave:Woody", "Loyal:Woody"]),
+            (true, vec!["Brave:Buzz", "Brave:Woody", "Loyal:Woody"])
+        ] {
+            let (first, second, merged) = create_connections();
+
+            for conn in [&first, &second] {
+                conn.execute("CREATE TABLE \"Annotation\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"ownerId\" TEXT NOT NULL, \"text\" TEXT NOT NULL)", ()).unwrap();
+            }
+
+            let annotate = |conn: &Connection, owner: &Owner, text: &str| {
+                conn.execute("INSERT INTO \"Annotation\" VALUES (?1, ?2, ?3)", [&Uuid::new_v4().to_string(), &owner.id, text]).unwrap();
+            };
+
+            let woody = Owner::create(&first, "Woody");
+            annotate(&first, &woody, "Brave");
+
+            // Woody's ID differs in the second input, but it's translated before hashing.
+            let other_woody = Owner::create(&second, "Woody");
+            let buzz = Owner::create(&second, "Buzz");
+            annotate(&second, &other_woody, "Brave");
+            annotate(&second, &other_woody, "Loyal");
+            annotate(&second, &buzz, "Brave");
+
+            let mut schema = prisma_parser::parse(r#"
+                model Owner {
+                  id   String @id
+                  name String @unique
+                }
+
+                /// @prismerge.dedupeByHash
+                model Annotation {
+                  id      String @id
+                  ownerId String
+                  owner   Owner  @relation(fields: [ownerId], references: [id])
+                  text    String
+                }
+            "#).unwrap();
+
+            assert!(schema.models["Annotation"].dedupe_by_hash);
+            schema.models.get_mut("Annotation").unwrap().dedupe_by_hash = dedupe_by_hash;
+
+            crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+            let annotations: Vec<String> = merged
+                .prepare("SELECT text || ':' || name FROM Annotation JOIN Owner ON Owner.id = Annotation.ownerId ORDER BY 1")
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(|annotation| annotation.unwrap())
+                .collect();
+
+            assert!(annotations == expected_annotations);
+        }
+    }
+
+    #[test]
+    fn forgets_hashes_of_rejected_rows() {
+        let first = create_connection();
+        let second = create_connection();
+        let merged = create_connection();
+
+        for conn in [&first, &second] {
+            conn.execute_batch(r#"
+                CREATE TABLE "Note" ("id" TEXT NOT NULL PRIMARY KEY, "text" TEXT NOT NULL);
+                CREATE TABLE "Tag" ("id" TEXT NOT NULL PRIMARY KEY, "noteId" TEXT NOT NULL REFERENCES "Note" ("id"));
+            "#).unwrap();
+        }
+
+        first.execute("INSERT INTO \"Note\" VALUES ('a', 'Brave'), ('b', 'Loyal')", ()).unwrap();
+        second.execute("INSERT INTO \"Note\" VALUES ('c', 'Brave')", ()).unwrap();
+        second.execute("INSERT INTO \"Tag\" VALUES ('t', 'c')", ()).unwrap();
+
+        let schema = prisma_parser::parse(r#"
+            /// @prismerge.dedupeByHash
+            model Note {
+              id   String @id
+              text String
+              tags Tag[]
+            }
+
+            model Tag {
+              id     String @id
+              noteId String
+              note   Note   @relation(fields: [noteId], references: [id])
+            }
+        "#).unwrap();
+
+        // The output rejects the first input's Brave note, so the second input's copy
+        // has to be inserted instead of being mapped to it.
+        let options = crate::MergeOptions {
+            output_ddl: Some(vec![
+                "CREATE TABLE \"Note\" (\"id\" TEXT NOT NULL PRIMARY KEY CHECK (\"id\" <> 'a'), \"text\" TEXT NOT NULL)".to_string(),
+                "CREATE TABLE \"Tag\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"noteId\" TEXT NOT NULL REFERENCES \"Note\" (\"id\"))".to_string()
+            ]),
+            ..test_options()
+        };
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &options).unwrap();
+
+        let tagged: Vec<String> = merged
+            .prepare("SELECT text FROM Tag JOIN Note ON Note.id = Tag.noteId")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .map(|text| text.unwrap())
+            .collect();
+
+        assert!(tagged == vec!["Brave"]);
+
+        let note_report = reports.iter().find(|report| report.name == "Note").unwrap();
+        assert!(note_report.rows_inserted == 2);
+        assert!(note_report.rows_failed == 1);
+        assert!(note_report.rows_deduplicated == 0);
+    }
+
+    #[test]
+    fn copies_keyless_models_and_unmodeled_tables() {
+        for (policy, expected_visits, expected_settings) in [
+            ("skip", vec![], vec![]),
+            ("primary", vec!["Mon:Woody", "Tue:Buzz"], vec!["lang=en", "theme=dark"]),
+            ("union", vec!["Mon:Woody", "Mon:Woody", "Tue:Buzz"], vec!["lang=en", "theme=dark", "theme=dark"]),
+            ("union:dedup", vec!["Mon:Woody", "Tue:Buzz"], vec!["lang=en", "theme=dark"])
+        ] {
+            let (first, second, merged) = create_connections();
+
+            for conn in [&first, &second] {
+                conn.execute_batch(r#"
+                    CREATE TABLE "Visit" ("ownerId" TEXT NOT NULL, "day" TEXT NOT NULL);
+                    CREATE TABLE "Setting" ("key" TEXT NOT NULL, "value" TEXT NOT NULL);
+                "#).unwrap();
+            }
+
+            let woody = Owner::create(&first, "Woody");
+            first.execute("INSERT INTO \"Visit\" VALUES (?1, 'Mon')", [&woody.id]).unwrap();
+            first.execute("INSERT INTO \"Setting\" VALUES ('theme', 'dark')", ()).unwrap();
+
+            let other_woody = Owner::create(&second, "Woody");
+            let buzz = Owner::create(&second, "Buzz");
+            second.execute("INSERT INTO \"Visit\" VALUES (?1, 'Mon')", [&other_woody.id]).unwrap();
+            second.execute("INSERT INTO \"Visit\" VALUES (?1, 'Tue')", [&buzz.id]).unwrap();
+            second.execute("INSERT INTO \"Setting\" VALUES ('theme', 'dark'), ('lang', 'en')", ()).unwrap();
+
+            let mut schema = prisma_parser::parse(r#"
+                model Owner {
+                  id   String @id
+                  name String @unique
+                }
+
+                model Visit {
+                  ownerId String
+                  owner   Owner  @relation(fields: [ownerId], references: [id])
+                  day     String
+                }
+            "#).unwrap();
+
+            let specs = vec![format!("Visit={}", policy), format!("Setting={}", policy)];
+            let passthrough = crate::apply_passthrough(&mut schema, &specs).unwrap();
+            let options = crate::MergeOptions { passthrough, ..test_options() };
+
+            crate::prismerge(&schema, &[first, second], &merged, &options).unwrap();
+
+            let query = |sql: &str| -> Vec<String> {
+                merged
+                    .prepare(sql)
+                    .unwrap()
+                    .query_map((), |row| row.get(0))
+                    .unwrap()
+                    .map(|value| value.unwrap())
+                    .collect()
+            };
+
+            assert!(query("SELECT day || ':' || name FROM Visit JOIN Owner ON Owner.id = Visit.ownerId ORDER BY 1") == expected_visits);
+            assert!(query("SELECT key || '=' || value FROM Setting ORDER BY 1") == expected_settings);
+        }
+    }
+
+    #[test]
+    fn rejects_passthrough_policies_for_models_with_primary_keys() {
+        let mut schema = test_schema();
+        assert!(crate::apply_passthrough(&mut schema, &["Owner=union".to_string()]).is_err());
+        assert!(crate::apply_passthrough(&mut schema, &["Setting=all".to_string()]).is_err());
+    }
+
+    #[test]
+    fn skips_models() {
+        let (first, second, merged) = create_connections();
+        let woody = Owner::create(&first, "Woody");
+        TodoList::create(&first, "Chores", woody.id.as_str());
+        Toy::create(&second, "Rex", None, None, None);
+
+        let mut schema = test_schema();
+        schema.models.get_mut("Toy").unwrap().skip = true;
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        assert!(Owner::all_by_name(&merged).len() == 1);
+        assert!(TodoList::all_by_name(&merged).len() == 1);
+        assert!(Toy::all_by_name(&merged).is_empty());
+        assert!(!reports.iter().any(|r| r.name == "Toy"));
+    }
+
+    #[test]
+    fn filters_rows() {
+        let (first, second, merged) = create_connections();
+
+        Toy::create(&first, "Rex", None, Some(1), None);
+        Toy::create(&first, "Slinky", None, Some(5), None);
+        Toy::create(&second, "Hamm", None, Some(10), None);
+
+        let mut schema = test_schema();
+        crate::apply_filters(&mut schema, &["Toy=quantity > 2".to_string()]).unwrap();
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys.len() == 2);
+        assert!(toys.contains_key("Slinky"));
+        assert!(toys.contains_key("Hamm"));
+
+        let toy_report = reports.iter().find(|r| r.name == "Toy").unwrap();
+        assert!(toy_report.rows_read == vec![1, 1]);
+    }
+
+    #[test]
+    fn filters_cascade_to_children() {
+        let (first, second, merged) = create_connections();
+
+        let woody = Owner::create(&first, "Woody");
+        let jessie = Owner::create(&first, "Jessie");
+        TodoList::create(&first, "Chores", woody.id.as_str());
+        TodoList::create(&first, "Rodeo", jessie.id.as_str());
+        Owner::create(&second, "Woody");
+
+        let mut schema = test_schema();
+        crate::apply_filters(&mut schema, &["Owner=name = 'Woody'".to_string()]).unwrap();
+        assert!(schema.cascade_filters() == vec![("TodoList".to_string(), "Owner".to_string())]);
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let todo_lists = TodoList::all_by_name(&merged);
+        assert!(todo_lists.len() == 1);
+        assert!(todo_lists.contains_key("Chores"));
+
+        let todo_list_report = reports.iter().find(|r| r.name == "TodoList").unwrap();
+        assert!(todo_list_report.rows_read == vec![1, 0]);
+        assert!(todo_list_report.rows_orphaned == 0);
+    }
+
+    #[test]
+    fn extracts_related_rows() {
+        let (first, second, merged) = create_connections();
+
+        let woody = Owner::create(&first, "Woody");
+        TodoList::create(&first, "Chores", woody.id.as_str());
+        TodoList::create(&first, "Errands", woody.id.as_str());
+
+        let buzz = Owner::create(&second, "Buzz");
+        TodoList::create(&second, "Space", buzz.id.as_str());
+
+        let mut schema = test_schema();
+        let seeds = vec!["TodoList=name = 'Chores'".parse::<Seed>().unwrap()];
+
+        for conn in [&first, &second] {
+            extract::compute_closure(&schema, conn, &seeds).unwrap();
+        }
+
+        extract::apply_filters(&mut schema).unwrap();
+        crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let owners = Owner::all_by_name(&merged);
+        assert!(owners.len() == 1);
+        assert!(owners.contains_key("Woody"));
+
+        let todo_lists = TodoList::all_by_name(&merged);
+        assert!(todo_lists.len() == 1);
+        assert!(todo_lists["Chores"].owner_id == owners["Woody"].id);
+
+        assert!(Toy::all_by_name(&merged).is_empty());
+    }
+
+    #[test]
+    fn merges_inputs_with_schema_drift() {
+        // The first input was created by an older version of the schema, before the
+        // color column was added and the qty column was renamed to quantity.
+        let old = create_connection();
+        let new = create_connection();
+        let merged = create_connection();
+
+        old.execute_batch(r#"
+            CREATE TABLE "Toy" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "qty" INTEGER, "updatedAt" DATETIME);
+            INSERT INTO "Toy" VALUES ('t1', 'Rex', 2, NULL), ('t2', 'Hamm', 3, NULL);
+        "#).unwrap();
+
+        Owner::setup(&old);
+        TodoList::setup(&old);
+        apply_schema(&new);
+        Toy::create(&new, "Slinky", Some("brown"), Some(1), None);
+
+        let mut schema = test_schema();
+        let toy_model = schema.models.get_mut("Toy").unwrap();
+        toy_model.add_rename("quantity", "qty").unwrap();
+        toy_model.get_col_mut("color").unwrap().default = Some("'green'".to_string());
+
+        crate::prismerge(&schema, &[old, new], &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys.len() == 3);
+        assert!(toys["Rex"].quantity == Some(2));
+        assert!(toys["Rex"].color.as_deref() == Some("green"));
+        assert!(toys["Slinky"].color.as_deref() == Some("brown"));
+    }
+
+    #[test]
+    fn scopes_renames_to_schema_versions() {
+        // Both inputs lack the quantity column and have a qty column. The qty column was
+        // renamed to quantity by the second migration, so only the input that hasn't
+        // applied it yet holds quantities in qty.
+        let mut inputs: Vec<Connection> = ["1_init", "2_rename_qty"]
+            .iter()
+            .enumerate()
+            .map(|(idx, last_migration)| {
+                let conn = create_connection();
+                Owner::setup(&conn);
+                TodoList::setup(&conn);
+
+                conn.execute_batch(format!(r#"
+                    CREATE TABLE "_prisma_migrations" (
+                        "id"                    TEXT PRIMARY KEY NOT NULL,
+                        "checksum"              TEXT NOT NULL,
+                        "finished_at"           DATETIME,
+                        "migration_name"        TEXT NOT NULL,
+                        "logs"                  TEXT,
+                        "rolled_back_at"        DATETIME,
+                        "started_at"            DATETIME NOT NULL DEFAULT current_timestamp,
+                        "applied_steps_count"   INTEGER UNSIGNED NOT NULL DEFAULT 0
+                    );
+
+                    INSERT INTO "_prisma_migrations" ("id", "checksum", "finished_at", "migration_name")
+                    VALUES ('{idx}', '', current_timestamp, '{last_migration}');
+
+                    CREATE TABLE "Toy" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "color" TEXT, "qty" INTEGER, "updatedAt" DATETIME);
+                    INSERT INTO "Toy" VALUES ('t{idx}', 'Toy {idx}', NULL, 7, NULL);
+                "#).as_str()).unwrap();
+
+                conn
+            })
+            .collect();
+
+        // The merged database's tables are created from an up-to-date input.
+        let current = create_connection();
+        apply_schema(&current);
+        inputs.push(current);
+
+        let merged = create_connection();
+        let mut schema = test_schema();
+        schema.models.get_mut("Toy").unwrap().add_rename("quantity", "qty@2_rename_qty").unwrap();
+
+        crate::prismerge(&schema, &inputs, &merged, &test_options()).unwrap();
+
+        let toys = Toy::all_by_name(&merged);
+        assert!(toys["Toy 0"].quantity == Some(7));
+        assert!(toys["Toy 1"].quantity.is_none());
+    }
+
+    #[test]
+    fn creates_output_schema_from_prisma_schema() {
+        let (first, second, merged) = create_connections();
+        let woody = Owner::create(&first, "Woody");
+        TodoList::create(&first, "Chores", woody.id.as_str());
+        Owner::create(&second, "Buzz");
+
+        let schema = test_schema();
+        let options = crate::MergeOptions { output_ddl: Some(ddl::prisma_ddl(&schema)), ..test_options() };
+
+        crate::prismerge(&schema, &[first, second], &merged, &options).unwrap();
+
+        assert!(Owner::all_by_name(&merged).len() == 2);
+        assert!(TodoList::all_by_name(&merged).len() == 1);
+
+        // The generated schema declares the foreign key from TodoList to Owner.
+        let foreign_key_table: String = merged
+            .query_row("SELECT \"table\" FROM pragma_foreign_key_list('TodoList')", (), |row| row.get(0))
+            .unwrap();
+
+        assert!(foreign_key_table == "Owner");
+    }
+
+    #[test]
+    fn merges_with_introspected_schema() {
+        let (first, second, merged) = create_connections();
+        let woody = Owner::create(&first, "Woody");
+        TodoList::create(&first, "Chores", woody.id.as_str());
+        let buzz = Owner::create(&second, "Buzz");
+        TodoList::create(&second, "Errands", buzz.id.as_str());
+
+        let schema = introspect::introspect(&first).unwrap().schema;
+
+        crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
 
-    fn create_connection() -> Connection {
-        Connection::open_in_memory().unwrap()
+        assert!(Owner::all_by_name(&merged).len() == 2);
+        assert!(TodoList::all_by_name(&merged).len() == 2);
     }
 
-    fn create_connections() -> (Connection, Connection, Connection) {
-        let first = create_connection();
-        let second = create_connection();
-        let merged = create_connection();
+    #[test]
+    fn merges_introspected_integer_keys_via_passthrough() {
+        for (policy, expected_count) in [("skip", 0), ("union", 2)] {
+            let (first, second, merged) = create_connections();
+
+            for (conn, id) in [(&first, 1), (&second, 2)] {
+                conn.execute_batch(format!(r#"
+                    CREATE TABLE "Counter" ("id" INTEGER PRIMARY KEY, "label" TEXT NOT NULL);
+                    INSERT INTO "Counter" VALUES ({id}, 'counter {id}');
+                "#).as_str()).unwrap();
+            }
 
-        apply_schema(&first);
-        apply_schema(&second);
+            Owner::create(&first, "Woody");
+            Owner::create(&second, "Buzz");
 
-        (first, second, merged)
-    }
+            let mut schema = introspect::introspect(&first).unwrap().schema;
+            assert!(schema.models["Counter"].primary_key().is_none());
 
-    #[derive(Debug)]
-    struct Owner {
-        id: String,
-        name: String,
-    }
+            crate::apply_passthrough(&mut schema, &[format!("Counter={}", policy)]).unwrap();
+            schema.select(&[], &[]).unwrap();
 
-    impl Owner {
-        fn setup(conn: &Connection) {
-            conn.execute_batch(
-                r#"
-                    CREATE TABLE IF NOT EXISTS "Owner" (
-                        "id"    TEXT NOT NULL PRIMARY KEY,
-                        "name"  TEXT NOT NULL
-                    );
+            let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
 
-                    CREATE UNIQUE INDEX IF NOT EXISTS "Owner_name_key"
-                    ON "Owner"("name");
-                "#
-            ).unwrap();
+            let count: u64 = merged.query_row("SELECT COUNT(*) FROM \"Counter\"", (), |row| row.get(0)).unwrap();
+            assert!(count == expected_count);
+            assert!(Owner::all_by_name(&merged).len() == 2);
+            assert!(reports.iter().all(|report| report.rows_failed == 0));
         }
+    }
 
-        fn create(conn: &Connection, name: &str) -> Owner {
-            let id = Uuid::new_v4().to_string();
+    #[test]
+    fn copies_ignored_models() {
+        for (policy, expected_places) in [
+            (IgnorePolicy::Skip, vec![]),
+            (IgnorePolicy::RawCopy, vec!["Attic:Woody", "Attic:Woody", "Yard:Buzz"]),
+            (IgnorePolicy::Dedup, vec!["Attic:Woody", "Yard:Buzz"])
+        ] {
+            let (first, second, merged) = create_connections();
+
+            for conn in [&first, &second] {
+                conn.execute("CREATE TABLE \"Sighting\" (\"ownerId\" TEXT NOT NULL, \"place\" TEXT NOT NULL)", ()).unwrap();
+            }
 
-            conn.execute(
-                "INSERT INTO Owner(\"id\", \"name\") VALUES(?1, ?2)",
-                [id.as_str(), name]
-            ).unwrap();
+            let woody = Owner::create(&first, "Woody");
+            first.execute("INSERT INTO \"Sighting\" VALUES (?1, 'Attic')", [&woody.id]).unwrap();
 
-            Owner {
-                id,
-                name: name.to_string()
-            }
-        }
+            let other_woody = Owner::create(&second, "Woody");
+            let buzz = Owner::create(&second, "Buzz");
+            second.execute("INSERT INTO \"Sighting\" VALUES (?1, 'Attic')", [&other_woody.id]).unwrap();
+            second.execute("INSERT INTO \"Sighting\" VALUES (?1, 'Yard')", [&buzz.id]).unwrap();
 
-        fn all_by_name(conn: &Connection) -> HashMap<String, Owner> {
-            let mut result: HashMap<String, Owner> = HashMap::new();
-            let mut stmt = conn.prepare("SELECT * FROM \"Owner\" WHERE 1").unwrap();
-            let mut rows = stmt.query([]).unwrap();
+            let mut schema = prisma_parser::parse(r#"
+                model Owner {
+                  id   String @id
+                  name String @unique
+                }
 
-            loop {
-                match rows.next() {
-                    Ok(Some(row)) => {
-                        let id: String = row.get("id").unwrap();
-                        let name: String = row.get("name").unwrap();
-                        result.insert(name.clone(), Owner { id, name });
-                    },
+                model Sighting {
+                  ownerId String
+                  owner   Owner  @relation(fields: [ownerId], references: [id])
+                  place   String
 
-                    Ok(None) => break,
-                    Err(_) => break,
+                  @@ignore
                 }
-            }
+            "#).unwrap();
 
-            result
+            schema.models.get_mut("Sighting").unwrap().ignore_policy = policy;
+            schema.select(&[], &[]).unwrap();
+
+            let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+            // Foreign keys are translated, so every sighting points at a merged owner.
+            let places: Vec<String> = merged
+                .prepare("SELECT place || ':' || name FROM Sighting JOIN Owner ON Owner.id = Sighting.ownerId ORDER BY 1")
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(|place| place.unwrap())
+                .collect();
+
+            assert!(places == expected_places);
+
+            let report = reports.iter().find(|r| r.name == "Sighting");
+
+            match policy {
+                IgnorePolicy::Skip => assert!(report.is_none()),
+                IgnorePolicy::RawCopy => assert!(report.unwrap().rows_inserted == 3),
+                IgnorePolicy::Dedup => assert!(report.unwrap().rows_deduplicated == 1)
+            }
         }
     }
 
-    #[allow(dead_code)]
-    #[derive(Debug)]
-    struct TodoList {
-        id: String,
-        name: String,
-        owner_id: String
+    #[test]
+    fn copies_ignored_models_missing_from_some_inputs() {
+        let (first, second, merged) = create_connections();
+
+        // Only the second input has the table, eg. because the first predates it.
+        second.execute("CREATE TABLE \"Sighting\" (\"ownerId\" TEXT NOT NULL, \"place\" TEXT NOT NULL)", ()).unwrap();
+
+        Owner::create(&first, "Woody");
+        let buzz = Owner::create(&second, "Buzz");
+        second.execute("INSERT INTO \"Sighting\" VALUES (?1, 'Yard')", [&buzz.id]).unwrap();
+
+        let mut schema = prisma_parser::parse(r#"
+            model Owner {
+              id   String @id
+              name String @unique
+            }
+
+            model Sighting {
+              ownerId String
+              owner   Owner  @relation(fields: [ownerId], references: [id])
+              place   String
+
+              @@ignore
+            }
+        "#).unwrap();
+
+        schema.models.get_mut("Sighting").unwrap().ignore_policy = IgnorePolicy::RawCopy;
+        schema.select(&[], &[]).unwrap();
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
+
+        let place: String = merged.query_row("SELECT place FROM Sighting", (), |row| row.get(0)).unwrap();
+        assert!(place == "Yard");
+
+        let report = reports.iter().find(|r| r.name == "Sighting").unwrap();
+        assert!(report.rows_read == vec![0, 1]);
+        assert!(report.rows_inserted == 1);
     }
 
-    impl TodoList {
-        fn setup(conn: &Connection) {
-            conn.execute_batch(
-                r#"
-                    CREATE TABLE IF NOT EXISTS "TodoList" (
-                        "id"      TEXT NOT NULL PRIMARY KEY,
-                        "name"    TEXT NOT NULL,
-                        "ownerId" TEXT NOT NULL,
-                        CONSTRAINT "TodoList_ownerId_fkey"
-                            FOREIGN KEY ("ownerId")
-                            REFERENCES "Owner" ("id")
-                            ON DELETE RESTRICT
-                            ON UPDATE CASCADE
-                    );
+    #[test]
+    fn deduplicates_ignored_models_with_primary_keys() {
+        let (first, second, merged) = create_connections();
 
-                    CREATE UNIQUE INDEX IF NOT EXISTS "TodoList_name_ownerId_key"
-                    ON "TodoList"("name", "ownerId");
-                "#
-            ).unwrap();
+        for conn in [&first, &second] {
+            conn.execute_batch(r#"
+                CREATE TABLE "Badge" ("id" TEXT NOT NULL PRIMARY KEY, "ownerId" TEXT NOT NULL, "label" TEXT NOT NULL);
+                CREATE TABLE "Sighting" ("badgeId" TEXT NOT NULL, "place" TEXT NOT NULL);
+            "#).unwrap();
         }
 
-        fn create(conn: &Connection, name: &str, owner_id: &str) -> TodoList {
-            let id = Uuid::new_v4().to_string();
+        let woody = Owner::create(&first, "Woody");
+        first.execute("INSERT INTO \"Badge\" VALUES ('a', ?1, 'Sheriff')", [&woody.id]).unwrap();
+
+        let other_woody = Owner::create(&second, "Woody");
+        let buzz = Owner::create(&second, "Buzz");
+        second.execute("INSERT INTO \"Badge\" VALUES ('b', ?1, 'Sheriff')", [&other_woody.id]).unwrap();
+        second.execute("INSERT INTO \"Badge\" VALUES ('c', ?1, 'Ranger')", [&buzz.id]).unwrap();
+        second.execute("INSERT INTO \"Sighting\" VALUES ('b', 'Yard')", ()).unwrap();
+
+        let mut schema = prisma_parser::parse(r#"
+            model Owner {
+              id   String @id
+              name String @unique
+            }
 
-            conn.execute(
-                "INSERT INTO TodoList(\"id\", \"name\", \"ownerId\") VALUES(?1, ?2, ?3)",
-                [id.as_str(), name, owner_id]
-            ).unwrap();
+            model Badge {
+              id       String     @id
+              ownerId  String
+              owner    Owner      @relation(fields: [ownerId], references: [id])
+              label    String
+              sighting Sighting[]
 
-            TodoList {
-                id,
-                name: name.to_string(),
-                owner_id: owner_id.to_string()
+              @@ignore
             }
+
+            model Sighting {
+              badgeId String
+              badge   Badge  @relation(fields: [badgeId], references: [id])
+              place   String
+
+              @@ignore
+            }
+        "#).unwrap();
+
+        for model_name in ["Badge", "Sighting"] {
+            schema.models.get_mut(model_name).unwrap().ignore_policy = IgnorePolicy::Dedup;
         }
 
-        fn all_by_name(conn: &Connection) -> HashMap<String, TodoList> {
-            let mut result: HashMap<String, TodoList> = HashMap::new();
-            let mut stmt = conn.prepare("SELECT * FROM \"TodoList\" WHERE 1").unwrap();
-            let mut rows = stmt.query([]).unwrap();
+        schema.select(&[], &[]).unwrap();
 
-            loop {
-                match rows.next() {
-                    Ok(Some(row)) => {
-                        let id: String = row.get("id").unwrap();
-                        let name: String = row.get("name").unwrap();
-                        let owner_id: String = row.get("ownerId").unwrap();
-                        result.insert(name.clone(), TodoList { id, name, owner_id });
-                    },
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
 
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
+        // The second input's Sheriff badge duplicates the first's, so it's dropped and
+        // its sighting is moved over to the surviving badge.
+        let badges: Vec<String> = merged
+            .prepare("SELECT id FROM Badge ORDER BY id")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .map(|id| id.unwrap())
+            .collect();
 
-            result
+        assert!(badges == vec!["a", "c"]);
+
+        let badge_id: String = merged.query_row("SELECT badgeId FROM Sighting", (), |row| row.get(0)).unwrap();
+        assert!(badge_id == "a");
+
+        let report = reports.iter().find(|r| r.name == "Badge").unwrap();
+        assert!(report.rows_deduplicated == 1);
+        assert!(report.rows_failed == 0);
+    }
+
+    #[test]
+    fn rejects_copied_rows_that_collide() {
+        let first = create_connection();
+        let second = create_connection();
+        let merged = create_connection();
+
+        for conn in [&first, &second] {
+            conn.execute("CREATE TABLE \"Badge\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"label\" TEXT NOT NULL)", ()).unwrap();
         }
+
+        first.execute("INSERT INTO \"Badge\" VALUES ('a', 'Sheriff')", ()).unwrap();
+        second.execute("INSERT INTO \"Badge\" VALUES ('a', 'Deputy'), ('b', 'Ranger')", ()).unwrap();
+
+        let mut schema = prisma_parser::parse(r#"
+            model Badge {
+              id    String @id
+              label String
+
+              @@ignore
+            }
+        "#).unwrap();
+
+        schema.models.get_mut("Badge").unwrap().ignore_policy = IgnorePolicy::RawCopy;
+
+        let options = crate::MergeOptions {
+            input_labels: vec!["first".to_string(), "second".to_string()],
+            ..test_options()
+        };
+
+        let reports = crate::prismerge(&schema, &[first, second], &merged, &options).unwrap();
+
+        // The second input's badge has the same primary key as the first's, so it's
+        // rejected rather than silently dropped.
+        let rejects: Vec<String> = merged
+            .prepare("SELECT input || ':' || model FROM _prismerge_rejects")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .map(|reject| reject.unwrap())
+            .collect();
+
+        assert!(rejects == vec!["second:Badge"]);
+        assert!(reports[0].rows_inserted == 2);
+        assert!(reports[0].rows_failed == 1);
+        assert!(reports[0].rows_deduplicated == 0);
     }
 
     #[test]
-    fn merges_tables_with_no_foreign_keys() {
-        let (first, second, merged) = create_connections();
+    fn counts_copied_rows_that_fail_to_read() {
+        let first = create_connection();
+        let merged = create_connection();
 
-        let woody = Owner::create(&first, "Woody");
-        let jessie = Owner::create(&second, "Jessie");
-        let bo = Owner::create(&second, "Bo");
+        first.execute_batch(r#"
+            CREATE TABLE "Sighting" ("place" TEXT NOT NULL);
+            INSERT INTO "Sighting" VALUES ('Attic'), ('Yard');
+        "#).unwrap();
 
-        crate::prismerge(
-            &SCHEMA,
-            &vec![first, second],
-            &merged,
-            1,
-            false
-        );
+        // Succeeds while the rows are counted, then fails on the very first row read.
+        let calls = std::sync::atomic::AtomicUsize::new(0);
 
-        let records = Owner::all_by_name(&merged);
-        assert!(records.len() == 3);
+        first.create_scalar_function("flaky", 0, rusqlite::functions::FunctionFlags::SQLITE_UTF8, move |_| {
+            match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 | 1 => Ok(1),
+                _ => Err(rusqlite::Error::UserFunctionError("disk on fire".into()))
+            }
+        }).unwrap();
 
-        // Jessie and Bo are part of the primary because there are more records in
-        // that db (2 vs 1). Because they're in the primary, they retain their old
-        // IDs.
-        assert!(records["Jessie"].name == "Jessie");
-        assert!(records["Jessie"].id == jessie.id);
+        let mut schema = prisma_parser::parse(r#"
+            model Sighting {
+              place String
 
-        assert!(records["Bo"].name == "Bo");
-        assert!(records["Bo"].id == bo.id);
+              @@ignore
+            }
+        "#).unwrap();
 
-        // Woody is in the secondary DB and therefore gets a new ID.
-        assert!(records["Woody"].name == "Woody");
-        assert!(records["Woody"].id != woody.id);
+        let sighting = schema.models.get_mut("Sighting").unwrap();
+        sighting.ignore_policy = IgnorePolicy::Dedup;
+        sighting.filter = Some("flaky() = 1".to_string());
+
+        let reports = crate::prismerge(&schema, &[first], &merged, &test_options()).unwrap();
+
+        assert!(reports[0].rows_read == vec![0]);
+        assert!(reports[0].rows_failed == 2);
+        assert!(reports[0].rows_inserted == 0);
+        assert!(reports[0].rows_deduplicated == 0);
     }
 
     #[test]
-    fn merges_tables_with_foreign_keys() {
-        let (first, second, merged) = create_connections();
+    fn applies_ignore_policy_to_ignored_columns() {
+        for (policy, expected_nickname, expected_conflicts) in [
+            (IgnorePolicy::Skip, None, 0),
+            (IgnorePolicy::RawCopy, Some("Dino".to_string()), 0),
+            (IgnorePolicy::Dedup, Some("Dino".to_string()), 1)
+        ] {
+            let first = create_connection();
+            let second = create_connection();
+            let merged = create_connection();
+
+            for conn in [&first, &second] {
+                conn.execute("CREATE TABLE \"Pet\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"name\" TEXT NOT NULL, \"nickname\" TEXT)", ()).unwrap();
+            }
 
-        let woody = Owner::create(&first, "Woody");
-        let jessie = Owner::create(&second, "Jessie");
-        let bo = Owner::create(&second, "Bo");
+            first.execute("INSERT INTO \"Pet\" VALUES ('a', 'Rex', 'T-Rex')", ()).unwrap();
+            second.execute("INSERT INTO \"Pet\" VALUES ('b', 'Rex', 'Dino')", ()).unwrap();
+            second.execute("INSERT INTO \"Pet\" VALUES ('c', 'Slinky', 'Dog')", ()).unwrap();
 
-        TodoList::create(&first, "Groceries", woody.id.as_str());
-        TodoList::create(&second, "Chores", jessie.id.as_str());
-        TodoList::create(&second, "Errands", bo.id.as_str());
+            let mut schema = prisma_parser::parse(r#"
+                model Pet {
+                  id       String  @id
+                  name     String  @unique
+                  nickname String? @ignore
+                }
+            "#).unwrap();
 
-        crate::prismerge(
-            &SCHEMA,
-            &vec![first, second],
-            &merged,
-            1,
-            false
-        );
+            schema.models.get_mut("Pet").unwrap().ignore_policy = policy;
 
-        let owners = Owner::all_by_name(&merged);
-        let todo_lists = TodoList::all_by_name(&merged);
+            // The second input has more rows, so it's the primary and its values win.
+            let reports = crate::prismerge(&schema, &[first, second], &merged, &test_options()).unwrap();
 
-        assert!(owners.len() == 3);
-        assert!(todo_lists.len() == 3);
+            let nickname: Option<String> = merged
+                .query_row("SELECT nickname FROM Pet WHERE name = 'Rex'", (), |row| row.get(0))
+                .unwrap();
 
-        let woodys_groceries = todo_lists.get("Groceries").unwrap();
-        assert!(woodys_groceries.name == "Groceries");
-        assert!(woodys_groceries.owner_id == owners.get("Woody").unwrap().id);
+            assert!(nickname == expected_nickname);
+            assert!(reports[0].rows_conflicted == expected_conflicts);
+        }
+    }
 
-        let jessies_chores = todo_lists.get("Chores").unwrap();
-        assert!(jessies_chores.name == "Chores");
-        assert!(jessies_chores.owner_id == owners.get("Jessie").unwrap().id);
+    #[test]
+    fn rejects_rows_that_fail_to_insert() {
+        for max_errors in [None, Some(0)] {
+            let first = create_connection();
+            let second = create_connection();
+            let merged = create_connection();
+
+            for conn in [&first, &second] {
+                conn.execute("CREATE TABLE \"Pet\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"name\" TEXT NOT NULL, \"nickname\" TEXT)", ()).unwrap();
+            }
 
-        let bos_errands = todo_lists.get("Errands").unwrap();
-        assert!(bos_errands.name == "Errands");
-        assert!(bos_errands.owner_id == owners.get("Bo").unwrap().id);
+            first.execute("INSERT INTO \"Pet\" VALUES ('a', 'Rex', 'Dino'), ('b', 'Slinky', NULL)", ()).unwrap();
+            second.execute("INSERT INTO \"Pet\" VALUES ('c', 'Hamm', 'Pig')", ()).unwrap();
+
+            let schema = prisma_parser::parse(r#"
+                model Pet {
+                  id       String  @id
+                  name     String  @unique
+                  nickname String?
+                }
+            "#).unwrap();
+
+            // The output is stricter than the inputs, so Slinky can't be inserted.
+            let options = crate::MergeOptions {
+                min_inserts: 100,
+                output_ddl: Some(vec!["CREATE TABLE \"Pet\" (\"id\" TEXT NOT NULL PRIMARY KEY, \"name\" TEXT NOT NULL, \"nickname\" TEXT NOT NULL)".to_string()]),
+                input_labels: vec!["first".to_string(), "second".to_string()],
+                max_errors,
+                ..test_options()
+            };
+
+            let result = crate::prismerge(&schema, &[first, second], &merged, &options);
+
+            let rejects: Vec<String> = merged
+                .prepare("SELECT input || ':' || model || ':' || old_id FROM _prismerge_rejects")
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(|reject| reject.unwrap())
+                .collect();
+
+            assert!(rejects == vec!["first:Pet:b"]);
+
+            match max_errors {
+                None => {
+                    let reports = result.unwrap();
+                    assert!(reports[0].rows_inserted == 2);
+                    assert!(reports[0].rows_failed == 1);
+
+                    let names: Vec<String> = merged
+                        .prepare("SELECT name FROM Pet ORDER BY name")
+                        .unwrap()
+                        .query_map((), |row| row.get(0))
+                        .unwrap()
+                        .map(|name| name.unwrap())
+                        .collect();
+
+                    assert!(names == vec!["Hamm", "Rex"]);
+                }
+
+                Some(_) => assert!(result.is_err())
+            }
+        }
     }
 
     #[test]
-    fn merges_duplicate_records() {
-        let (first, second, merged) = create_connections();
-        let first_woody = Owner::create(&first, "Woody");
-        let second_woody = Owner::create(&second, "Woody");
+    fn applies_orphan_policies() {
+        for policy in ["drop", "keep", "abort"] {
+            let (first, second, merged) = create_connections();
 
-        TodoList::create(&first, "Chores", first_woody.id.as_str());
-        TodoList::create(&second, "Errands", second_woody.id.as_str());
+            let woody = Owner::create(&first, "Woody");
+            TodoList::create(&first, "Chores", &woody.id);
 
-        crate::prismerge(
-            &SCHEMA,
-            &vec![first, second],
-            &merged,
-            1,
-            false
-        );
+            // The second input's list belongs to an owner that was deleted without
+            // cascading.
+            second.execute("PRAGMA foreign_keys = OFF", ()).unwrap();
+            TodoList::create(&second, "Errands", "missing");
 
-        let owners = Owner::all_by_name(&merged);
-        let todo_lists = TodoList::all_by_name(&merged);
+            let mut schema = test_schema();
+            crate::apply_orphan_policies(&mut schema, &[format!("TodoList.owner={}", policy)]).unwrap();
 
-        assert!(owners.len() == 1);
-        assert!(todo_lists.len() == 2);
+            let result = crate::prismerge(&schema, &[first, second], &merged, &test_options());
 
-        let merged_woody = owners.get("Woody").unwrap();
-        assert!([&first_woody.id, &second_woody.id].contains(&&merged_woody.id));
+            if policy == "abort" {
+                assert!(result.unwrap_err().contains("orphan policy is abort"));
+                continue;
+            }
 
-        for (_, todo_list) in todo_lists.iter() {
-            assert!(todo_list.owner_id == merged_woody.id);
+            let reports = result.unwrap();
+            let todo_list_report = reports.iter().find(|r| r.name == "TodoList").unwrap();
+            assert!(todo_list_report.rows_orphaned == 1);
+
+            let owner_ids: Vec<String> = merged
+                .prepare("SELECT ownerId FROM TodoList ORDER BY name")
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(|owner_id| owner_id.unwrap())
+                .collect();
+
+            match policy {
+                "drop" => {
+                    assert!(todo_list_report.rows_dropped == 1);
+                    assert!(owner_ids == vec![woody.id.clone()]);
+                }
+
+                _ => {
+                    assert!(todo_list_report.rows_dropped == 0);
+                    assert!(owner_ids == vec![woody.id.clone(), "missing".to_string()]);
+                }
+            }
+        }
+
+        let mut schema = test_schema();
+        assert!(crate::apply_orphan_policies(&mut schema, &["TodoList.owner=null".to_string()]).is_err());
+        assert!(crate::apply_orphan_policies(&mut schema, &["TodoList.name=drop".to_string()]).is_err());
+    }
+
+    #[test]
+    fn command_line_flags_override_config_booleans() {
+        let config = Config::parse("keep_id_maps = true\nrecord_conflicts = true\nmigrate = true").unwrap();
+
+        let mut options = crate::Cli::parse_from(["prismerge", "--no-keep-id-maps", "--no-record-conflicts", "a.db"]);
+        options.merge_config(&config);
+        assert!(!options.keep_id_maps);
+        assert!(!options.record_conflicts);
+        assert!(options.migrate);
+
+        let mut options = crate::Cli::parse_from(["prismerge", "--no-exclude-broken-inputs", "--exclude-broken-inputs", "a.db"]);
+        options.merge_config(&Config::default());
+        assert!(options.exclude_broken_inputs);
+    }
+
+    #[test]
+    fn opens_every_input_including_the_first() {
+        let options = crate::Cli::parse_from(["prismerge", "first.db", "second.db"]);
+        let inputs = options.inputs(&Config::default()).unwrap();
+        assert!(inputs.iter().map(|input| input.path.as_str()).collect::<Vec<_>>() == vec!["first.db", "second.db"]);
+
+        let dir = std::env::temp_dir().join(format!("prismerge-open-inputs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let paths: Vec<String> = ["first.db", "second.db"]
+            .iter()
+            .map(|name| dir.join(name).to_string_lossy().to_string())
+            .collect();
+
+        for (i, path) in paths.iter().enumerate() {
+            let conn = Connection::open(path).unwrap();
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS Input; CREATE TABLE Input (position INTEGER); INSERT INTO Input VALUES ({});", i)).unwrap();
         }
+
+        let connections = crate::open_inputs(&paths.iter().collect::<Vec<_>>());
+        let positions: Vec<i64> = connections
+            .iter()
+            .map(|conn| conn.query_row("SELECT position FROM Input", (), |row| row.get(0)).unwrap())
+            .collect();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(positions == vec![0, 1]);
     }
 }
@@ -0,0 +1,374 @@
+use glob::glob;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use crate::conflicts::{Aggregate, ConflictPolicy};
+use crate::data::{IgnorePolicy, OrphanPolicy, Rename, Schema};
+use crate::normalize::Normalizer;
+
+pub const DEFAULT_CONFIG_PATH: &str = "prismerge.toml";
+
+/* Project configuration, usually read from a prismerge.toml file. The top-level
+ * keys mirror the command-line options, and the [models.<Model>] tables hold the
+ * same per-model settings as the @prismerge directives. Settings are applied in
+ * order of increasing precedence: schema directives, then the config file, then
+ * the command line.
+ *
+ * Relative paths in the config file are resolved relative to the directory the
+ * file lives in, not the current working directory. For example:
+ *
+ *   schema_path = "prisma/schema.prisma"
+ *   output_path = "merged.db"
+ *   min_inserts = 5000
+ *   max_errors = 100
+ *   exclude = ["AuditLog"]
+ *   seed = ["Repository=name = 'prismerge'"]
+ *
+ *   [[inputs]]
+ *   path = "dbs/nightly-*.db"
+ *   label = "nightly"
+ *
+ *   [passthrough]
+ *   Setting = "primary"
+ *
+ *   [models.ComponentUsage]
+ *   filter = "version >= '35'"
+ *
+ *   [models.Annotation]
+ *   dedupe_by_hash = true
+ *
+ *   [models.Component]
+ *   dedupe_by = ["name", "ownerId"]
+ *   conflict = "newest:updatedAt"
+ *   ignore = "raw-copy"
+ *
+ *   [models.Component.columns.name]
+ *   normalize = ["trim", "lowercase"]
+ *   renamed_from = ["title"]
+ *
+ *   [models.Component.columns.owner]
+ *   orphans = "keep"
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub schema_path: Option<String>,
+    pub output_path: Option<String>,
+    pub keep_id_maps: Option<bool>,
+    pub min_inserts: Option<u64>,
+    pub report: Option<String>,
+    pub record_conflicts: Option<bool>,
+    pub output_schema: Option<String>,
+    pub migrations_path: Option<String>,
+    pub migrate: Option<bool>,
+    pub exclude_broken_inputs: Option<bool>,
+    pub max_errors: Option<u64>,
+    pub rejects: Option<String>,
+
+    // Merge only these models (and their required parents).
+    #[serde(default)]
+    pub only: Vec<String>,
+
+    // Skip these models (and their children).
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    // Extract the subset of rows related to these seed rows instead of merging
+    // everything, eg. "Repository=name = 'prismerge'".
+    #[serde(default)]
+    pub seed: Vec<String>,
+
+    #[serde(default)]
+    pub inputs: Vec<InputConfig>,
+
+    // How to copy models without a primary key and tables that aren't in the schema, by
+    // table name, eg. AuditLog = "union:dedup".
+    #[serde(default)]
+    pub passthrough: HashMap<String, String>,
+
+    #[serde(default)]
+    pub models: HashMap<String, ModelConfig>,
+
+    // The directory containing the config file.
+    #[serde(skip)]
+    pub base_dir: PathBuf
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputConfig {
+    // A path or glob pattern, eg. "dbs/*.db".
+    pub path: String,
+    pub label: Option<String>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConfig {
+    pub skip: Option<bool>,
+    pub dedupe_by: Option<Vec<String>>,
+    pub dedupe_by_hash: Option<bool>,
+    pub conflict: Option<String>,
+    pub filter: Option<String>,
+
+    // What to do with the model if it's marked @@ignore, and with its @ignore fields.
+    pub ignore: Option<String>,
+
+    #[serde(default)]
+    pub columns: HashMap<String, ColumnConfig>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnConfig {
+    pub conflict: Option<String>,
+    pub normalize: Option<Vec<String>>,
+
+    // What to do with rows whose foreign key can't be translated, for relation fields.
+    pub orphans: Option<String>,
+
+    // Names the column had in older versions of the schema, most recent first, each
+    // optionally followed by @MIGRATION to only use it for inputs that predate that
+    // migration.
+    pub renamed_from: Option<Vec<String>>
+}
+
+// An input database along with the label used to identify it in reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub path: String,
+    pub label: String
+}
+
+impl Input {
+    // Inputs given on the command line are labeled with their paths.
+    pub fn from_path(path: &str) -> Self {
+        Input { path: path.to_string(), label: path.to_string() }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read config file {}: {}", path, e))?;
+
+        let mut config = Self::parse(&contents)
+            .map_err(|e| format!("Unable to parse config file {}: {}", path, e))?;
+
+        config.base_dir = Path::new(path)
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_default();
+
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    // Load the config file at the given path, or prismerge.toml in the current
+    // directory if no path is given and that file exists.
+    pub fn discover(path: Option<&str>) -> Result<Option<Self>, String> {
+        match path {
+            Some(path) => Ok(Some(Self::load(path)?)),
+            None if Path::new(DEFAULT_CONFIG_PATH).is_file() => Ok(Some(Self::load(DEFAULT_CONFIG_PATH)?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn resolve_path(self: &Self, path: &str) -> String {
+        self.base_dir.join(path).to_string_lossy().to_string()
+    }
+
+    // Expand the input globs into a list of inputs. Matches for each glob are sorted
+    // so the order is stable from run to run. When a glob matches more than one file,
+    // each file's label is suffixed with its file name.
+    pub fn inputs(self: &Self) -> Result<Vec<Input>, String> {
+        let mut inputs = vec![];
+
+        for input in self.inputs.iter() {
+            let pattern = self.resolve_path(&input.path);
+
+            let mut paths = glob(&pattern)
+                .map_err(|e| format!("Invalid input pattern '{}': {}", input.path, e))?
+                .filter_map(|entry| entry.ok())
+                .collect::<Vec<PathBuf>>();
+
+            if paths.is_empty() {
+                return Err(format!("Input pattern '{}' did not match any files", input.path));
+            }
+
+            paths.sort();
+
+            let multiple = paths.len() > 1;
+
+            for path in paths {
+                let path_str = path.to_string_lossy().to_string();
+
+                let label = match (&input.label, multiple) {
+                    (Some(label), false) => label.clone(),
+                    (Some(label), true) => {
+                        format!("{}/{}", label, path.file_name().unwrap_or_default().to_string_lossy())
+                    }
+                    (None, _) => path_str.clone()
+                };
+
+                inputs.push(Input { path: path_str, label });
+            }
+        }
+
+        Ok(inputs)
+    }
+
+    // Apply the per-model settings to the models in the schema.
+    pub fn apply_to(self: &Self, schema: &mut Schema) -> Result<(), String> {
+        for (model_name, model_config) in self.models.iter() {
+            let model = schema.models
+                .get_mut(model_name)
+                .ok_or_else(|| format!("Unknown model '{}' in config file", model_name))?;
+
+            if let Some(skip) = model_config.skip {
+                model.skip = skip;
+            }
+
+            if let Some(dedupe_by) = &model_config.dedupe_by {
+                model.set_dedupe_by(dedupe_by.clone())?;
+            }
+
+            if let Some(dedupe_by_hash) = model_config.dedupe_by_hash {
+                model.set_dedupe_by_hash(dedupe_by_hash)?;
+            }
+
+            if let Some(conflict) = &model_config.conflict {
+                model.set_conflict_policy(conflict.parse::<ConflictPolicy>()?)?;
+            }
+
+            if let Some(filter) = &model_config.filter {
+                model.filter = Some(filter.clone());
+            }
+
+            if let Some(ignore) = &model_config.ignore {
+                model.ignore_policy = ignore.parse::<IgnorePolicy>()?;
+            }
+
+            for (column_name, column_config) in model_config.columns.iter() {
+                let column = model
+                    .get_col_mut(column_name)
+                    .ok_or_else(|| format!("Unknown column '{}.{}' in config file", model_name, column_name))?;
+
+                if let Some(conflict) = &column_config.conflict {
+                    column.aggregate = Some(conflict.parse::<Aggregate>()?);
+                }
+
+                if let Some(normalize) = &column_config.normalize {
+                    column.normalizers = normalize
+                        .iter()
+                        .map(|normalizer| normalizer.parse::<Normalizer>())
+                        .collect::<Result<Vec<Normalizer>, String>>()?;
+                }
+
+                if let Some(renamed_from) = &column_config.renamed_from {
+                    column.renamed_from = renamed_from
+                        .iter()
+                        .map(|old_name| old_name.parse::<Rename>())
+                        .collect::<Result<Vec<Rename>, String>>()?;
+                }
+
+                if let Some(orphans) = &column_config.orphans {
+                    column.set_orphan_policy(orphans.parse::<OrphanPolicy>()?)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+    use crate::prisma_parser;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id   String @id
+          name String @unique
+          age  Int
+        }
+
+        model Sticker {
+          id    String @id
+          label String
+        }
+    "#;
+
+    #[test]
+    fn applies_model_settings() {
+        let config = Config::parse(r#"
+            [models.Owner]
+            skip = true
+            dedupe_by = ["name", "age"]
+            conflict = "keep-last"
+            filter = "age > 3"
+            ignore = "raw-copy"
+
+            [models.Owner.columns.age]
+            conflict = "max"
+
+            [models.Owner.columns.name]
+            normalize = ["lowercase"]
+        "#).unwrap();
+
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        config.apply_to(&mut schema).unwrap();
+
+        let owner = &schema.models["Owner"];
+        assert!(owner.skip);
+        assert!(owner.unique.as_ref().unwrap().column_names == vec!["name", "age"]);
+        assert!(owner.conflict_policy == ConflictPolicy::KeepLast);
+        assert!(owner.filter.as_deref() == Some("age > 3"));
+        assert!(owner.ignore_policy == IgnorePolicy::RawCopy);
+        assert!(owner.get_col("age").unwrap().aggregate == Some(Aggregate::Max));
+        assert!(owner.get_col("name").unwrap().normalizers == vec![Normalizer::Lowercase]);
+    }
+
+    #[test]
+    fn only_dedupes_models_without_unique_keys_by_hash() {
+        let config = Config::parse("[models.Sticker]\ndedupe_by_hash = true").unwrap();
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        config.apply_to(&mut schema).unwrap();
+        assert!(schema.models["Sticker"].dedupe_by_hash);
+
+        let config = Config::parse("[models.Owner]\ndedupe_by_hash = true").unwrap();
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        assert!(config.apply_to(&mut schema).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_models_and_keys() {
+        let config = Config::parse("[models.Nope]\nskip = true").unwrap();
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        assert!(config.apply_to(&mut schema).is_err());
+
+        assert!(Config::parse("bogus = 1").is_err());
+    }
+
+    #[test]
+    fn parses_the_readme_example() {
+        let readme = include_str!("../README.md");
+        let (_, example) = readme.split_once("```toml\n").unwrap();
+        let (example, _) = example.split_once("```").unwrap();
+
+        let config = Config::parse(example).unwrap();
+        assert!(config.exclude == vec!["AuditLog"]);
+        assert!(config.inputs.len() == 1);
+        assert!(config.passthrough["AuditLog"] == "union:dedup");
+    }
+
+    #[test]
+    fn resolves_paths_relative_to_config_file() {
+        let config = Config { base_dir: PathBuf::from("project"), ..Default::default() };
+        assert!(config.resolve_path("schema.prisma") == Path::new("project").join("schema.prisma").to_string_lossy());
+    }
+}
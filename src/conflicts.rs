@@ -0,0 +1,241 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::data::Column;
+use crate::normalize::normalize_type;
+use crate::utils::quote_literal;
+
+/* When a row from a secondary database matches an existing row via the model's
+ * unique index, prismerge maps it to the existing row and throws the rest of its
+ * values away. The ConflictTable records the cases where those discarded values
+ * differ from the ones already in the merged database, one row per differing
+ * column. All values are stored as quoted SQL literals, exactly as they were
+ * compared.
+ */
+pub struct ConflictTable {
+    pub name: String
+}
+
+impl Default for ConflictTable {
+    fn default() -> Self {
+        ConflictTable { name: "_prismerge_conflicts".to_string() }
+    }
+}
+
+impl ConflictTable {
+    pub fn create_into(self: &Self, connection: &Connection) {
+        let create_conflict_table_sql = format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS "{table}" (
+                    model TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    merged_id TEXT NOT NULL,
+                    column_name TEXT NOT NULL,
+                    merged_value TEXT,
+                    input TEXT NOT NULL,
+                    input_value TEXT
+                )
+            "#,
+            table = self.name
+        );
+
+        connection.execute(create_conflict_table_sql.as_str(), ()).unwrap();
+    }
+
+    pub fn insert_sql(self: &Self, model_name: &str, conflict: &Conflict, input: &str) -> String {
+        format!(
+            "INSERT INTO \"{table}\" (model, key, merged_id, column_name, merged_value, input, input_value) VALUES ({model}, {key}, {merged_id}, {column}, {merged_value}, {input}, {input_value})",
+            table = self.name,
+            model = quote_literal(model_name),
+            key = quote_literal(&conflict.key),
+            merged_id = quote_literal(&conflict.merged_id),
+            column = quote_literal(&conflict.column_name),
+            merged_value = quote_literal(&conflict.merged_value),
+            input = quote_literal(input),
+            input_value = quote_literal(&conflict.input_value)
+        )
+    }
+}
+
+// A single column whose value differs between an incoming row and the existing
+// row it was deduplicated against.
+#[derive(Debug)]
+pub struct Conflict {
+    pub key: String,
+    pub merged_id: String,
+    pub column_name: String,
+    pub merged_value: String,
+    pub input_value: String
+}
+
+/* Determines what happens to an existing merged row when a row from a later input
+ * matches it via the model's unique index.
+ *
+ * KeepFirst:   the existing row is left alone (the default).
+ * KeepLast:    the existing row takes on the values of the row from the input that
+ *              comes last in input order.
+ * Newest(col): the existing row takes on the incoming row's values if the incoming
+ *              row's timestamp column is greater than the existing row's.
+ * Coalesce:    NULL columns in the existing row are filled in from the incoming row.
+ *
+ * Independently of the policy, individual numeric columns can be aggregated; see
+ * the Aggregate enum below.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    #[default]
+    KeepFirst,
+    KeepLast,
+    Newest(String),
+    Coalesce
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("newest", column)) => Ok(ConflictPolicy::Newest(column.trim().to_string())),
+            _ => match s {
+                "keep-first" => Ok(ConflictPolicy::KeepFirst),
+                "keep-last" => Ok(ConflictPolicy::KeepLast),
+                "coalesce" => Ok(ConflictPolicy::Coalesce),
+                _ => Err(format!("Unknown conflict policy '{}', expected one of keep-first, keep-last, newest:<column>, or coalesce", s))
+            }
+        }
+    }
+}
+
+// Combines the existing and incoming values of a numeric column.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    Max,
+    Min,
+    Sum
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(Aggregate::Max),
+            "min" => Ok(Aggregate::Min),
+            "sum" => Ok(Aggregate::Sum),
+            _ => Err(format!("Unknown aggregate '{}', expected one of max, min, or sum", s))
+        }
+    }
+}
+
+// Build an UPDATE statement that applies the given policy and any column aggregates to
+// the existing row identified by `merged_id`. The `incoming` argument pairs each of the
+// columns that may be updated with the incoming row's quoted value. Returns None if
+// there is nothing to update.
+//
+// Note that SQLite evaluates every expression in an UPDATE against the row's original
+// values, so the newest policy's timestamp comparison is unaffected by the timestamp
+// column itself being updated. Timestamps are compared in their canonical form, since
+// inputs may store the same DateTime as milliseconds or as an ISO 8601 string.
+pub fn update_sql(table: &str, primary_key: &str, merged_id: &str, policy: &ConflictPolicy, incoming: &[(&Column, String)]) -> Option<String> {
+    let newer = if let ConflictPolicy::Newest(ts_column) = policy {
+        let (ts_col, ts_value) = incoming.iter().find(|(col, _)| &col.name == ts_column)?;
+        let existing_ts = normalize_type(&ts_col.ty.name, &format!("\"{}\"", ts_column));
+        let incoming_ts = normalize_type(&ts_col.ty.name, ts_value);
+        Some(format!("({ts_value} IS NOT NULL AND (\"{ts_column}\" IS NULL OR {existing_ts} < {incoming_ts}))"))
+    } else {
+        None
+    };
+
+    let mut assignments: Vec<String> = vec![];
+
+    for (col, value) in incoming.iter() {
+        let name = &col.name;
+
+        let expr = match (col.aggregate, policy) {
+            (Some(Aggregate::Max), _) => format!("CASE WHEN \"{name}\" IS NULL OR {value} > \"{name}\" THEN {value} ELSE \"{name}\" END"),
+            (Some(Aggregate::Min), _) => format!("CASE WHEN \"{name}\" IS NULL OR {value} < \"{name}\" THEN {value} ELSE \"{name}\" END"),
+            (Some(Aggregate::Sum), _) => format!("CASE WHEN {value} IS NULL THEN \"{name}\" WHEN \"{name}\" IS NULL THEN {value} ELSE \"{name}\" + {value} END"),
+            (None, ConflictPolicy::KeepFirst) => continue,
+            (None, ConflictPolicy::KeepLast) => value.clone(),
+            (None, ConflictPolicy::Newest(_)) => format!("CASE WHEN {} THEN {value} ELSE \"{name}\" END", newer.as_ref().unwrap()),
+            (None, ConflictPolicy::Coalesce) => format!("COALESCE(\"{name}\", {value})")
+        };
+
+        assignments.push(format!("\"{name}\" = {expr}"));
+    }
+
+    if assignments.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "UPDATE \"{table}\" SET {assignments} WHERE \"{primary_key}\" = {merged_id}",
+        assignments = assignments.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conflicts::*;
+    use crate::data::ColumnType;
+
+    fn column(name: &str) -> Column {
+        Column { name: name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert!("keep-last".parse::<ConflictPolicy>() == Ok(ConflictPolicy::KeepLast));
+        assert!("newest:updatedAt".parse::<ConflictPolicy>() == Ok(ConflictPolicy::Newest("updatedAt".to_string())));
+        assert!("sum".parse::<Aggregate>() == Ok(Aggregate::Sum));
+        assert!("newest".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn keep_first_without_aggregates_does_not_update() {
+        let color = column("color");
+        let incoming = vec![(&color, "'green'".to_string())];
+        assert!(update_sql("Toy", "id", "'abc'", &ConflictPolicy::KeepFirst, &incoming).is_none());
+    }
+
+    #[test]
+    fn coalesce_fills_nulls() {
+        let color = column("color");
+        let incoming = vec![(&color, "'green'".to_string())];
+        let sql = update_sql("Toy", "id", "'abc'", &ConflictPolicy::Coalesce, &incoming).unwrap();
+        assert!(sql == "UPDATE \"Toy\" SET \"color\" = COALESCE(\"color\", 'green') WHERE \"id\" = 'abc'");
+    }
+
+    #[test]
+    fn newest_requires_timestamp_column() {
+        let color = column("color");
+        let incoming = vec![(&color, "'green'".to_string())];
+        let policy = ConflictPolicy::Newest("updatedAt".to_string());
+        assert!(update_sql("Toy", "id", "'abc'", &policy, &incoming).is_none());
+    }
+
+    #[test]
+    fn newest_compares_timestamps_across_representations() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE Toy (id TEXT PRIMARY KEY, color TEXT, updatedAt DATETIME)").unwrap();
+
+        let color = column("color");
+        let updated_at = Column { ty: ColumnType { name: "DateTime".to_string(), ..Default::default() }, ..column("updatedAt") };
+        let policy = ConflictPolicy::Newest("updatedAt".to_string());
+
+        // The existing row is stored as milliseconds, the incoming ones as ISO 8601.
+        for (incoming_ts, expected_color) in [("'2024-01-01T00:00:00Z'", "red"), ("'2024-01-03T00:00:00Z'", "green")] {
+            conn.execute_batch("DELETE FROM Toy; INSERT INTO Toy VALUES ('abc', 'red', 1704164645678)").unwrap();
+
+            let incoming = vec![(&color, "'green'".to_string()), (&updated_at, incoming_ts.to_string())];
+            let sql = update_sql("Toy", "id", "'abc'", &policy, &incoming).unwrap();
+            conn.execute_batch(&sql).unwrap();
+
+            let color: String = conn.query_row("SELECT color FROM Toy", (), |row| row.get(0)).unwrap();
+            assert!(color == expected_color);
+        }
+    }
+}
@@ -1,28 +1,152 @@
 use rusqlite::Connection;
+use serde::{Serialize, Serializer};
 use tap::prelude::*;
+
+use crate::conflicts::{Aggregate, ConflictPolicy};
+use crate::normalize::Normalizer;
+use crate::verify::{self, ForeignKeyViolation};
 use topological_sort::TopologicalSort;
-use std::{collections::HashMap, hash::Hash};
+use std::{collections::{HashMap, HashSet}, fmt, hash::Hash, str::FromStr};
 
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize)]
 pub struct Relation {
     pub fields: Vec<String>,
-    pub references: Vec<String>
+    pub references: Vec<String>,
+
+    // What to do with rows whose foreign key doesn't match any merged parent row. If
+    // None, optional relations are nulled out and rows with required ones are dropped.
+    pub orphans: Option<OrphanPolicy>
 }
 
-#[derive(Debug)]
+/* Determines what happens to a row whose foreign key can't be translated because
+ * there's no parent row with the old key, eg. because the parent was deleted without
+ * cascading, or filtered out.
+ *
+ * Drop:  the row is left out of the merged database (the default for required
+ *        relations).
+ * Null:  the foreign key is set to NULL (the default for optional relations).
+ * Keep:  the foreign key keeps its original, untranslated value.
+ * Abort: the merge is aborted.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanPolicy {
+    Drop,
+    Null,
+    Keep,
+    Abort
+}
+
+impl FromStr for OrphanPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(OrphanPolicy::Drop),
+            "null" => Ok(OrphanPolicy::Null),
+            "keep" => Ok(OrphanPolicy::Keep),
+            "abort" => Ok(OrphanPolicy::Abort),
+            _ => Err(format!("Unknown orphan policy '{}', expected one of drop, null, keep, or abort", s))
+        }
+    }
+}
+
+impl fmt::Display for OrphanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OrphanPolicy::Drop => "drop",
+            OrphanPolicy::Null => "null",
+            OrphanPolicy::Keep => "keep",
+            OrphanPolicy::Abort => "abort"
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct ColumnType {
     pub name: String,
     pub collection: bool,
     pub nullable: bool
 }
 
-#[derive(Debug)]
+impl ColumnType {
+    pub fn is_numeric(self: &Self) -> bool {
+        matches!(self.name.as_str(), "Int" | "BigInt" | "Float" | "Decimal")
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct Column {
     pub name: String,
     pub ty: ColumnType,
     pub relation: Option<Relation>,
     pub unique: bool,
-    pub primary_key: bool
+    pub primary_key: bool,
+
+    // How to combine this column's values when duplicate rows are merged, if at all.
+    pub aggregate: Option<Aggregate>,
+
+    // Applied to this column's values when checking for duplicate rows.
+    pub normalizers: Vec<Normalizer>,
+
+    // The column's @default value as an SQL literal, if it's a constant.
+    pub default: Option<String>,
+
+    // The name of the function that generates the column's default value, eg. uuid or
+    // now, if any.
+    pub default_function: Option<String>,
+
+    // Whether the column is marked @updatedAt.
+    pub updated_at: bool,
+
+    // Whether the column is marked @ignore. What happens to its values is up to the
+    // model's ignore policy.
+    pub ignored: bool,
+
+    // Names this column had in older versions of the schema, most recent first.
+    pub renamed_from: Vec<Rename>
+}
+
+/* A name a column had in an older version of the schema, written as OLD_NAME or
+ * OLD_NAME@MIGRATION. With a migration, the old name only applies to inputs that
+ * haven't applied that migration yet, i.e. inputs whose schema version predates the
+ * rename. Without one, or for inputs without a migrations table, it applies to any
+ * input whose table has a column with the old name.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rename {
+    pub from: String,
+    pub until: Option<String>
+}
+
+impl FromStr for Rename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, until) = match s.split_once('@') {
+            Some((from, until)) => (from, Some(until.trim().to_string())),
+            None => (s, None)
+        };
+
+        if from.trim().is_empty() || until.as_deref() == Some("") {
+            return Err(format!("Invalid rename '{}', expected OLD_NAME or OLD_NAME@MIGRATION", s));
+        }
+
+        Ok(Rename { from: from.trim().to_string(), until })
+    }
+}
+
+impl Rename {
+    // Whether the old name applies to an input that has applied the given migrations.
+    // None means the input's migrations aren't known.
+    pub fn applies_to(self: &Self, applied_migrations: Option<&HashSet<String>>) -> bool {
+        match (&self.until, applied_migrations) {
+            (Some(until), Some(applied_migrations)) => !applied_migrations.contains(until),
+            _ => true
+        }
+    }
 }
 
 impl Column {
@@ -30,6 +154,28 @@ impl Column {
         self.relation.is_some()
     }
 
+    // The orphan policy of this relation field.
+    pub fn orphan_policy(self: &Self) -> OrphanPolicy {
+        match self.relation.as_ref().and_then(|relation| relation.orphans) {
+            Some(policy) => policy,
+            None if self.ty.nullable => OrphanPolicy::Null,
+            None => OrphanPolicy::Drop
+        }
+    }
+
+    pub fn set_orphan_policy(self: &mut Self, policy: OrphanPolicy) -> Result<(), String> {
+        if policy == OrphanPolicy::Null && !self.ty.nullable {
+            return Err(format!("Relation {} is required, so its orphans can't be nulled out", self.name));
+        }
+
+        match self.relation.as_mut() {
+            Some(relation) => relation.orphans = Some(policy),
+            None => return Err(format!("Column {} isn't a relation, so it can't have an orphan policy", self.name))
+        }
+
+        Ok(())
+    }
+
     pub fn get_related_column<'a>(self: &Self, model: &'a Model) -> Option<&'a Column> {
         for column in model.columns.iter() {
             if let Some(relation) = &column.relation {
@@ -46,6 +192,31 @@ impl Column {
         format!("quote(\"{}\".\"{}\")", model_name, self.name)
     }
 
+    // Like quoted(), but tolerates inputs created with older versions of the schema.
+    // If the column doesn't exist in the input, its value is read from the first of
+    // its old names that does and applies to the input's schema version, or failing
+    // that, replaced by its default value or NULL.
+    pub fn quoted_from(self: &Self, model_name: &String, existing_columns: &HashSet<String>, applied_migrations: Option<&HashSet<String>>) -> String {
+        if existing_columns.contains(&self.name) {
+            return self.quoted(model_name);
+        }
+
+        if let Some(old_name) = self.old_name_in(existing_columns, applied_migrations) {
+            return format!("quote(\"{}\".\"{}\")", model_name, old_name);
+        }
+
+        format!("quote({})", self.default.as_deref().unwrap_or("NULL"))
+    }
+
+    // The first of the column's old names that exists in an input and applies to its
+    // schema version, if any.
+    pub fn old_name_in(self: &Self, existing_columns: &HashSet<String>, applied_migrations: Option<&HashSet<String>>) -> Option<&str> {
+        self.renamed_from
+            .iter()
+            .find(|rename| rename.applies_to(applied_migrations) && existing_columns.contains(&rename.from))
+            .map(|rename| rename.from.as_str())
+    }
+
     pub fn is_regular(self: &Self, schema: &Schema) -> bool {
         !self.primary_key &&
             !self.ty.collection &&
@@ -54,18 +225,146 @@ impl Column {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Unique {
     pub column_names: Vec<String>
 }
 
-#[derive(Debug)]
+// An index declared in the Prisma schema via @unique, @@unique, or @@index.
+#[derive(Debug, Serialize)]
+pub struct Index {
+    pub column_names: Vec<String>,
+    pub unique: bool
+}
+
+impl Index {
+    // The name Prisma gives the index, eg. Toy_name_ownerId_key.
+    pub fn name(self: &Self, model_name: &str) -> String {
+        let suffix = if self.unique { "key" } else { "idx" };
+        format!("{}_{}_{}", model_name, self.column_names.join("_"), suffix)
+    }
+}
+
+/* Determines what happens to models marked @@ignore, and to the columns of any model
+ * marked @ignore. Prisma's client doesn't touch either, so they're often missing the
+ * primary and unique keys prismerge relies on.
+ *
+ * Skip:    ignored models aren't merged, i.e. their tables are left empty, and
+ *          ignored columns are left NULL or set to their default values (the
+ *          default).
+ * RawCopy: ignored models' rows are copied as-is from every input, keeping their
+ *          primary keys, and ignored columns are copied but not used to detect
+ *          conflicts.
+ * Dedup:   like raw-copy, except rows of ignored models that are identical to a
+ *          row that's already been copied are dropped, and ignored columns are
+ *          treated like any other column.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IgnorePolicy {
+    #[default]
+    Skip,
+    RawCopy,
+    Dedup
+}
+
+impl FromStr for IgnorePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(IgnorePolicy::Skip),
+            "raw-copy" => Ok(IgnorePolicy::RawCopy),
+            "dedup" => Ok(IgnorePolicy::Dedup),
+            _ => Err(format!("Unknown ignore policy '{}', expected one of skip, raw-copy, or dedup", s))
+        }
+    }
+}
+
+/* Determines how the rows of tables prismerge can't merge are copied, i.e. tables of
+ * models without a primary key and tables that aren't in the schema at all. Rows are
+ * copied as-is, except that foreign keys declared in the schema are translated.
+ *
+ * Skip:    the table is left empty (the default).
+ * Primary: rows are copied from the input with the most rows in the table only.
+ * Union:   rows are copied from every input.
+ *
+ * With dedup, rows identical to a row that's already been copied are dropped. Policies
+ * are written as eg. "union" or "union:dedup".
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PassthroughPolicy {
+    #[default]
+    Skip,
+    Primary,
+    Union
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Passthrough {
+    pub policy: PassthroughPolicy,
+    pub dedup: bool
+}
+
+impl FromStr for Passthrough {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (policy, dedup) = match s.split_once(':') {
+            Some((policy, "dedup")) => (policy, true),
+            Some(_) => return Err(format!("Invalid passthrough policy '{}', expected POLICY or POLICY:dedup", s)),
+            None => (s, false)
+        };
+
+        let policy = match policy {
+            "skip" => PassthroughPolicy::Skip,
+            "primary" => PassthroughPolicy::Primary,
+            "union" => PassthroughPolicy::Union,
+            _ => return Err(format!("Unknown passthrough policy '{}', expected one of skip, primary, or union", policy))
+        };
+
+        Ok(Passthrough { policy, dedup })
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct Model {
     pub name: String,
     pub columns: Vec<Column>,
     pub unique: Option<Unique>,
+
+    // The indices declared in the schema. Unlike `unique`, these are not affected by
+    // dedupe settings.
+    pub indices: Vec<Index>,
+
+    #[serde(skip)]
     pub map_table: MapTable,
-    pub primary_key_index: Option<usize>
+
+    #[serde(skip)]
+    pub primary_key_index: Option<usize>,
+
+    // What to do with the existing row when a duplicate is encountered.
+    pub conflict_policy: ConflictPolicy,
+
+    // Skipped models are not merged, i.e. their tables are left empty.
+    pub skip: bool,
+
+    // Whether to detect duplicate rows by hashing their contents if the model has no
+    // unique key.
+    pub dedupe_by_hash: bool,
+
+    // An SQL predicate rows must satisfy in order to be merged, eg. "version >= '35'".
+    pub filter: Option<String>,
+
+    // Whether the model is marked @@ignore.
+    pub ignored: bool,
+
+    // What to do with the model if it's ignored, and with its ignored columns.
+    pub ignore_policy: IgnorePolicy,
+
+    // How to copy the model's rows if it has no primary key.
+    pub passthrough: Passthrough
 }
 
 impl Model {
@@ -82,8 +381,16 @@ impl Model {
             name: name.clone(),
             columns,
             unique,
+            indices: vec![],
             map_table: MapTable::new(name),
-            primary_key_index: primary_key_index
+            primary_key_index,
+            conflict_policy: ConflictPolicy::default(),
+            skip: false,
+            dedupe_by_hash: false,
+            filter: None,
+            ignored: false,
+            ignore_policy: IgnorePolicy::default(),
+            passthrough: Passthrough::default()
         }
     }
 
@@ -95,33 +402,173 @@ impl Model {
         None
     }
 
+    // How the model's rows are copied if they can't be merged, i.e. if the model is
+    // ignored or has no primary key. Returns None for models that are merged.
+    pub fn copy_mode(self: &Self) -> Option<Passthrough> {
+        if self.ignored {
+            return Some(match self.ignore_policy {
+                IgnorePolicy::Skip => Passthrough { policy: PassthroughPolicy::Skip, dedup: false },
+                IgnorePolicy::RawCopy => Passthrough { policy: PassthroughPolicy::Union, dedup: false },
+                IgnorePolicy::Dedup => Passthrough { policy: PassthroughPolicy::Union, dedup: true }
+            });
+        }
+
+        if self.primary_key().is_none() {
+            return Some(self.passthrough);
+        }
+
+        None
+    }
+
     // Return the column with the given name.
     pub fn get_col(self: &Self, name: &str) -> Option<&Column> {
-        for column in self.columns.iter() {
-            if column.name == name {
-                return Some(column);
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    // Whether the given column's values are copied into the merged database.
+    pub fn copies(self: &Self, column: &Column) -> bool {
+        !column.ignored || self.ignore_policy != IgnorePolicy::Skip
+    }
+
+    // Whether the given column's values are compared to detect conflicts between
+    // duplicate rows.
+    pub fn compares(self: &Self, column: &Column) -> bool {
+        !column.ignored || self.ignore_policy == IgnorePolicy::Dedup
+    }
+
+    // Return the column with the given name for modification.
+    pub fn get_col_mut(self: &mut Self, name: &str) -> Option<&mut Column> {
+        self.columns.iter_mut().find(|column| column.name == name)
+    }
+
+    // Use the given columns to detect duplicate rows instead of the unique index
+    // defined in the schema.
+    pub fn set_dedupe_by(self: &mut Self, column_names: Vec<String>) -> Result<(), String> {
+        for column_name in column_names.iter() {
+            if self.get_col(column_name).is_none() {
+                return Err(format!("Unknown column '{}' in dedupe columns for model {}", column_name, self.name));
             }
         }
 
-        None
+        self.unique = Some(Unique { column_names });
+        Ok(())
     }
 
-    // Check that all foreign keys point to existing records. Returns the count of
-    // rows that have bad/missing foreign keys.
-    pub fn verify_integrity(self: &Self, conn: &Connection) -> Result<(), usize> {
-        let mut result: Result<(), usize> = Ok(());
+    pub fn set_dedupe_by_hash(self: &mut Self, dedupe_by_hash: bool) -> Result<(), String> {
+        self.dedupe_by_hash = dedupe_by_hash;
+        self.check_dedupe_by_hash()
+    }
 
-        conn.query_row(format!("SELECT COUNT(*) FROM pragma_foreign_key_check('{}');", self.name).as_str(), (), |row| {
-            let count = row.get::<_, usize>(0).unwrap();
+    // Models with a unique key are deduplicated by their key, so hashing would never
+    // kick in. Checked again once all sources of configuration have been combined, in
+    // case a unique key is set after dedupe-by-hash.
+    pub fn check_dedupe_by_hash(self: &Self) -> Result<(), String> {
+        if self.dedupe_by_hash && self.unique.is_some() {
+            return Err(format!("Model {} has a unique key, so it can't be deduplicated by hash", self.name));
+        }
 
-            if count > 0 {
-                result = Err(count);
+        Ok(())
+    }
+
+    pub fn set_conflict_policy(self: &mut Self, policy: ConflictPolicy) -> Result<(), String> {
+        if let ConflictPolicy::Newest(ts_column) = &policy {
+            if self.get_col(ts_column).is_none() {
+                return Err(format!("Unknown timestamp column '{}' in conflict policy for model {}", ts_column, self.name));
             }
+        }
 
-            Ok(())
-        }).unwrap();
+        self.conflict_policy = policy;
+        Ok(())
+    }
+
+    // Check that the conflict policy and column aggregates can be applied once all the
+    // sources of configuration have been combined. Rows are only duplicates if their
+    // unique keys match, so there's nothing to combine for key columns, and foreign keys
+    // are translated rather than merged.
+    pub fn check_conflict_policy(self: &Self) -> Result<(), String> {
+        let key: &[String] = self.unique
+            .as_ref()
+            .map(|unique| unique.column_names.as_slice())
+            .unwrap_or(&[]);
+
+        if let ConflictPolicy::Newest(ts_column) = &self.conflict_policy {
+            if key.contains(ts_column) {
+                return Err(format!("Timestamp column '{}' in the conflict policy for model {} is part of its unique key", ts_column, self.name));
+            }
+        }
+
+        for column in self.columns.iter().filter(|column| column.aggregate.is_some()) {
+            if !column.ty.is_numeric() {
+                return Err(format!("Column {}.{} has type {}, but only numeric columns can be aggregated", self.name, column.name, column.ty.name));
+            }
+
+            if column.primary_key || key.contains(&column.name) {
+                return Err(format!("Column {}.{} is part of the unique key of model {}, so it can't be aggregated", self.name, column.name, self.name));
+            }
+
+            if column.get_related_column(self).is_some() {
+                return Err(format!("Column {}.{} is a foreign key, so it can't be aggregated", self.name, column.name));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Models with a primary key are merged, so only keyless models can be copied via a
+    // passthrough policy.
+    pub fn set_passthrough(self: &mut Self, passthrough: Passthrough) -> Result<(), String> {
+        if self.primary_key().is_some() {
+            return Err(format!("Model {} has a primary key, so it's merged rather than copied via a passthrough policy", self.name));
+        }
+
+        self.passthrough = passthrough;
+        Ok(())
+    }
+
+    // Restrict merging to rows that satisfy the given SQL predicate in addition to any
+    // existing filter.
+    pub fn add_filter(self: &mut Self, predicate: &str) {
+        self.filter = Some(match self.filter.take() {
+            Some(existing) => format!("({}) AND ({})", existing, predicate),
+            None => predicate.to_string()
+        });
+    }
 
-        result
+    // Return the names of the columns that exist in the model's table in the given
+    // database, which may differ from the schema if the database is out of date.
+    pub fn existing_columns(self: &Self, conn: &Connection) -> HashSet<String> {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)").unwrap();
+
+        stmt
+            .query_map([&self.name], |row| row.get::<_, String>(0))
+            .unwrap()
+            .filter_map(|name| name.ok())
+            .collect()
+    }
+
+    // Record that the given column used to be called `old_name`, optionally only up
+    // to a given migration, eg. title@20240301000000_rename_title.
+    pub fn add_rename(self: &mut Self, column_name: &str, old_name: &str) -> Result<(), String> {
+        let model_name = self.name.clone();
+
+        let column = self
+            .get_col_mut(column_name)
+            .ok_or_else(|| format!("Unknown column '{}' in renames for model {}", column_name, model_name))?;
+
+        column.renamed_from.push(old_name.parse::<Rename>()?);
+        Ok(())
+    }
+
+    // Check that all foreign keys point to existing records. Returns the rows that have
+    // bad/missing foreign keys.
+    pub fn verify_integrity(self: &Self, conn: &Connection) -> Result<(), Vec<ForeignKeyViolation>> {
+        let violations = verify::foreign_key_violations(conn, Some(&self.name)).unwrap();
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 }
 
@@ -191,14 +638,271 @@ impl MapTable {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize)]
+pub struct Enum {
+    pub name: String,
+
+    // The values as they're stored in the database, i.e. after applying any @map.
+    pub values: Vec<String>
+}
+
+// A datasource url, either given directly or read from an environment variable.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Url {
+    Literal(String),
+    Env(String)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Datasource {
+    pub name: String,
+    pub provider: String,
+    pub url: Option<Url>
+}
+
+impl Datasource {
+    // The path of the SQLite database file the url points to, if any, eg. "./dev.db"
+    // for "file:./dev.db". Like Prisma, relative paths are relative to the schema file.
+    pub fn file_path(self: &Self) -> Option<String> {
+        let url = match self.url.as_ref()? {
+            Url::Literal(url) => url.clone(),
+            Url::Env(name) => std::env::var(name).ok()?
+        };
+
+        let path = url.strip_prefix("file:")?;
+
+        // Drop connection parameters, eg. file:./dev.db?connection_limit=1.
+        Some(path.split('?').next().unwrap_or(path).to_string())
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Generator {
+    pub name: String,
+    pub provider: Option<String>
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct Schema {
-    pub models: HashMap<String, Model>
+    #[serde(serialize_with = "serialize_sorted")]
+    pub models: HashMap<String, Model>,
+
+    #[serde(serialize_with = "serialize_sorted")]
+    pub enums: HashMap<String, Enum>,
+
+    // Views and composite types are declared like models, but aren't merged.
+    #[serde(serialize_with = "serialize_sorted")]
+    pub views: HashMap<String, Model>,
+
+    #[serde(serialize_with = "serialize_sorted")]
+    pub types: HashMap<String, Model>,
+
+    pub datasource: Option<Datasource>,
+    pub generators: Vec<Generator>
+}
+
+// Serialize maps in key order so the output is stable.
+fn serialize_sorted<S: Serializer, T: Serialize>(map: &HashMap<String, T>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut entries: Vec<(&String, &T)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    serializer.collect_seq(entries.into_iter().map(|(_, value)| value))
 }
 
 impl Schema {
     pub fn new() -> Self {
-        Schema { models: HashMap::new() }
+        Schema::default()
+    }
+
+    // Whether a model, enum, view, or composite type with the given name exists.
+    pub fn is_declared(self: &Self, name: &str) -> bool {
+        self.models.contains_key(name) ||
+            self.enums.contains_key(name) ||
+            self.views.contains_key(name) ||
+            self.types.contains_key(name)
+    }
+
+    // Return the names of the models the given model references via required (i.e.
+    // non-nullable) relations. Rows in these models must exist for the given model's
+    // rows to be inserted.
+    pub fn required_parents(self: &Self, model: &Model) -> Vec<String> {
+        model.columns
+            .iter()
+            .filter(|column| column.has_relation() && !column.ty.nullable && self.models.contains_key(&column.ty.name))
+            .map(|column| column.ty.name.clone())
+            .collect()
+    }
+
+    // Restrict merging to a subset of models. If `only` is non-empty, all models not
+    // listed are skipped, except for the required parents of the listed models. Models
+    // listed in `exclude` are skipped, as are models that can't be merged and aren't
+    // copied either, eg. ignored models whose ignore policy is skip. Finally, skipping
+    // cascades to the children of skipped models so no foreign keys are left dangling.
+    // Returns the names of the models that were skipped as a result of the cascade,
+    // along with the name of the skipped parent responsible.
+    pub fn select(self: &mut Self, only: &[String], exclude: &[String]) -> Result<Vec<(String, String)>, String> {
+        for name in only.iter().chain(exclude.iter()) {
+            if !self.models.contains_key(name) {
+                return Err(format!("Unknown model '{}'", name));
+            }
+        }
+
+        if !only.is_empty() {
+            let mut included: HashSet<String> = HashSet::new();
+            let mut pending: Vec<String> = only.to_vec();
+
+            while let Some(name) = pending.pop() {
+                if included.insert(name.clone()) {
+                    pending.append(&mut self.required_parents(&self.models[&name]));
+                }
+            }
+
+            for model in self.models.values_mut() {
+                if !included.contains(&model.name) {
+                    model.skip = true;
+                }
+            }
+        }
+
+        for name in exclude.iter() {
+            self.models.get_mut(name).unwrap().skip = true;
+        }
+
+        for model in self.models.values_mut() {
+            if model.copy_mode().is_some_and(|mode| mode.policy == PassthroughPolicy::Skip) {
+                model.skip = true;
+            }
+        }
+
+        Ok(self.cascade_skips())
+    }
+
+    fn cascade_skips(self: &mut Self) -> Vec<(String, String)> {
+        let mut cascaded: Vec<(String, String)> = vec![];
+
+        // Visiting models in topological order means parents are always skipped
+        // before their children are checked.
+        let order: Vec<String> = self.sorted().iter().map(|model| model.name.clone()).collect();
+
+        for name in order {
+            if self.models[&name].skip {
+                continue;
+            }
+
+            let skipped_parent = self.required_parents(&self.models[&name])
+                .into_iter()
+                .find(|parent| self.models[parent].skip);
+
+            if let Some(parent) = skipped_parent {
+                self.models.get_mut(&name).unwrap().skip = true;
+                cascaded.push((name, parent));
+            }
+        }
+
+        cascaded
+    }
+
+    // Extend row filters to the children of filtered models, so rows whose required
+    // relations point at filtered-out parent rows are filtered out too rather than
+    // orphaned. Like skipping, filters don't cascade over optional relations; see
+    // orphaned_by_filters(). Returns the names of the models whose filters were
+    // extended, along with the name of the filtered parent responsible.
+    pub fn cascade_filters(self: &mut Self) -> Vec<(String, String)> {
+        let mut cascaded: Vec<(String, String)> = vec![];
+
+        // Visiting models in topological order means a parent's filter already
+        // includes the filters cascaded from its own parents.
+        let order: Vec<String> = self.sorted().iter().map(|model| model.name.clone()).collect();
+
+        for name in order {
+            let model = &self.models[&name];
+
+            if model.skip {
+                continue;
+            }
+
+            let mut predicates: Vec<(String, String)> = vec![];
+
+            for column in model.columns.iter().filter(|column| !column.ty.nullable) {
+                let (relation, parent) = match (&column.relation, self.models.get(&column.ty.name)) {
+                    (Some(relation), Some(parent)) if parent.name != name && !parent.skip => (relation, parent),
+                    _ => continue
+                };
+
+                if let Some(parent_filter) = &parent.filter {
+                    let quote_all = |table: &str, columns: &[String]| {
+                        columns
+                            .iter()
+                            .map(|column| format!("\"{}\".\"{}\"", table, column))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    };
+
+                    predicates.push((
+                        format!(
+                            "({fields}) IN (SELECT {references} FROM \"{parent}\" WHERE {filter})",
+                            fields = quote_all(&name, &relation.fields),
+                            references = quote_all(&parent.name, &relation.references),
+                            parent = parent.name,
+                            filter = parent_filter
+                        ),
+                        parent.name.clone()
+                    ));
+                }
+            }
+
+            let model = self.models.get_mut(&name).unwrap();
+
+            for (predicate, parent_name) in predicates {
+                model.add_filter(&predicate);
+                cascaded.push((name.clone(), parent_name));
+            }
+        }
+
+        cascaded
+    }
+
+    // Return the optional relations that point at filtered models, as (model, relation
+    // field, parent) tuples. Rows whose parents are filtered out are left to the
+    // relation's orphan policy.
+    pub fn orphaned_by_filters(self: &Self) -> Vec<(String, String, String)> {
+        let mut orphaned: Vec<(String, String, String)> = vec![];
+
+        for model in self.sorted() {
+            for column in model.columns.iter().filter(|column| column.has_relation() && column.ty.nullable) {
+                if let Some(parent) = self.models.get(&column.ty.name) {
+                    if parent.filter.is_some() && !parent.skip {
+                        orphaned.push((model.name.clone(), column.name.clone(), parent.name.clone()));
+                    }
+                }
+            }
+        }
+
+        orphaned
+    }
+
+    // Return the names of the tables in the given database that don't belong to any
+    // model, excluding SQLite's and Prisma's internal tables and the ones prismerge
+    // creates.
+    pub fn unmodeled_tables(self: &Self, conn: &Connection) -> Result<Vec<String>, String> {
+        let mut stmt = conn.prepare(r#"
+            SELECT name FROM sqlite_master
+            WHERE type = 'table' AND name NOT LIKE 'sqlite\_%' ESCAPE '\' AND name NOT LIKE '\_prismerge\_%' ESCAPE '\' AND name != '_prisma_migrations'
+            ORDER BY name
+        "#).map_err(|e| e.to_string())?;
+
+        let names = stmt
+            .query_map((), |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(
+            names
+                .into_iter()
+                .filter(|name| !self.models.contains_key(name) && !self.models.values().any(|model| &model.map_table.name == name))
+                .collect()
+        )
     }
 
     pub fn sorted(self: &Self) -> Vec<&Model> {
@@ -221,3 +925,140 @@ impl Schema {
             .tap_mut(|order| order.reverse())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::conflicts::{Aggregate, ConflictPolicy};
+    use crate::data::Rename;
+    use crate::prisma_parser;
+    use std::collections::HashSet;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id    String @id
+          name  String @unique
+        }
+
+        model Toy {
+          id      String  @id
+          name    String
+          ownerId String
+          owner   Owner   @relation(fields: [ownerId], references: [id])
+          boxId   String?
+          box     Box?    @relation(fields: [boxId], references: [id])
+        }
+
+        model Box {
+          id    String @id
+          label String
+        }
+
+        model Part {
+          id    String @id
+          toyId String
+          toy   Toy    @relation(fields: [toyId], references: [id])
+        }
+    "#;
+
+    fn skipped(schema: &crate::data::Schema) -> Vec<String> {
+        let mut names: Vec<String> = schema.models
+            .values()
+            .filter(|model| model.skip)
+            .map(|model| model.name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn only_includes_required_parents() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        let cascaded = schema.select(&["Part".to_string()], &[]).unwrap();
+
+        // Box is only referenced via an optional relation, so it isn't required.
+        assert!(skipped(&schema) == vec!["Box"]);
+        assert!(cascaded.is_empty());
+    }
+
+    #[test]
+    fn exclude_cascades_to_children() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        let cascaded = schema.select(&[], &["Owner".to_string()]).unwrap();
+
+        assert!(skipped(&schema) == vec!["Owner", "Part", "Toy"]);
+        assert!(cascaded.contains(&("Toy".to_string(), "Owner".to_string())));
+        assert!(cascaded.contains(&("Part".to_string(), "Toy".to_string())));
+    }
+
+    #[test]
+    fn exclude_does_not_cascade_over_optional_relations() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        schema.select(&[], &["Box".to_string()]).unwrap();
+        assert!(skipped(&schema) == vec!["Box"]);
+    }
+
+    #[test]
+    fn rejects_unknown_models() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        assert!(schema.select(&["Nope".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn filters_cascade_over_required_relations() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        schema.models.get_mut("Owner").unwrap().add_filter("name = 'Woody'");
+        schema.models.get_mut("Box").unwrap().add_filter("label = 'Toys'");
+
+        let mut cascaded = schema.cascade_filters();
+        cascaded.sort();
+
+        assert!(cascaded == vec![("Part".to_string(), "Toy".to_string()), ("Toy".to_string(), "Owner".to_string())]);
+        assert!(schema.models["Part"].filter.as_ref().unwrap().contains("name = 'Woody'"));
+        assert!(schema.orphaned_by_filters() == vec![("Toy".to_string(), "box".to_string(), "Box".to_string())]);
+    }
+
+    #[test]
+    fn parses_renames() {
+        assert!("title".parse::<Rename>() == Ok(Rename { from: "title".to_string(), until: None }));
+        assert!("title@2_rename".parse::<Rename>() == Ok(Rename { from: "title".to_string(), until: Some("2_rename".to_string()) }));
+        assert!("title@".parse::<Rename>().is_err());
+
+        let rename = "title@2_rename".parse::<Rename>().unwrap();
+        assert!(rename.applies_to(None));
+        assert!(rename.applies_to(Some(&HashSet::from(["1_init".to_string()]))));
+        assert!(!rename.applies_to(Some(&HashSet::from(["1_init".to_string(), "2_rename".to_string()]))));
+    }
+
+    #[test]
+    fn checks_conflict_policies() {
+        let schema = || prisma_parser::parse(r#"
+            model Owner {
+              id        String   @id
+              number    Int      @unique
+              score     Int
+              updatedAt DateTime
+            }
+
+            model Toy {
+              id          String @id
+              ownerNumber Int
+              owner       Owner  @relation(fields: [ownerNumber], references: [number])
+            }
+        "#).unwrap();
+
+        let check = |model_name: &str, column_name: &str, aggregate: Option<Aggregate>, policy: ConflictPolicy| {
+            let mut schema = schema();
+            let model = schema.models.get_mut(model_name).unwrap();
+            model.conflict_policy = policy;
+            model.get_col_mut(column_name).unwrap().aggregate = aggregate;
+            model.check_conflict_policy()
+        };
+
+        assert!(check("Owner", "score", Some(Aggregate::Max), ConflictPolicy::Newest("updatedAt".to_string())).is_ok());
+        assert!(check("Owner", "updatedAt", Some(Aggregate::Max), ConflictPolicy::KeepFirst).is_err());
+        assert!(check("Owner", "number", Some(Aggregate::Sum), ConflictPolicy::KeepFirst).is_err());
+        assert!(check("Toy", "ownerNumber", Some(Aggregate::Sum), ConflictPolicy::KeepFirst).is_err());
+        assert!(check("Owner", "score", None, ConflictPolicy::Newest("number".to_string())).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use rusqlite::Connection;
+use std::{path::Path, str::FromStr};
+
+use crate::data::{Column, Model, Schema};
+use crate::migrations::read_migrations;
+
+/* The output database's tables and indices can come from one of three places:
+ *
+ * Input:      the sqlite_master table of the input that best matches the schema (the
+ *             default).
+ * Migrations: the migration.sql files in a Prisma migrations folder, applied in order.
+ * Prisma:     generated from the parsed Prisma schema, mimicking the DDL Prisma's own
+ *             migration engine produces for SQLite.
+ *
+ * Each function below returns a list of SQL batches to execute against the output
+ * database in order.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputSchema {
+    #[default]
+    Input,
+    Migrations,
+    Prisma
+}
+
+impl FromStr for OutputSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "input" => Ok(OutputSchema::Input),
+            "migrations" => Ok(OutputSchema::Migrations),
+            "prisma" => Ok(OutputSchema::Prisma),
+            _ => Err(format!("Unknown output schema '{}', expected one of input, migrations, or prisma", s))
+        }
+    }
+}
+
+// Return the DDL for the objects in the given input database, excluding SQLite's
+// internal objects (eg. sqlite_sequence and sqlite_autoindex_*), the tables prismerge
+// itself creates, and map tables left behind by previous merges.
+pub fn input_ddl(conn: &Connection, schema: &Schema) -> Result<Vec<String>, String> {
+    // Tables are created before the indices, views, and triggers that refer to them.
+    let mut stmt = conn.prepare(r#"
+        SELECT name, sql FROM sqlite_master
+        WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite\_%' ESCAPE '\' AND name NOT LIKE '\_prismerge\_%' ESCAPE '\'
+        ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 WHEN 'view' THEN 2 ELSE 3 END, rowid
+    "#).map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map((), |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()
+        .map_err(|e| e.to_string())?;
+
+    let map_table_names: Vec<&String> = schema.models.values().map(|model| &model.map_table.name).collect();
+
+    Ok(
+        rows
+            .into_iter()
+            .filter(|(name, _)| {
+                !map_table_names.iter().any(|map_table_name| {
+                    name == *map_table_name || name.starts_with(&format!("{}_", map_table_name))
+                })
+            })
+            .map(|(_, sql)| sql)
+            .collect()
+    )
+}
+
+// Return the contents of each migration.sql file in the given Prisma migrations folder,
+// in the order the migrations were created.
+pub fn migrations_ddl(migrations_path: &Path) -> Result<Vec<String>, String> {
+    Ok(read_migrations(migrations_path)?.into_iter().map(|migration| migration.sql).collect())
+}
+
+// The SQLite type Prisma uses for the given scalar type. Enums are stored as text.
+fn column_type(prisma_type: &str) -> &'static str {
+    match prisma_type {
+        "Int" => "INTEGER",
+        "BigInt" => "BIGINT",
+        "Float" => "REAL",
+        "Decimal" => "DECIMAL",
+        "Boolean" => "BOOLEAN",
+        "DateTime" => "DATETIME",
+        "Bytes" => "BLOB",
+        _ => "TEXT"
+    }
+}
+
+fn column_definition(column: &Column) -> String {
+    let mut definition = format!("\"{}\" {}", column.name, column_type(&column.ty.name));
+
+    if !column.ty.nullable {
+        definition.push_str(" NOT NULL");
+    }
+
+    if column.primary_key {
+        definition.push_str(" PRIMARY KEY");
+    }
+
+    if let Some(default) = &column.default {
+        definition.push_str(&format!(" DEFAULT {}", default));
+    }
+
+    definition
+}
+
+fn quoted_list(names: &[String]) -> String {
+    names.iter().map(|name| format!("\"{}\"", name)).collect::<Vec<String>>().join(", ")
+}
+
+fn model_ddl(model: &Model, schema: &Schema) -> Vec<String> {
+    let mut definitions: Vec<String> = model.columns
+        .iter()
+        .filter(|column| !column.ty.collection && !schema.models.contains_key(&column.ty.name))
+        .map(column_definition)
+        .collect();
+
+    for column in model.columns.iter() {
+        let Some(relation) = &column.relation else { continue };
+
+        if relation.fields.is_empty() {
+            continue;
+        }
+
+        // Prisma's default referential actions.
+        let on_delete = if column.ty.nullable { "SET NULL" } else { "RESTRICT" };
+
+        definitions.push(format!(
+            "CONSTRAINT \"{model}_{fields_name}_fkey\" FOREIGN KEY ({fields}) REFERENCES \"{parent}\" ({references}) ON DELETE {on_delete} ON UPDATE CASCADE",
+            model = model.name,
+            fields_name = relation.fields.join("_"),
+            fields = quoted_list(&relation.fields),
+            parent = column.ty.name,
+            references = quoted_list(&relation.references)
+        ));
+    }
+
+    let mut statements = vec![
+        format!("CREATE TABLE \"{}\" (\n    {}\n)", model.name, definitions.join(",\n    "))
+    ];
+
+    for index in model.indices.iter() {
+        statements.push(format!(
+            "CREATE {unique}INDEX \"{name}\" ON \"{model}\"({columns})",
+            unique = if index.unique { "UNIQUE " } else { "" },
+            name = index.name(&model.name),
+            model = model.name,
+            columns = quoted_list(&index.column_names)
+        ));
+    }
+
+    statements
+}
+
+// Generate the DDL for every model in the schema, including skipped ones so the output
+// database always has the full schema.
+pub fn prisma_ddl(schema: &Schema) -> Vec<String> {
+    schema
+        .sorted()
+        .iter()
+        .flat_map(|model| model_ddl(model, schema))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ddl::*;
+    use std::fs;
+    use crate::prisma_parser;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id    String @id
+          name  String @unique
+          toys  Toy[]
+        }
+
+        model Toy {
+          id       String  @id
+          name     String
+          quantity Int     @default(1)
+          ownerId  String?
+          owner    Owner?  @relation(fields: [ownerId], references: [id])
+
+          @@index([name])
+        }
+    "#;
+
+    #[test]
+    fn generates_ddl_from_schema() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let ddl = prisma_ddl(&schema);
+
+        assert!(ddl == vec![
+            "CREATE TABLE \"Owner\" (\n    \"id\" TEXT NOT NULL PRIMARY KEY,\n    \"name\" TEXT NOT NULL\n)",
+            "CREATE UNIQUE INDEX \"Owner_name_key\" ON \"Owner\"(\"name\")",
+            "CREATE TABLE \"Toy\" (\n    \"id\" TEXT NOT NULL PRIMARY KEY,\n    \"name\" TEXT NOT NULL,\n    \"quantity\" INTEGER NOT NULL DEFAULT 1,\n    \"ownerId\" TEXT,\n    CONSTRAINT \"Toy_ownerId_fkey\" FOREIGN KEY (\"ownerId\") REFERENCES \"Owner\" (\"id\") ON DELETE SET NULL ON UPDATE CASCADE\n)",
+            "CREATE INDEX \"Toy_name_idx\" ON \"Toy\"(\"name\")"
+        ]);
+
+        // The generated DDL must be valid.
+        let conn = Connection::open_in_memory().unwrap();
+
+        for statement in ddl {
+            conn.execute_batch(&statement).unwrap();
+        }
+    }
+
+    #[test]
+    fn excludes_internal_objects_from_input_ddl() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" INTEGER PRIMARY KEY AUTOINCREMENT, "name" TEXT UNIQUE);
+            CREATE INDEX "Owner_name_idx" ON "Owner"("name");
+            CREATE TABLE "Owner_id_map" (old_id TEXT, new_id TEXT);
+            CREATE INDEX "Owner_id_map_old_id" ON "Owner_id_map"(old_id);
+            CREATE TABLE "_prismerge_conflicts" (model TEXT);
+        "#).unwrap();
+
+        let ddl = input_ddl(&conn, &schema).unwrap();
+
+        // sqlite_sequence and sqlite_autoindex_Owner_1 are excluded along with the rest.
+        assert!(ddl == vec![
+            "CREATE TABLE \"Owner\" (\"id\" INTEGER PRIMARY KEY AUTOINCREMENT, \"name\" TEXT UNIQUE)",
+            "CREATE INDEX \"Owner_name_idx\" ON \"Owner\"(\"name\")"
+        ]);
+    }
+
+    #[test]
+    fn reads_migrations_in_order() {
+        let dir = std::env::temp_dir().join(format!("prismerge-migrations-{}", std::process::id()));
+
+        for (name, sql) in [("20240102000000_add_toys", "CREATE TABLE b (id TEXT);"), ("20240101000000_init", "CREATE TABLE a (id TEXT);")] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            fs::write(dir.join(name).join("migration.sql"), sql).unwrap();
+        }
+
+        fs::write(dir.join("migration_lock.toml"), "provider = \"sqlite\"").unwrap();
+
+        let ddl = migrations_ddl(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(ddl == vec!["CREATE TABLE a (id TEXT);", "CREATE TABLE b (id TEXT);"]);
+    }
+}
@@ -0,0 +1,282 @@
+use rusqlite::Connection;
+use std::str::FromStr;
+
+use crate::data::{Model, Schema};
+use crate::utils::quote_literal;
+
+pub const EXTRACT_TABLE: &str = "_prismerge_extract";
+
+/* Extraction merges a small, self-contained subset of the input databases, eg. a
+ * single repository along with everything it references and everything that
+ * references it. The subset starts with one or more seed rows and is expanded in
+ * two phases:
+ *
+ * 1. Downward: rows that reference rows already in the subset (i.e. children,
+ *    grandchildren, etc) are added until no more rows can be found.
+ * 2. Upward: rows referenced by rows in the subset (i.e. parents, grandparents,
+ *    etc) are added until no more rows can be found. This ensures referential
+ *    integrity. Note that the children of parents added in this phase are not
+ *    added, otherwise the subset would quickly balloon to include most of the
+ *    database.
+ *
+ * The primary keys of the rows in the subset are stored in a TEMP table in each
+ * input connection, which leaves the input database files untouched. Each model is
+ * then given a filter that restricts merging to the rows in the TEMP table.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Seed {
+    pub model: String,
+    pub predicate: String
+}
+
+impl FromStr for Seed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (model, predicate) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid seed '{}', expected MODEL=PREDICATE", s))?;
+
+        Ok(Seed { model: model.to_string(), predicate: predicate.to_string() })
+    }
+}
+
+// A relation from the `child` model's `fields` to the `parent` model's `references`.
+struct Edge<'a> {
+    child: &'a Model,
+    parent: &'a Model,
+    fields: &'a [String],
+    references: &'a [String]
+}
+
+fn edges(schema: &Schema) -> Vec<Edge<'_>> {
+    let mut edges = vec![];
+
+    for child in schema.models.values() {
+        for column in child.columns.iter() {
+            if let (Some(relation), Some(parent)) = (&column.relation, schema.models.get(&column.ty.name)) {
+                if !relation.fields.is_empty() && relation.fields.len() == relation.references.len() {
+                    edges.push(Edge { child, parent, fields: &relation.fields, references: &relation.references });
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+fn column_list(table: &str, columns: &[String]) -> String {
+    let columns = columns
+        .iter()
+        .map(|column| format!("\"{}\".\"{}\"", table, column))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!("({})", columns)
+}
+
+// The set of primary keys in the subset for the given model.
+fn extracted_ids(model: &Model) -> String {
+    format!("SELECT id FROM temp.\"{}\" WHERE model = {}", EXTRACT_TABLE, quote_literal(&model.name))
+}
+
+fn primary_key_name(model: &Model) -> Result<&str, String> {
+    model
+        .primary_key()
+        .map(|column| column.name.as_str())
+        .ok_or_else(|| format!("Model {} has no primary key and can't be extracted", model.name))
+}
+
+// Compute the subset of rows in the given connection reachable from the seeds and
+// store their primary keys in the connection's TEMP extract table. Returns the total
+// number of rows in the subset.
+pub fn compute_closure(schema: &Schema, conn: &Connection, seeds: &[Seed]) -> Result<usize, String> {
+    conn.execute_batch(
+        format!(r#"
+            DROP TABLE IF EXISTS temp."{table}";
+            CREATE TEMP TABLE "{table}" (model TEXT NOT NULL, id, PRIMARY KEY (model, id));
+        "#, table = EXTRACT_TABLE).as_str()
+    ).map_err(|e| e.to_string())?;
+
+    for seed in seeds {
+        let model = schema.models
+            .get(&seed.model)
+            .ok_or_else(|| format!("Unknown model '{}' in seed", seed.model))?;
+
+        let seed_sql = format!(
+            "INSERT OR IGNORE INTO temp.\"{table}\" (model, id) SELECT {model_name}, \"{primary_key}\" FROM \"{model}\" WHERE ({predicate})",
+            table = EXTRACT_TABLE,
+            model_name = quote_literal(&model.name),
+            primary_key = primary_key_name(model)?,
+            model = model.name,
+            predicate = seed.predicate
+        );
+
+        conn.execute(seed_sql.as_str(), ())
+            .map_err(|e| format!("Unable to select seed rows for {}: {}", seed.model, e))?;
+    }
+
+    let edges: Vec<Edge> = edges(schema)
+        .into_iter()
+        .filter(|edge| !edge.child.skip && !edge.parent.skip)
+        .collect();
+
+    // Downward: add children of rows in the subset.
+    expand(conn, &edges, |edge| {
+        Ok(format!(
+            "INSERT OR IGNORE INTO temp.\"{table}\" (model, id) SELECT {model_name}, \"{child}\".\"{primary_key}\" FROM \"{child}\" WHERE {fields} IN (SELECT {references} FROM \"{parent}\" WHERE \"{parent}\".\"{parent_key}\" IN ({parent_ids}))",
+            table = EXTRACT_TABLE,
+            model_name = quote_literal(&edge.child.name),
+            child = edge.child.name,
+            primary_key = primary_key_name(edge.child)?,
+            fields = column_list(&edge.child.name, edge.fields),
+            references = column_list(&edge.parent.name, edge.references),
+            parent = edge.parent.name,
+            parent_key = primary_key_name(edge.parent)?,
+            parent_ids = extracted_ids(edge.parent)
+        ))
+    })?;
+
+    // Upward: add parents of rows in the subset.
+    expand(conn, &edges, |edge| {
+        Ok(format!(
+            "INSERT OR IGNORE INTO temp.\"{table}\" (model, id) SELECT {model_name}, \"{parent}\".\"{primary_key}\" FROM \"{parent}\" WHERE {references} IN (SELECT {fields} FROM \"{child}\" WHERE \"{child}\".\"{child_key}\" IN ({child_ids}))",
+            table = EXTRACT_TABLE,
+            model_name = quote_literal(&edge.parent.name),
+            parent = edge.parent.name,
+            primary_key = primary_key_name(edge.parent)?,
+            references = column_list(&edge.parent.name, edge.references),
+            fields = column_list(&edge.child.name, edge.fields),
+            child = edge.child.name,
+            child_key = primary_key_name(edge.child)?,
+            child_ids = extracted_ids(edge.child)
+        ))
+    })?;
+
+    conn.query_row(format!("SELECT COUNT(*) FROM temp.\"{}\"", EXTRACT_TABLE).as_str(), (), |row| row.get::<_, usize>(0))
+        .map_err(|e| e.to_string())
+}
+
+// Repeatedly run the statements generated for each edge until none of them add any
+// more rows.
+fn expand<F>(conn: &Connection, edges: &[Edge], statement_for: F) -> Result<(), String>
+    where F: Fn(&Edge) -> Result<String, String> {
+    let statements = edges
+        .iter()
+        .map(statement_for)
+        .collect::<Result<Vec<String>, String>>()?;
+
+    loop {
+        let mut added = 0;
+
+        for statement in statements.iter() {
+            added += conn.execute(statement.as_str(), ()).map_err(|e| e.to_string())?;
+        }
+
+        if added == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// Restrict every model to the rows in the TEMP extract table. Must be called after
+// the closure has been computed for every input connection.
+pub fn apply_filters(schema: &mut Schema) -> Result<(), String> {
+    for model in schema.models.values_mut() {
+        if model.skip {
+            continue;
+        }
+
+        let predicate = format!("\"{}\" IN ({})", primary_key_name(model)?, extracted_ids(model));
+        model.add_filter(&predicate);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::*;
+    use crate::prisma_parser;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id    String @id
+          name  String @unique
+        }
+
+        model Toy {
+          id      String @id
+          name    String
+          ownerId String
+          owner   Owner  @relation(fields: [ownerId], references: [id])
+        }
+
+        model Part {
+          id    String @id
+          toyId String
+          toy   Toy    @relation(fields: [toyId], references: [id])
+        }
+    "#;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT PRIMARY KEY, "name" TEXT);
+            CREATE TABLE "Toy" ("id" TEXT PRIMARY KEY, "name" TEXT, "ownerId" TEXT);
+            CREATE TABLE "Part" ("id" TEXT PRIMARY KEY, "toyId" TEXT);
+
+            INSERT INTO "Owner" VALUES ('o1', 'Andy'), ('o2', 'Sid');
+            INSERT INTO "Toy" VALUES ('t1', 'Woody', 'o1'), ('t2', 'Buzz', 'o1'), ('t3', 'Babyface', 'o2');
+            INSERT INTO "Part" VALUES ('p1', 't1'), ('p2', 't2'), ('p3', 't3');
+        "#).unwrap();
+
+        conn
+    }
+
+    fn extracted(conn: &Connection, model: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(
+            format!("SELECT id FROM temp.\"{}\" WHERE model = '{}' ORDER BY id", EXTRACT_TABLE, model).as_str()
+        ).unwrap();
+
+        stmt.query_map((), |row| row.get::<_, String>(0)).unwrap().map(|id| id.unwrap()).collect()
+    }
+
+    #[test]
+    fn extracts_children_and_parents() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = setup();
+        let seeds = vec!["Toy=name = 'Woody'".parse::<Seed>().unwrap()];
+
+        let count = compute_closure(&schema, &conn, &seeds).unwrap();
+
+        // Woody's part and owner are included, but not Andy's other toys.
+        assert!(count == 3);
+        assert!(extracted(&conn, "Toy") == vec!["t1"]);
+        assert!(extracted(&conn, "Part") == vec!["p1"]);
+        assert!(extracted(&conn, "Owner") == vec!["o1"]);
+    }
+
+    #[test]
+    fn extracts_descendants_transitively() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = setup();
+        let seeds = vec!["Owner=name = 'Andy'".parse::<Seed>().unwrap()];
+
+        compute_closure(&schema, &conn, &seeds).unwrap();
+
+        assert!(extracted(&conn, "Owner") == vec!["o1"]);
+        assert!(extracted(&conn, "Toy") == vec!["t1", "t2"]);
+        assert!(extracted(&conn, "Part") == vec!["p1", "p2"]);
+    }
+
+    #[test]
+    fn rejects_invalid_seeds() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = setup();
+
+        assert!("Toy".parse::<Seed>().is_err());
+        assert!(compute_closure(&schema, &conn, &["Nope=1".parse::<Seed>().unwrap()]).is_err());
+    }
+}
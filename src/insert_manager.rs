@@ -1,5 +1,7 @@
 use rusqlite::Connection;
 
+use crate::rejects::{Reject, RejectLog};
+
 /* The InsertManager is a convenient way to insert records in bulk. Every time
  * a record is inserted, the manager adds it to an internal list. When the length
  * of the list exceeds the given threshold, all the records are inserted at once,
@@ -17,46 +19,281 @@ use rusqlite::Connection;
  * method returns either 0 or this count value, indicating how many regular
  * records were actually inserted.
  *
+ * Regular records that map a duplicate row to an existing row, rather than inserting
+ * it, are added with `insert_duplicate()`. They count towards progress like other
+ * regular records, but are counted separately when they're rejected.
+ *
+ * Supporting records belong to the regular record inserted before them. If any
+ * statement in a bulk insert fails, the whole batch is rolled back and replayed one
+ * regular record at a time, each inside its own savepoint. Records that still fail
+ * are rolled back along with their supporting records and handed to the reject log,
+ * if one was given. Without a reject log, the error is returned instead.
+ *
  * Call the `flush()` method to force the InsertManager to insert all pending
  * records, regular and otherwise.
  */
 pub struct InsertManager<'a> {
     connection: &'a Connection,
     threshold: u64,
+    groups: Vec<Group>,
+    statement_count: u64,
+    count: usize,
+    rejects: Option<&'a mut RejectLog>,
+    model: String,
+    input: String,
+
+    // The number of regular records that were rejected, not counting duplicates.
+    pub rejected: u64,
+
+    // The number of duplicates that were rejected.
+    pub rejected_duplicates: u64,
+
+    // The old IDs of the regular records rejected since take_rejected_ids() was last
+    // called, not counting duplicates.
+    rejected_ids: Vec<String>
+}
+
+// A regular record together with its supporting records.
+struct Group {
     statements: Vec<String>,
-    count: usize
+    regular: bool,
+    duplicate: bool,
+    input: String,
+    old_id: Option<String>
 }
 
 impl<'a> InsertManager<'a> {
     pub fn new(connection: &'a Connection, threshold: u64) -> Self {
-        InsertManager { connection, threshold, statements: vec![], count: 0 }
+        InsertManager {
+            connection,
+            threshold,
+            groups: vec![],
+            statement_count: 0,
+            count: 0,
+            rejects: None,
+            model: String::new(),
+            input: String::new(),
+            rejected: 0,
+            rejected_duplicates: 0,
+            rejected_ids: vec![]
+        }
+    }
+
+    // Record records that fail to insert in the given log, attributing them to the
+    // given model.
+    pub fn with_rejects(mut self: Self, rejects: &'a mut RejectLog, model: &str) -> Self {
+        self.rejects = Some(rejects);
+        self.model = model.to_string();
+        self
+    }
+
+    // Set the input database subsequent records are read from.
+    pub fn set_input(self: &mut Self, input: &str) {
+        self.input = input.to_string();
+    }
+
+    pub fn insert(self: &mut Self, statement: String, old_id: Option<&str>) -> Result<u64, String> {
+        self.insert_regular(statement, old_id, false)
+    }
+
+    pub fn insert_duplicate(self: &mut Self, statement: String, old_id: Option<&str>) -> Result<u64, String> {
+        self.insert_regular(statement, old_id, true)
     }
 
-    pub fn insert(self: &mut Self, statement: String) -> u64 {
-        self.statements.push(statement);
+    fn insert_regular(self: &mut Self, statement: String, old_id: Option<&str>, duplicate: bool) -> Result<u64, String> {
+        self.groups.push(Group {
+            statements: vec![statement],
+            regular: true,
+            duplicate,
+            input: self.input.clone(),
+            old_id: old_id.map(|old_id| old_id.to_string())
+        });
+
+        self.statement_count += 1;
         self.count += 1;
         self.maybe_flush()
     }
 
-    pub fn insert_supporting(self: &mut Self, statement: String) -> u64 {
-        self.statements.push(statement);
+    pub fn insert_supporting(self: &mut Self, statement: String) -> Result<u64, String> {
+        match self.groups.last_mut() {
+            Some(group) => group.statements.push(statement),
+
+            None => self.groups.push(Group {
+                statements: vec![statement],
+                regular: false,
+                duplicate: false,
+                input: self.input.clone(),
+                old_id: None
+            })
+        }
+
+        self.statement_count += 1;
         self.maybe_flush()
     }
 
-    fn maybe_flush(self: &mut Self) -> u64 {
-        if self.statements.len() as u64 >= self.threshold {
+    // Whether all records have been inserted or rejected.
+    pub fn is_empty(self: &Self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn take_rejected_ids(self: &mut Self) -> Vec<String> {
+        std::mem::take(&mut self.rejected_ids)
+    }
+
+    // Record a row that couldn't be read from the current input.
+    pub fn reject(self: &mut Self, old_id: Option<&str>, statement: &str, error: &str) -> Result<(), String> {
+        let reject = Reject {
+            input: self.input.clone(),
+            model: self.model.clone(),
+            old_id: old_id.map(|old_id| old_id.to_string()),
+            statement: statement.to_string(),
+            error: error.to_string()
+        };
+
+        match self.rejects.as_mut() {
+            Some(rejects) => rejects.record(self.connection, reject),
+            None => Err(format!("Unable to read a row of {} from {}: {}", reject.model, reject.input, reject.error))
+        }
+    }
+
+    fn maybe_flush(self: &mut Self) -> Result<u64, String> {
+        if self.statement_count >= self.threshold {
             return self.flush();
         }
 
-        0
+        Ok(0)
     }
 
-    pub fn flush(self: &mut Self) -> u64 {
-        let batch = format!("BEGIN TRANSACTION; {}; COMMIT;", self.statements.join("; "));
-        self.connection.execute_batch(batch.as_str()).unwrap();
-        self.statements.clear();
+    pub fn flush(self: &mut Self) -> Result<u64, String> {
+        let statements: Vec<&str> = self.groups
+            .iter()
+            .flat_map(|group| group.statements.iter().map(|statement| statement.as_str()))
+            .collect();
+
+        if !statements.is_empty() {
+            let batch = format!("BEGIN TRANSACTION; {}; COMMIT;", statements.join("; "));
+
+            if let Err(e) = self.connection.execute_batch(batch.as_str()) {
+                self.rollback()?;
+
+                if self.rejects.is_none() {
+                    return Err(e.to_string());
+                }
+
+                self.replay()?;
+            }
+        }
+
+        self.groups.clear();
+        self.statement_count = 0;
         let count = self.count as u64;
         self.count = 0;
-        count
+        Ok(count)
+    }
+
+    // Insert each group separately so the ones that fail can be rejected without
+    // losing the rest.
+    fn replay(self: &mut Self) -> Result<(), String> {
+        let connection = self.connection;
+        let groups = std::mem::take(&mut self.groups);
+        connection.execute_batch("BEGIN TRANSACTION;").map_err(|e| e.to_string())?;
+
+        for group in groups.iter() {
+            connection.execute_batch("SAVEPOINT record;").map_err(|e| e.to_string())?;
+
+            let failure = group.statements
+                .iter()
+                .find_map(|statement| {
+                    connection.execute_batch(statement.as_str()).err().map(|e| (statement, e.to_string()))
+                });
+
+            match failure {
+                Some((statement, error)) => {
+                    connection.execute_batch("ROLLBACK TO record; RELEASE record;").map_err(|e| e.to_string())?;
+
+                    if group.duplicate {
+                        self.rejected_duplicates += 1;
+                    } else if group.regular {
+                        self.rejected += 1;
+                        self.rejected_ids.extend(group.old_id.clone());
+                    }
+
+                    let reject = Reject {
+                        input: group.input.clone(),
+                        model: self.model.clone(),
+                        old_id: group.old_id.clone(),
+                        statement: statement.trim().to_string(),
+                        error
+                    };
+
+                    // Keep the records inserted so far, along with the rejects, before
+                    // giving up.
+                    if let Err(e) = self.rejects.as_mut().unwrap().record(connection, reject) {
+                        connection.execute_batch("COMMIT;").map_err(|e| e.to_string())?;
+                        return Err(e);
+                    }
+                }
+
+                None => connection.execute_batch("RELEASE record;").map_err(|e| e.to_string())?
+            }
+        }
+
+        connection.execute_batch("COMMIT;").map_err(|e| e.to_string())
+    }
+
+    // A failed statement leaves its transaction open.
+    fn rollback(self: &Self) -> Result<(), String> {
+        if !self.connection.is_autocommit() {
+            self.connection.execute_batch("ROLLBACK;").map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::insert_manager::*;
+    use crate::rejects::RejectSink;
+
+    #[test]
+    fn rejects_failed_records_and_keeps_the_rest() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE Toy (id TEXT PRIMARY KEY, name TEXT NOT NULL); CREATE TABLE Toy_id_map (old_id TEXT, new_id TEXT NOT NULL);").unwrap();
+
+        let mut log = RejectLog::new(RejectSink::default(), None);
+        let mut inserter = InsertManager::new(&conn, 100).with_rejects(&mut log, "Toy");
+        inserter.set_input("a.db");
+
+        for (id, name) in [("1", "'Woody'"), ("2", "NULL"), ("3", "'Jessie'")] {
+            inserter.insert(format!("INSERT INTO Toy (id, name) VALUES ('{}', {})", id, name), Some(id)).unwrap();
+            inserter.insert_supporting(format!("INSERT INTO Toy_id_map (old_id, new_id) VALUES ('{}', '{}')", id, id)).unwrap();
+        }
+
+        // Mapping a duplicate to a row that doesn't exist fails the NOT NULL constraint.
+        inserter.insert_duplicate("INSERT INTO Toy_id_map (old_id, new_id) VALUES ('4', (SELECT id FROM Toy WHERE id = '2'))".to_string(), Some("4")).unwrap();
+
+        assert!(inserter.flush() == Ok(4));
+        assert!(inserter.rejected == 1);
+        assert!(inserter.rejected_duplicates == 1);
+        assert!(inserter.take_rejected_ids() == vec!["2"]);
+        assert!(inserter.is_empty());
+
+        let count = |sql: &str| conn.query_row(sql, (), |row| row.get::<_, u64>(0)).unwrap();
+        assert!(count("SELECT COUNT(*) FROM Toy") == 2);
+        assert!(count("SELECT COUNT(*) FROM Toy_id_map") == 2);
+        assert!(count("SELECT COUNT(*) FROM _prismerge_rejects WHERE old_id = '2' AND input = 'a.db'") == 1);
+    }
+
+    #[test]
+    fn returns_errors_without_a_reject_log() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE Toy (id TEXT PRIMARY KEY, name TEXT NOT NULL);").unwrap();
+
+        let mut inserter = InsertManager::new(&conn, 100);
+        inserter.insert("INSERT INTO Toy (id, name) VALUES ('1', NULL)".to_string(), Some("1")).unwrap();
+
+        assert!(inserter.flush().is_err());
+        assert!(conn.is_autocommit());
     }
 }
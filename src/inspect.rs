@@ -0,0 +1,418 @@
+use serde::Serialize;
+use std::{collections::HashSet, str::FromStr};
+
+use crate::data::{Model, Schema};
+
+/* Inspection shows how prismerge understood a schema: the unique key it uses to detect
+ * duplicates in each model, the order models are merged in, and any constructs it
+ * doesn't support. The result can be written as JSON or drawn as a relation graph in
+ * Graphviz (DOT) or Mermaid syntax, with problematic models highlighted.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Dot,
+    Mermaid
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            _ => Err(format!("Unknown format '{}', expected one of json, dot, or mermaid", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    // The model is part of, or depends on, a cycle of relations and can't be ordered.
+    Cycle,
+
+    // The model has no single-column primary key, or a relation uses several columns.
+    CompositeKey,
+
+    // The model has no unique key, so duplicate rows can't be detected.
+    MissingUnique
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub model: String,
+    pub message: String
+}
+
+// A relation from the `child` model to the `parent` model via the given fields.
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub child: String,
+    pub parent: String,
+    pub fields: Vec<String>,
+    pub nullable: bool
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inspection<'a> {
+    // Model names in the order they're merged, i.e. parents before children. Models
+    // that can't be ordered are left out.
+    pub order: Vec<String>,
+    pub edges: Vec<Edge>,
+    pub issues: Vec<Issue>,
+
+    #[serde(flatten)]
+    pub schema: &'a Schema
+}
+
+impl Inspection<'_> {
+    fn position(self: &Self, model: &Model) -> Option<usize> {
+        self.order.iter().position(|name| name == &model.name)
+    }
+
+    fn issues_for(self: &Self, model: &Model) -> Vec<&Issue> {
+        self.issues.iter().filter(|issue| issue.model == model.name).collect()
+    }
+
+    fn label(self: &Self, model: &Model, separator: &str) -> String {
+        let mut lines = vec![match self.position(model) {
+            Some(idx) => format!("{}. {}", idx + 1, model.name),
+            None => format!("?. {}", model.name)
+        }];
+
+        if let Some(unique) = &model.unique {
+            lines.push(format!("unique: {}", unique.column_names.join(", ")));
+        }
+
+        if model.skip {
+            lines.push("skipped".to_string());
+        }
+
+        if model.ignored {
+            lines.push("ignored".to_string());
+        }
+
+        for issue in self.issues_for(model) {
+            lines.push(issue.message.clone());
+        }
+
+        lines.join(separator)
+    }
+
+    fn sorted_models(self: &Self) -> Vec<&Model> {
+        let mut models: Vec<&Model> = self.schema.models.values().collect();
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        models
+    }
+}
+
+// The relations Schema::sorted() orders models by, i.e. every relation column whose
+// type is another model.
+fn dependencies(schema: &Schema) -> Vec<(&Model, &Model)> {
+    let mut dependencies = vec![];
+
+    for model in schema.models.values() {
+        for column in model.columns.iter() {
+            if column.relation.is_some() {
+                if let Some(related) = schema.models.get(&column.ty.name) {
+                    dependencies.push((model, related));
+                }
+            }
+        }
+    }
+
+    dependencies
+}
+
+// Return the names of the models reachable from the given one via dependencies.
+fn reachable<'a>(schema: &'a Schema, from: &Model) -> HashSet<&'a str> {
+    let dependencies = dependencies(schema);
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut pending: Vec<&str> = vec![from.name.as_str()];
+
+    while let Some(name) = pending.pop() {
+        for (model, related) in dependencies.iter() {
+            if model.name == name && seen.insert(related.name.as_str()) {
+                pending.push(related.name.as_str());
+            }
+        }
+    }
+
+    seen
+}
+
+fn cycle_issues(schema: &Schema, order: &[String]) -> Vec<Issue> {
+    let mut unordered: Vec<&Model> = schema.models
+        .values()
+        .filter(|model| !order.contains(&model.name))
+        .collect();
+
+    unordered.sort_by(|a, b| a.name.cmp(&b.name));
+
+    unordered
+        .into_iter()
+        .map(|model| {
+            let reachable = reachable(schema, model);
+
+            let message = if reachable.contains(model.name.as_str()) {
+                // The other members of the cycle are the models that can reach this one
+                // and are reachable from it.
+                let mut members: Vec<&str> = reachable
+                    .iter()
+                    .filter(|name| **name != model.name && reachable_from(schema, name).contains(model.name.as_str()))
+                    .copied()
+                    .collect();
+
+                members.sort();
+
+                if members.is_empty() {
+                    "references itself".to_string()
+                } else {
+                    format!("is part of a relation cycle with {}", members.join(", "))
+                }
+            } else {
+                "depends on a model in a relation cycle".to_string()
+            };
+
+            Issue { kind: IssueKind::Cycle, model: model.name.clone(), message }
+        })
+        .collect()
+}
+
+fn reachable_from<'a>(schema: &'a Schema, name: &str) -> HashSet<&'a str> {
+    schema.models.get(name).map(|model| reachable(schema, model)).unwrap_or_default()
+}
+
+fn key_issues(schema: &Schema, model: &Model) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    if model.primary_key().is_none() {
+        issues.push(Issue {
+            kind: IssueKind::CompositeKey,
+            model: model.name.clone(),
+            message: "has no single-column primary key".to_string()
+        });
+    }
+
+    for column in model.columns.iter() {
+        let Some(relation) = &column.relation else { continue };
+
+        if relation.fields.len() > 1 {
+            issues.push(Issue {
+                kind: IssueKind::CompositeKey,
+                model: model.name.clone(),
+                message: format!("relation {} uses a composite foreign key ({})", column.name, relation.fields.join(", "))
+            });
+        } else if let (Some(reference), Some(parent)) = (relation.references.first(), schema.models.get(&column.ty.name)) {
+            if parent.primary_key().is_some_and(|primary_key| &primary_key.name != reference) {
+                issues.push(Issue {
+                    kind: IssueKind::CompositeKey,
+                    model: model.name.clone(),
+                    message: format!("relation {} references {}.{}, which isn't its primary key", column.name, parent.name, reference)
+                });
+            }
+        }
+    }
+
+    if model.unique.is_none() && !model.dedupe_by_hash {
+        issues.push(Issue {
+            kind: IssueKind::MissingUnique,
+            model: model.name.clone(),
+            message: "has no unique key, so duplicate rows can't be detected".to_string()
+        });
+    }
+
+    issues
+}
+
+pub fn inspect(schema: &Schema) -> Inspection<'_> {
+    let order: Vec<String> = schema.sorted().iter().map(|model| model.name.clone()).collect();
+    let mut issues = cycle_issues(schema, &order);
+
+    let mut models: Vec<&Model> = schema.models.values().collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Ignored models are copied as-is, so they don't need keys.
+    for model in models.iter().filter(|model| !model.ignored) {
+        issues.append(&mut key_issues(schema, model));
+    }
+
+    let mut edges = vec![];
+
+    for model in models.iter() {
+        for column in model.columns.iter() {
+            if let Some(relation) = &column.relation {
+                if !relation.fields.is_empty() && schema.models.contains_key(&column.ty.name) {
+                    edges.push(Edge {
+                        child: model.name.clone(),
+                        parent: column.ty.name.clone(),
+                        fields: relation.fields.clone(),
+                        nullable: column.ty.nullable
+                    });
+                }
+            }
+        }
+    }
+
+    Inspection { order, edges, issues, schema }
+}
+
+pub fn to_json(inspection: &Inspection) -> Result<String, String> {
+    serde_json::to_string_pretty(inspection).map_err(|e| e.to_string())
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Edges point from child to parent. Optional relations are dashed.
+pub fn to_dot(inspection: &Inspection) -> String {
+    let mut lines = vec![
+        "digraph schema {".to_string(),
+        "  node [shape=box];".to_string()
+    ];
+
+    for model in inspection.sorted_models() {
+        let color = if inspection.issues_for(model).is_empty() { "" } else { ", color=red, fontcolor=red" };
+
+        lines.push(format!(
+            "  \"{}\" [label=\"{}\"{}];",
+            escape(&model.name),
+            escape(&inspection.label(model, "\n")).replace('\n', "\\n"),
+            color
+        ));
+    }
+
+    for edge in inspection.edges.iter() {
+        let style = if edge.nullable { ", style=dashed" } else { "" };
+        let color = if edge.fields.len() > 1 { ", color=red" } else { "" };
+
+        lines.push(format!(
+            "  \"{}\" -> \"{}\" [label=\"{}\"{}{}];",
+            escape(&edge.child),
+            escape(&edge.parent),
+            escape(&edge.fields.join(", ")),
+            style,
+            color
+        ));
+    }
+
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
+
+// Mermaid node IDs can't contain arbitrary characters, so models are identified by
+// their position in name order.
+pub fn to_mermaid(inspection: &Inspection) -> String {
+    let models = inspection.sorted_models();
+    let id = |name: &str| format!("m{}", models.iter().position(|model| model.name == name).unwrap());
+
+    let mut lines = vec!["flowchart LR".to_string()];
+    let mut problems = vec![];
+
+    for model in models.iter() {
+        lines.push(format!("  {}[\"{}\"]", id(&model.name), inspection.label(model, "<br/>").replace('"', "#quot;")));
+
+        if !inspection.issues_for(model).is_empty() {
+            problems.push(id(&model.name));
+        }
+    }
+
+    for edge in inspection.edges.iter() {
+        let arrow = if edge.nullable { "-.->" } else { "-->" };
+        lines.push(format!("  {} {}|\"{}\"| {}", id(&edge.child), arrow, edge.fields.join(", "), id(&edge.parent)));
+    }
+
+    if !problems.is_empty() {
+        lines.push("  classDef problem stroke:#d00,stroke-width:2px,color:#d00".to_string());
+        lines.push(format!("  class {} problem", problems.join(",")));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+pub fn render(inspection: &Inspection, format: Format) -> Result<String, String> {
+    match format {
+        Format::Json => to_json(inspection).map(|json| json + "\n"),
+        Format::Dot => Ok(to_dot(inspection)),
+        Format::Mermaid => Ok(to_mermaid(inspection))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inspect::*;
+    use crate::prisma_parser;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id    String @id
+          name  String @unique
+          toys  Toy[]
+        }
+
+        model Toy {
+          id      String @id
+          name    String
+          ownerId String
+          owner   Owner  @relation(fields: [ownerId], references: [id])
+        }
+
+        model Category {
+          id       String     @id
+          name     String     @unique
+          parentId String?
+          parent   Category?  @relation("Tree", fields: [parentId], references: [id])
+        }
+    "#;
+
+    #[test]
+    fn reports_order_and_issues() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let inspection = inspect(&schema);
+
+        assert!(inspection.order == vec!["Owner", "Toy"]);
+
+        let issues: Vec<(IssueKind, &str, &str)> = inspection.issues
+            .iter()
+            .map(|issue| (issue.kind, issue.model.as_str(), issue.message.as_str()))
+            .collect();
+
+        assert!(issues == vec![
+            (IssueKind::Cycle, "Category", "references itself"),
+            (IssueKind::MissingUnique, "Toy", "has no unique key, so duplicate rows can't be detected")
+        ]);
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&to_json(&inspect(&schema)).unwrap()).unwrap();
+
+        assert!(json["order"] == serde_json::json!(["Owner", "Toy"]));
+        assert!(json["models"][1]["name"] == "Owner");
+        assert!(json["models"][1]["unique"]["column_names"] == serde_json::json!(["name"]));
+        assert!(json["issues"][0]["kind"] == "cycle");
+    }
+
+    #[test]
+    fn draws_relation_graphs() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let inspection = inspect(&schema);
+
+        let dot = to_dot(&inspection);
+        assert!(dot.contains("\"Toy\" -> \"Owner\" [label=\"ownerId\"];"));
+        assert!(dot.contains("\"Owner\" [label=\"1. Owner\\nunique: name\"];"));
+        assert!(dot.contains("\"Category\" [label=\"?. Category\\nunique: name\\nreferences itself\", color=red, fontcolor=red];"));
+
+        // Models are numbered in name order: Category, Owner, Toy.
+        let mermaid = to_mermaid(&inspection);
+        assert!(mermaid.contains("  m2 -->|\"ownerId\"| m1"));
+        assert!(mermaid.contains("  m0 -.->|\"parentId\"| m0"));
+        assert!(mermaid.contains("  class m0,m2 problem"));
+    }
+}
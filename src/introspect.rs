@@ -0,0 +1,539 @@
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+use crate::data::{Column, ColumnType, Index, Model, Relation, Schema, Unique};
+
+/* Builds a Schema from the tables in a SQLite database, for databases that don't come
+ * with a Prisma schema. Primary keys, unique and regular indices, and foreign keys are
+ * read from SQLite's pragmas. Each foreign key becomes a relation column on the child
+ * model named after the parent, eg. ownerId -> owner.
+ *
+ * Prismerge needs a single-column String primary key to merge a table, since rows from
+ * secondary inputs are given UUID keys. Other tables are still included in the schema,
+ * but without a primary key, so they're copied according to their passthrough policy
+ * and skipped by default. Integer primary keys are kept as unique columns so foreign
+ * keys can still refer to them.
+ */
+pub struct Introspection {
+    pub schema: Schema,
+
+    // Problems that don't prevent merging, eg. tables that can't be merged.
+    pub warnings: Vec<String>
+}
+
+struct TableColumn {
+    name: String,
+    declared_type: String,
+    not_null: bool,
+    default: Option<String>,
+    primary_key_position: usize
+}
+
+struct ForeignKey {
+    table: String,
+    from: Vec<String>,
+    to: Vec<Option<String>>
+}
+
+// Map a declared SQLite type to the closest Prisma scalar type, checking the types
+// Prisma itself uses first and falling back to SQLite's type affinity rules.
+fn prisma_type(declared_type: &str) -> &'static str {
+    let declared_type = declared_type.to_uppercase();
+
+    match declared_type.as_str() {
+        "BIGINT" => return "BigInt",
+        "BOOLEAN" | "BOOL" => return "Boolean",
+        "DATETIME" | "DATE" | "TIMESTAMP" => return "DateTime",
+        "JSON" | "JSONB" => return "Json",
+        _ => ()
+    }
+
+    if declared_type.starts_with("DECIMAL") || declared_type.starts_with("NUMERIC") {
+        "Decimal"
+    } else if declared_type.contains("INT") {
+        "Int"
+    } else if ["CHAR", "CLOB", "TEXT"].iter().any(|t| declared_type.contains(t)) {
+        "String"
+    } else if declared_type.is_empty() || declared_type.contains("BLOB") {
+        "Bytes"
+    } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| declared_type.contains(t)) {
+        "Float"
+    } else {
+        "String"
+    }
+}
+
+// Default values are only kept if they're constant literals, eg. 'draft' or 0, and not
+// expressions like CURRENT_TIMESTAMP.
+fn literal_default(default: Option<String>) -> Option<String> {
+    let default = default?;
+    let is_string = default.starts_with('\'') && default.ends_with('\'') && default.len() >= 2;
+
+    if is_string || default.parse::<f64>().is_ok() {
+        Some(default)
+    } else {
+        None
+    }
+}
+
+fn lower_first(name: &str) -> String {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+fn query_rows<T, P, F>(conn: &Connection, sql: &str, params: P, f: F) -> Result<Vec<T>, String>
+    where P: rusqlite::Params, F: FnMut(&rusqlite::Row<'_>) -> rusqlite::Result<T> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params, f)
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<T>>>()
+        .map_err(|e| e.to_string());
+
+    rows
+}
+
+fn table_names(conn: &Connection) -> Result<Vec<String>, String> {
+    let names = query_rows(
+        conn,
+        r#"
+            SELECT name FROM sqlite_master
+            WHERE type = 'table' AND name NOT LIKE 'sqlite\_%' ESCAPE '\' AND name NOT LIKE '\_prisma%' ESCAPE '\'
+            ORDER BY name
+        "#,
+        (),
+        |row| row.get::<_, String>(0)
+    )?;
+
+    // Leave out map tables left behind by previous merges.
+    Ok(
+        names
+            .iter()
+            .filter(|name| {
+                !name
+                    .strip_suffix("_id_map")
+                    .is_some_and(|model_name| names.iter().any(|other| other == model_name))
+            })
+            .cloned()
+            .collect()
+    )
+}
+
+fn introspect_table(conn: &Connection, table: &str, warnings: &mut Vec<String>) -> Result<Model, String> {
+    let table_columns = query_rows(
+        conn,
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?1) ORDER BY cid",
+        [table],
+        |row| Ok(TableColumn {
+            name: row.get(0)?,
+            declared_type: row.get(1)?,
+            not_null: row.get(2)?,
+            default: row.get(3)?,
+            primary_key_position: row.get(4)?
+        })
+    )?;
+
+    let primary_key_count = table_columns.iter().filter(|column| column.primary_key_position > 0).count();
+
+    if primary_key_count != 1 {
+        warnings.push(format!("{} doesn't have a single-column primary key, so it can't be merged and is copied according to its passthrough policy", table));
+    }
+
+    let mut columns: Vec<Column> = table_columns
+        .into_iter()
+        .map(|column| {
+            let key = column.primary_key_position > 0 && primary_key_count == 1;
+            let ty = prisma_type(&column.declared_type);
+
+            if key && ty != "String" {
+                warnings.push(format!(
+                    "{}'s primary key {} has type {}, but only String primary keys can be merged, so it's copied according to its passthrough policy",
+                    table, column.name, ty
+                ));
+            }
+
+            Column {
+                ty: ColumnType {
+                    name: ty.to_string(),
+                    collection: false,
+                    nullable: !column.not_null && !key
+                },
+                name: column.name,
+                primary_key: key && ty == "String",
+                unique: key && ty != "String",
+                default: literal_default(column.default),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    // Indices, skipping the one SQLite creates for the primary key.
+    let index_names = query_rows(
+        conn,
+        "SELECT name, \"unique\" FROM pragma_index_list(?1) WHERE origin != 'pk' ORDER BY seq DESC",
+        [table],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?))
+    )?;
+
+    let mut indices: Vec<Index> = vec![];
+
+    for (index_name, unique) in index_names {
+        let column_names = query_rows(
+            conn,
+            "SELECT name FROM pragma_index_info(?1) ORDER BY seqno",
+            [&index_name],
+            |row| row.get::<_, Option<String>>(0)
+        )?;
+
+        // Indices on expressions have no column names.
+        if column_names.iter().any(|name| name.is_none()) {
+            continue;
+        }
+
+        let column_names: Vec<String> = column_names.into_iter().flatten().collect();
+
+        if unique && column_names.len() == 1 {
+            if let Some(column) = columns.iter_mut().find(|column| column.name == column_names[0]) {
+                column.unique = true;
+            }
+        }
+
+        indices.push(Index { column_names, unique });
+    }
+
+    // Foreign keys, one row per column.
+    let foreign_key_rows = query_rows(
+        conn,
+        "SELECT id, \"table\", \"from\", \"to\" FROM pragma_foreign_key_list(?1) ORDER BY id, seq",
+        [table],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, Option<String>>(3)?))
+    )?;
+
+    let mut foreign_keys: Vec<(i64, ForeignKey)> = vec![];
+
+    for (id, parent, from, to) in foreign_key_rows {
+        if foreign_keys.last().map(|(last_id, _)| *last_id != id).unwrap_or(true) {
+            foreign_keys.push((id, ForeignKey { table: parent, from: vec![], to: vec![] }));
+        }
+
+        let (_, fk) = foreign_keys.last_mut().unwrap();
+        fk.from.push(from);
+        fk.to.push(to);
+    }
+
+    // Relation columns are named after the parent model. If the same parent is
+    // referenced more than once, the names are disambiguated using the foreign key
+    // columns instead.
+    let mut parent_counts: HashMap<&str, usize> = HashMap::new();
+
+    for (_, fk) in foreign_keys.iter() {
+        *parent_counts.entry(fk.table.as_str()).or_default() += 1;
+    }
+
+    let mut relation_columns: Vec<Column> = vec![];
+
+    for (_, fk) in foreign_keys.iter() {
+        let name = if parent_counts[fk.table.as_str()] > 1 {
+            format!("{}_{}", lower_first(&fk.table), fk.from.join("_"))
+        } else {
+            lower_first(&fk.table)
+        };
+
+        let nullable = fk.from.iter().any(|from| {
+            columns.iter().any(|column| &column.name == from && column.ty.nullable)
+        });
+
+        relation_columns.push(Column {
+            name,
+            ty: ColumnType { name: fk.table.clone(), collection: false, nullable },
+            relation: Some(Relation {
+                fields: fk.from.clone(),
+                // A foreign key without target columns references the primary key,
+                // which is filled in once all tables have been introspected.
+                references: fk.to.iter().map(|to| to.clone().unwrap_or_default()).collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+    }
+
+    columns.append(&mut relation_columns);
+
+    // Prefer a unique index to detect duplicate rows with, since it's what Prisma
+    // would use.
+    let unique = indices
+        .iter()
+        .find(|index| index.unique)
+        .map(|index| Unique { column_names: index.column_names.clone() });
+
+    let mut model = Model::new(table.to_string(), columns, unique);
+    model.indices = indices;
+    Ok(model)
+}
+
+// Build a schema from the tables in the given database.
+pub fn introspect(conn: &Connection) -> Result<Introspection, String> {
+    let mut schema = Schema::new();
+    let mut warnings = vec![];
+
+    for table in table_names(conn)? {
+        let model = introspect_table(conn, &table, &mut warnings)?;
+        schema.models.insert(model.name.clone(), model);
+    }
+
+    // Fill in references to primary keys, and drop relations to tables that don't exist.
+    // Primary keys that can't be merged count too, since foreign keys still refer to them.
+    let mut primary_keys: HashMap<String, Vec<String>> = HashMap::new();
+
+    for name in schema.models.keys() {
+        let key_columns = query_rows(
+            conn,
+            "SELECT name FROM pragma_table_info(?1) WHERE pk > 0 ORDER BY pk",
+            [name],
+            |row| row.get::<_, String>(0)
+        )?;
+
+        primary_keys.insert(name.clone(), key_columns);
+    }
+
+    for model in schema.models.values_mut() {
+        let model_name = model.name.clone();
+
+        model.columns.retain(|column| {
+            if column.relation.is_some() && !primary_keys.contains_key(&column.ty.name) {
+                warnings.push(format!("Ignoring foreign key from {} to {}, which doesn't exist", model_name, column.ty.name));
+                return false;
+            }
+
+            true
+        });
+
+        for column in model.columns.iter_mut() {
+            let Some(relation) = &mut column.relation else { continue };
+
+            for (idx, reference) in relation.references.iter_mut().enumerate().filter(|(_, reference)| reference.is_empty()) {
+                *reference = primary_keys[&column.ty.name].get(idx).cloned().unwrap_or_default();
+            }
+        }
+    }
+
+    Ok(Introspection { schema, warnings })
+}
+
+// Convert a default value stored as an SQL literal into Prisma syntax.
+fn prisma_default(column: &Column) -> Option<String> {
+    let default = column.default.as_ref()?;
+
+    if let Some(string) = default.strip_prefix('\'').and_then(|default| default.strip_suffix('\'')) {
+        let string = string.replace("''", "'").replace('\\', "\\\\").replace('"', "\\\"");
+        return Some(format!("\"{}\"", string));
+    }
+
+    match (column.ty.name.as_str(), default.as_str()) {
+        ("Boolean", "1") => Some("true".to_string()),
+        ("Boolean", "0") => Some("false".to_string()),
+        _ => Some(default.clone())
+    }
+}
+
+// Render the schema as a Prisma schema file. Back relations, which Prisma requires but
+// introspection doesn't produce, are added to the parent models. Relations between the
+// same pair of models are given names to tell them apart.
+pub fn to_prisma(schema: &Schema) -> String {
+    let mut model_names: Vec<&String> = schema.models.keys().collect();
+    model_names.sort();
+
+    // Count relations between each pair of models to find the ambiguous ones.
+    let mut pair_counts: HashMap<(&str, &str), usize> = HashMap::new();
+
+    for model in schema.models.values() {
+        for column in model.columns.iter().filter(|column| column.relation.is_some()) {
+            *pair_counts.entry((model.name.as_str(), column.ty.name.as_str())).or_default() += 1;
+        }
+    }
+
+    let relation_name = |child: &str, column: &Column| -> Option<String> {
+        if pair_counts[&(child, column.ty.name.as_str())] > 1 {
+            Some(format!("{}_{}", child, column.relation.as_ref().unwrap().fields.join("_")))
+        } else {
+            None
+        }
+    };
+
+    let mut output = String::from("datasource db {\n  provider = \"sqlite\"\n  url      = env(\"DATABASE_URL\")\n}\n");
+
+    for model_name in model_names {
+        let model = &schema.models[model_name];
+
+        // Each line is made up of a name, a type, and attributes, which are aligned
+        // the same way `prisma format` aligns them.
+        let mut lines: Vec<(String, String, Vec<String>)> = vec![];
+
+        for column in model.columns.iter() {
+            let mut ty = column.ty.name.clone();
+            let mut attributes = vec![];
+
+            if column.ty.nullable {
+                ty.push('?');
+            }
+
+            if column.primary_key {
+                attributes.push("@id".to_string());
+            }
+
+            if let Some(default) = prisma_default(column) {
+                attributes.push(format!("@default({})", default));
+            }
+
+            if column.unique {
+                attributes.push("@unique".to_string());
+            }
+
+            if let Some(relation) = &column.relation {
+                let name = relation_name(&model.name, column).map(|name| format!("\"{}\", ", name)).unwrap_or_default();
+
+                attributes.push(format!(
+                    "@relation({}fields: [{}], references: [{}])",
+                    name,
+                    relation.fields.join(", "),
+                    relation.references.join(", ")
+                ));
+            }
+
+            lines.push((column.name.clone(), ty, attributes));
+        }
+
+        // Back relations from the models that reference this one.
+        let mut child_names: Vec<&String> = schema.models.keys().collect();
+        child_names.sort();
+
+        for child_name in child_names {
+            let child = &schema.models[child_name];
+
+            for column in child.columns.iter().filter(|column| column.relation.is_some() && &column.ty.name == model_name) {
+                let (name, attributes) = match relation_name(&child.name, column) {
+                    Some(relation_name) => (format!("{}_{}", lower_first(&child.name), column.name), vec![format!("@relation(\"{}\")", relation_name)]),
+                    None => (lower_first(&child.name), vec![])
+                };
+
+                lines.push((name, format!("{}[]", child.name), attributes));
+            }
+        }
+
+        let name_width = lines.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0);
+        let type_width = lines.iter().map(|(_, ty, _)| ty.len()).max().unwrap_or(0);
+
+        output.push_str(&format!("\nmodel {} {{\n", model.name));
+
+        for (name, ty, attributes) in lines {
+            let line = format!("  {:name_width$} {:type_width$} {}", name, ty, attributes.join(" "));
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+
+        let block_indices: Vec<&Index> = model.indices
+            .iter()
+            .filter(|index| !(index.unique && index.column_names.len() == 1))
+            .collect();
+
+        if !block_indices.is_empty() {
+            output.push('\n');
+        }
+
+        for index in block_indices {
+            let kind = if index.unique { "unique" } else { "index" };
+            output.push_str(&format!("  @@{}([{}])\n", kind, index.column_names.join(", ")));
+        }
+
+        output.push_str("}\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::introspect::*;
+    use crate::prisma_parser;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "active" BOOLEAN NOT NULL DEFAULT 1);
+            CREATE UNIQUE INDEX "Owner_name_key" ON "Owner"("name");
+            CREATE TABLE "Toy" (
+                "id" INTEGER PRIMARY KEY,
+                "name" TEXT NOT NULL,
+                "status" TEXT DEFAULT 'new',
+                "ownerId" TEXT NOT NULL REFERENCES "Owner",
+                "previousOwnerId" TEXT REFERENCES "Owner" ("id"),
+                "createdAt" DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE UNIQUE INDEX "Toy_name_ownerId_key" ON "Toy"("name", "ownerId");
+            CREATE TABLE "Tag" ("toyId" INTEGER, "label" TEXT, PRIMARY KEY ("toyId", "label"));
+            CREATE TABLE "Toy_id_map" (old_id TEXT, new_id TEXT);
+        "#).unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn introspects_tables() {
+        let introspection = introspect(&setup()).unwrap();
+        let schema = &introspection.schema;
+
+        assert!(schema.models.len() == 3);
+
+        let owner = &schema.models["Owner"];
+        assert!(owner.primary_key().unwrap().name == "id");
+        assert!(owner.unique.as_ref().unwrap().column_names == vec!["name"]);
+        assert!(owner.get_col("active").unwrap().ty.name == "Boolean");
+
+        // Integer primary keys can't be merged, so they're left as unique columns.
+        let toy = &schema.models["Toy"];
+        assert!(toy.primary_key().is_none());
+        assert!(toy.get_col("id").unwrap().unique);
+        assert!(toy.get_col("id").unwrap().ty.name == "Int");
+        assert!(toy.unique.as_ref().unwrap().column_names == vec!["name", "ownerId"]);
+        assert!(toy.get_col("status").unwrap().default.as_deref() == Some("'new'"));
+        assert!(toy.get_col("createdAt").unwrap().default.is_none());
+
+        let owner_relation = toy.get_col("owner_ownerId").unwrap();
+        assert!(owner_relation.relation.as_ref().unwrap().references == vec!["id"]);
+        assert!(!owner_relation.ty.nullable);
+        assert!(toy.get_col("owner_previousOwnerId").unwrap().ty.nullable);
+
+        // Composite primary keys aren't supported either.
+        assert!(schema.models["Tag"].primary_key().is_none());
+        assert!(introspection.warnings.len() == 2);
+    }
+
+    #[test]
+    fn generates_a_valid_prisma_schema() {
+        let introspection = introspect(&setup()).unwrap();
+        let prisma = to_prisma(&introspection.schema);
+
+        // Compare lines ignoring alignment.
+        let has_line = |expected: &str| {
+            prisma.lines().any(|line| line.split_whitespace().collect::<Vec<&str>>().join(" ") == expected)
+        };
+
+        assert!(has_line("id String @id"));
+        assert!(has_line("active Boolean @default(true)"));
+        assert!(has_line("status String? @default(\"new\")"));
+        assert!(has_line("owner_ownerId Owner @relation(\"Toy_ownerId\", fields: [ownerId], references: [id])"));
+        assert!(has_line("toy_owner_ownerId Toy[] @relation(\"Toy_ownerId\")"));
+        assert!(has_line("@@unique([name, ownerId])"));
+
+        // The generated schema can be parsed again.
+        let schema = prisma_parser::parse(&prisma).unwrap();
+        assert!(schema.models["Toy"].get_col("status").unwrap().default.as_deref() == Some("'new'"));
+        assert!(schema.models["Toy"].get_col("owner_ownerId").unwrap().relation.is_some());
+    }
+}
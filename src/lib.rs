@@ -1,5 +1,18 @@
+pub mod config;
+pub mod conflicts;
 pub mod data;
+pub mod ddl;
+pub mod extract;
 pub mod insert_manager;
+pub mod inspect;
+pub mod introspect;
+pub mod lint;
+pub mod migrations;
+pub mod normalize;
+pub mod preflight;
 pub mod prisma_parser;
 pub mod progress;
+pub mod rejects;
+pub mod report;
 pub mod utils;
+pub mod verify;
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use crate::data::{Model, PassthroughPolicy, Schema};
+use crate::inspect::{self, IssueKind};
+use crate::preflight::Severity;
+
+/* Linting checks the Prisma schema for shapes prismerge can't merge safely, without
+ * looking at any data. Errors are problems that make the merge fail or corrupt the
+ * output, eg. integer primary keys, which prismerge replaces with UUIDs in rows from
+ * secondary inputs. Warnings are problems that make the merge produce duplicate rows.
+ *
+ * Each lint names the rule that produced it so schema reviewers can look it up in
+ * the README.
+ */
+#[derive(Debug)]
+pub struct Lint {
+    pub severity: Severity,
+    pub rule: &'static str,
+    pub model: String,
+    pub message: String
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+
+        write!(f, "{}[{}]: {}: {}", severity, self.rule, self.model, self.message)
+    }
+}
+
+struct Linter<'a> {
+    schema: &'a Schema,
+    lints: Vec<Lint>
+}
+
+impl Linter<'_> {
+    fn add(self: &mut Self, severity: Severity, rule: &'static str, model: &Model, message: String) {
+        self.lints.push(Lint { severity, rule, model: model.name.clone(), message });
+    }
+
+    fn check_primary_key(self: &mut Self, model: &Model) {
+        // Models without a primary key can't be merged, but they can be copied.
+        let Some(primary_key) = model.primary_key() else {
+            if model.passthrough.policy == PassthroughPolicy::Skip {
+                self.add(
+                    Severity::Warning,
+                    "primary-key",
+                    model,
+                    "has no single-column primary key, so it's skipped unless it's given a passthrough policy".to_string()
+                );
+            }
+
+            return;
+        };
+
+        if primary_key.ty.name != "String" {
+            self.add(
+                Severity::Error,
+                "primary-key",
+                model,
+                format!("primary key {} has type {}, but rows from secondary inputs are given UUID primary keys", primary_key.name, primary_key.ty.name)
+            );
+        } else if primary_key.default_function.as_deref() != Some("uuid") {
+            self.add(
+                Severity::Warning,
+                "primary-key",
+                model,
+                format!("primary key {} doesn't default to uuid(), but rows from secondary inputs are given UUID primary keys", primary_key.name)
+            );
+        }
+    }
+
+    fn check_unique(self: &mut Self, model: &Model) {
+        let Some(unique) = &model.unique else {
+            if !model.dedupe_by_hash {
+                self.add(Severity::Warning, "missing-unique", model, "has no unique constraint, so rows will be duplicated".to_string());
+            }
+
+            return;
+        };
+
+        for column_name in unique.column_names.iter() {
+            let Some(column) = model.get_col(column_name) else { continue };
+
+            // NULL never equals NULL, so rows with NULLs in their unique key never
+            // match existing rows.
+            if column.ty.nullable {
+                self.add(
+                    Severity::Warning,
+                    "nullable-unique",
+                    model,
+                    format!("unique key column {} is nullable, so rows where it's NULL will be duplicated", column.name)
+                );
+            }
+
+            if column.updated_at || column.default_function.as_deref() == Some("now") {
+                self.add(
+                    Severity::Warning,
+                    "timestamp-unique",
+                    model,
+                    format!("unique key column {} is set to the current time, so it rarely matches across inputs", column.name)
+                );
+            }
+        }
+    }
+
+    fn check_relations(self: &mut Self, model: &Model) {
+        for column in model.columns.iter() {
+            let Some(relation) = &column.relation else { continue };
+            let Some(parent) = self.schema.models.get(&column.ty.name) else { continue };
+
+            if relation.fields.is_empty() {
+                continue;
+            }
+
+            // Foreign keys are translated via the parent's ID map, which only knows
+            // about primary keys.
+            let references_primary_key = relation.references.len() == 1 &&
+                parent.primary_key().is_some_and(|primary_key| primary_key.name == relation.references[0]);
+
+            if !references_primary_key {
+                self.add(
+                    Severity::Error,
+                    "non-id-reference",
+                    model,
+                    format!("relation {} references {}.{}, which isn't its primary key", column.name, parent.name, relation.references.join(", "))
+                );
+            }
+        }
+    }
+}
+
+// Lint every model that will be merged, ordered by model name.
+pub fn lint(schema: &Schema) -> Vec<Lint> {
+    let mut linter = Linter { schema, lints: vec![] };
+
+    for issue in inspect::inspect(schema).issues.iter().filter(|issue| issue.kind == IssueKind::Cycle) {
+        linter.lints.push(Lint {
+            severity: Severity::Error,
+            rule: "cycle",
+            model: issue.model.clone(),
+            message: format!("{}, so it can't be merged", issue.message)
+        });
+    }
+
+    // Rows of ignored models aren't deduplicated via keys, so none of the rules apply.
+    let mut models: Vec<&Model> = schema.models.values().filter(|model| !model.skip && !model.ignored).collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for model in models {
+        linter.check_primary_key(model);
+
+        if model.primary_key().is_some() {
+            linter.check_unique(model);
+        }
+
+        linter.check_relations(model);
+    }
+
+    linter.lints.sort_by(|a, b| a.model.cmp(&b.model));
+    linter.lints
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::*;
+    use crate::prisma_parser;
+
+    #[test]
+    fn accepts_merge_safe_schemas() {
+        let schema = prisma_parser::parse(r#"
+            model Owner {
+              id   String @id @default(uuid())
+              name String @unique
+            }
+
+            model Toy {
+              id      String @id @default(uuid())
+              name    String
+              ownerId String
+              owner   Owner  @relation(fields: [ownerId], references: [id])
+
+              @@unique([name, ownerId])
+            }
+        "#).unwrap();
+
+        assert!(lint(&schema).is_empty());
+    }
+
+    #[test]
+    fn reports_merge_safety_problems() {
+        let schema = prisma_parser::parse(r#"
+            model Owner {
+              id        Int      @id @default(autoincrement())
+              name      String
+              email     String   @unique
+            }
+
+            model Toy {
+              id         String   @id
+              name       String?
+              createdAt  DateTime @default(now())
+              ownerEmail String
+              owner      Owner    @relation(fields: [ownerEmail], references: [email])
+
+              @@unique([name, createdAt])
+            }
+        "#).unwrap();
+
+        let lints: Vec<String> = lint(&schema).iter().map(|lint| lint.to_string()).collect();
+
+        assert!(lints == vec![
+            "error[primary-key]: Owner: primary key id has type Int, but rows from secondary inputs are given UUID primary keys",
+            "warning[primary-key]: Toy: primary key id doesn't default to uuid(), but rows from secondary inputs are given UUID primary keys",
+            "warning[nullable-unique]: Toy: unique key column name is nullable, so rows where it's NULL will be duplicated",
+            "warning[timestamp-unique]: Toy: unique key column createdAt is set to the current time, so it rarely matches across inputs",
+            "error[non-id-reference]: Toy: relation owner references Owner.email, which isn't its primary key"
+        ]);
+    }
+
+    #[test]
+    fn reports_cycles_and_missing_uniques() {
+        let schema = prisma_parser::parse(r#"
+            model Category {
+              id       String    @id @default(uuid())
+              parentId String?
+              parent   Category? @relation("Tree", fields: [parentId], references: [id])
+            }
+        "#).unwrap();
+
+        let lints: Vec<String> = lint(&schema).iter().map(|lint| lint.to_string()).collect();
+
+        assert!(lints == vec![
+            "error[cycle]: Category: references itself, so it can't be merged",
+            "warning[missing-unique]: Category: has no unique constraint, so rows will be duplicated"
+        ]);
+    }
+
+    #[test]
+    fn warns_about_skipped_keyless_models() {
+        let schema = prisma_parser::parse(r#"
+            model Visit {
+              day String
+            }
+
+            /// @prismerge.passthrough(union: dedup)
+            model Log {
+              message String
+            }
+        "#).unwrap();
+
+        let lints: Vec<String> = lint(&schema).iter().map(|lint| lint.to_string()).collect();
+
+        assert!(lints == vec![
+            "warning[primary-key]: Visit: has no single-column primary key, so it's skipped unless it's given a passthrough policy"
+        ]);
+    }
+}
@@ -0,0 +1,324 @@
+use rusqlite::{types::Value, Connection};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, fs, path::{Path, PathBuf}};
+use uuid::Uuid;
+
+use crate::config::Input;
+
+pub const MIGRATIONS_TABLE: &str = "_prisma_migrations";
+
+// The columns of Prisma's migrations table, in order.
+const MIGRATIONS_COLUMNS: &[&str] = &[
+    "id",
+    "checksum",
+    "finished_at",
+    "migration_name",
+    "logs",
+    "rolled_back_at",
+    "started_at",
+    "applied_steps_count"
+];
+
+/* A single migration from a Prisma migrations folder. Each migration lives in its own
+ * folder named after the time it was created, eg. 20240101000000_init, and contains a
+ * migration.sql file.
+ */
+#[derive(Debug)]
+pub struct Migration {
+    pub name: String,
+    pub sql: String
+}
+
+impl Migration {
+    // Prisma identifies the contents of a migration by its SHA-256 checksum.
+    pub fn checksum(self: &Self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// Read the migrations in the given folder, sorted by name (and therefore by the time
+// they were created).
+pub fn read_migrations(migrations_path: &Path) -> Result<Vec<Migration>, String> {
+    let entries = fs::read_dir(migrations_path)
+        .map_err(|e| format!("Unable to read migrations from {}: {}", migrations_path.display(), e))?;
+
+    let mut migrations = vec![];
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path().join("migration.sql");
+
+        if !path.is_file() {
+            continue;
+        }
+
+        migrations.push(Migration {
+            name: entry.file_name().to_string_lossy().to_string(),
+            sql: fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?
+        });
+    }
+
+    if migrations.is_empty() {
+        return Err(format!("No migrations found in {}", migrations_path.display()));
+    }
+
+    migrations.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(migrations)
+}
+
+fn has_migrations_table(conn: &Connection) -> bool {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [MIGRATIONS_TABLE],
+        |row| row.get::<_, usize>(0)
+    ).unwrap_or(0) > 0
+}
+
+// Return the names of the migrations that have been successfully applied to the given
+// database, or None if the database has no migrations table.
+pub fn applied_migrations(conn: &Connection) -> Result<Option<HashSet<String>>, String> {
+    if !has_migrations_table(conn) {
+        return Ok(None);
+    }
+
+    let mut stmt = conn.prepare(
+        format!("SELECT migration_name FROM \"{}\" WHERE finished_at IS NOT NULL AND rolled_back_at IS NULL", MIGRATIONS_TABLE).as_str()
+    ).map_err(|e| e.to_string())?;
+
+    let names = stmt
+        .query_map((), |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<HashSet<String>>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(Some(names))
+}
+
+// Apply the given migrations to the database unless they've been applied already,
+// recording each one in the migrations table the same way Prisma does. Each migration
+// is applied in its own transaction. Returns the names of the migrations applied.
+pub fn apply_pending(conn: &Connection, migrations: &[Migration]) -> Result<Vec<String>, String> {
+    let applied = applied_migrations(conn)?.unwrap_or_default();
+    let mut newly_applied = vec![];
+
+    for migration in migrations.iter().filter(|migration| !applied.contains(&migration.name)) {
+        let record_sql = format!(
+            "INSERT INTO \"{}\" (id, checksum, finished_at, migration_name, started_at, applied_steps_count) VALUES (?1, ?2, current_timestamp, ?3, current_timestamp, 1)",
+            MIGRATIONS_TABLE
+        );
+
+        conn.execute_batch(format!("BEGIN;\n{}\n;", migration.sql).as_str())
+            .and_then(|_| conn.execute(record_sql.as_str(), (Uuid::new_v4().to_string(), migration.checksum(), &migration.name)))
+            .and_then(|_| conn.execute_batch("COMMIT;"))
+            .map_err(|e| {
+                let _ = conn.execute_batch("ROLLBACK;");
+                format!("Unable to apply migration {}: {}", migration.name, e)
+            })?;
+
+        newly_applied.push(migration.name.clone());
+    }
+
+    Ok(newly_applied)
+}
+
+/* Copies of the input databases with pending migrations applied. The copies live in a
+ * temporary directory that is removed when this struct is dropped, so it must outlive
+ * any connections to the copies.
+ */
+pub struct MigratedInputs {
+    pub dir: PathBuf,
+    pub paths: Vec<String>
+}
+
+impl MigratedInputs {
+    // Copy each input into a temporary directory and bring it up to date by applying
+    // any pending migrations. Inputs without a migrations table aren't managed by
+    // Prisma Migrate and are copied as-is.
+    pub fn create(inputs: &[Input], migrations: &[Migration]) -> Result<Self, String> {
+        let dir = std::env::temp_dir().join(format!("prismerge-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).map_err(|e| format!("Unable to create {}: {}", dir.display(), e))?;
+
+        // Construct the struct right away so the directory is cleaned up on error.
+        let mut migrated = MigratedInputs { dir, paths: vec![] };
+
+        for (idx, input) in inputs.iter().enumerate() {
+            let copy_path = migrated.dir.join(format!("{}.db", idx));
+            let copy_path_str = copy_path.to_string_lossy().to_string();
+
+            // VACUUM INTO produces a consistent copy even if the input is in WAL mode.
+            Connection::open(&input.path)
+                .and_then(|conn| conn.execute("VACUUM INTO ?1", [&copy_path_str]))
+                .map_err(|e| format!("Unable to copy {}: {}", input.path, e))?;
+
+            let conn = Connection::open(&copy_path).map_err(|e| e.to_string())?;
+
+            if has_migrations_table(&conn) {
+                let applied = apply_pending(&conn, migrations).map_err(|e| format!("{}: {}", input.label, e))?;
+
+                if !applied.is_empty() {
+                    println!("Applied {} pending migrations to {}: {}", applied.len(), input.label, applied.join(", "));
+                }
+            } else {
+                println!("Not migrating {} because it has no {} table", input.label, MIGRATIONS_TABLE);
+            }
+
+            migrated.paths.push(copy_path_str);
+        }
+
+        Ok(migrated)
+    }
+}
+
+impl Drop for MigratedInputs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Merge the migrations tables of all the inputs into the merged database. Rows are
+// identified by migration name, so each migration appears only once, taken from the
+// first input that applied it successfully. Failed and rolled back attempts are left
+// out. Does nothing if none of the inputs have a migrations table.
+pub fn merge_history(connections: &[Connection], merged: &Connection) -> Result<usize, String> {
+    let sources: Vec<&Connection> = connections.iter().filter(|conn| has_migrations_table(conn)).collect();
+
+    if sources.is_empty() {
+        return Ok(0);
+    }
+
+    // The output schema may not include the migrations table, eg. if it was generated
+    // from the Prisma schema.
+    merged.execute(
+        format!(r#"
+            CREATE TABLE IF NOT EXISTS "{}" (
+                "id"                    TEXT PRIMARY KEY NOT NULL,
+                "checksum"              TEXT NOT NULL,
+                "finished_at"           DATETIME,
+                "migration_name"        TEXT NOT NULL,
+                "logs"                  TEXT,
+                "rolled_back_at"        DATETIME,
+                "started_at"            DATETIME NOT NULL DEFAULT current_timestamp,
+                "applied_steps_count"   INTEGER UNSIGNED NOT NULL DEFAULT 0
+            )
+        "#, MIGRATIONS_TABLE).as_str(),
+        ()
+    ).map_err(|e| e.to_string())?;
+
+    let columns = MIGRATIONS_COLUMNS.join(", ");
+    let placeholders = (1..=MIGRATIONS_COLUMNS.len()).map(|idx| format!("?{}", idx)).collect::<Vec<String>>().join(", ");
+    let select_sql = format!(
+        "SELECT {} FROM \"{}\" WHERE finished_at IS NOT NULL AND rolled_back_at IS NULL ORDER BY started_at, migration_name",
+        columns,
+        MIGRATIONS_TABLE
+    );
+
+    let insert_sql = format!(
+        "INSERT INTO \"{table}\" ({columns}) SELECT {placeholders} WHERE NOT EXISTS (SELECT 1 FROM \"{table}\" WHERE migration_name = ?4)",
+        table = MIGRATIONS_TABLE
+    );
+
+    let mut inserted = 0;
+
+    for conn in sources {
+        let mut stmt = conn.prepare(select_sql.as_str()).map_err(|e| e.to_string())?;
+
+        let rows = stmt
+            .query_map((), |row| {
+                (0..MIGRATIONS_COLUMNS.len())
+                    .map(|idx| row.get::<_, Value>(idx))
+                    .collect::<rusqlite::Result<Vec<Value>>>()
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<Vec<Value>>>>()
+            .map_err(|e| e.to_string())?;
+
+        for row in rows {
+            inserted += merged
+                .execute(insert_sql.as_str(), rusqlite::params_from_iter(row))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrations::*;
+
+    fn migrations() -> Vec<Migration> {
+        vec![
+            Migration { name: "20240101000000_init".to_string(), sql: "CREATE TABLE \"Toy\" (\"id\" TEXT NOT NULL PRIMARY KEY);".to_string() },
+            Migration { name: "20240201000000_add_name".to_string(), sql: "ALTER TABLE \"Toy\" ADD COLUMN \"name\" TEXT;".to_string() }
+        ]
+    }
+
+    fn create_migrated_db(migrations: &[Migration]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "_prisma_migrations" (
+                "id"                    TEXT PRIMARY KEY NOT NULL,
+                "checksum"              TEXT NOT NULL,
+                "finished_at"           DATETIME,
+                "migration_name"        TEXT NOT NULL,
+                "logs"                  TEXT,
+                "rolled_back_at"        DATETIME,
+                "started_at"            DATETIME NOT NULL DEFAULT current_timestamp,
+                "applied_steps_count"   INTEGER UNSIGNED NOT NULL DEFAULT 0
+            );
+        "#).unwrap();
+
+        apply_pending(&conn, migrations).unwrap();
+        conn
+    }
+
+    #[test]
+    fn computes_checksums() {
+        let migration = Migration { name: "init".to_string(), sql: "abc".to_string() };
+        assert!(migration.checksum() == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn applies_pending_migrations() {
+        let migrations = migrations();
+        let conn = create_migrated_db(&migrations[0..1]);
+
+        let applied = apply_pending(&conn, &migrations).unwrap();
+        assert!(applied == vec!["20240201000000_add_name"]);
+
+        conn.execute("INSERT INTO \"Toy\" (id, name) VALUES ('t1', 'Rex')", ()).unwrap();
+        assert!(applied_migrations(&conn).unwrap().unwrap().len() == 2);
+
+        // Applying again is a no-op.
+        assert!(apply_pending(&conn, &migrations).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rolls_back_failed_migrations() {
+        let conn = create_migrated_db(&[]);
+
+        let broken = vec![Migration {
+            name: "20240101000000_broken".to_string(),
+            sql: "CREATE TABLE \"Toy\" (\"id\" TEXT); CREATE TABLE \"Toy\" (\"id\" TEXT);".to_string()
+        }];
+
+        assert!(apply_pending(&conn, &broken).is_err());
+        assert!(applied_migrations(&conn).unwrap().unwrap().is_empty());
+        assert!(conn.prepare("SELECT * FROM \"Toy\"").is_err());
+    }
+
+    #[test]
+    fn merges_history_by_migration_name() {
+        let migrations = migrations();
+        let connections = vec![create_migrated_db(&migrations[0..1]), create_migrated_db(&migrations)];
+        let merged = Connection::open_in_memory().unwrap();
+
+        assert!(merge_history(&connections, &merged).unwrap() == 2);
+
+        let names = applied_migrations(&merged).unwrap().unwrap();
+        assert!(names.len() == 2);
+    }
+}
@@ -0,0 +1,200 @@
+use rusqlite::{functions::FunctionFlags, types::{Value, ValueRef}, Connection};
+use serde::Serialize;
+use std::str::FromStr;
+
+// The SQL function registered by `register_functions()` that canonicalizes JSON.
+const CANONICAL_JSON_FUNCTION: &str = "prismerge_canonical_json";
+
+/* Normalizers are applied to both sides of a unique key comparison so that values
+ * which differ only superficially, eg. "Woody" and "woody ", are treated as
+ * duplicates. Each normalizer wraps a SQL expression in another SQL expression.
+ *
+ * Lowercase:      lowercases strings.
+ * Trim:           removes leading and trailing whitespace.
+ * Json:           parses JSON and re-serializes it without whitespace and with object
+ *                 keys in sorted order. Values that aren't valid JSON are left alone.
+ * Truncate(unit): converts timestamps to milliseconds since the epoch and truncates
+ *                 them to the given unit, eg. so rows written a few milliseconds apart
+ *                 match.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalizer {
+    Lowercase,
+    Trim,
+    Json,
+    Truncate(TimeUnit)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    Second,
+    Minute,
+    Hour,
+    Day
+}
+
+impl TimeUnit {
+    fn millis(self: &Self) -> u64 {
+        match self {
+            TimeUnit::Second => 1000,
+            TimeUnit::Minute => 60 * 1000,
+            TimeUnit::Hour => 60 * 60 * 1000,
+            TimeUnit::Day => 24 * 60 * 60 * 1000
+        }
+    }
+}
+
+impl FromStr for TimeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "second" => Ok(TimeUnit::Second),
+            "minute" => Ok(TimeUnit::Minute),
+            "hour" => Ok(TimeUnit::Hour),
+            "day" => Ok(TimeUnit::Day),
+            _ => Err(format!("Unknown time unit '{}', expected one of second, minute, hour, or day", s))
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn apply(self: &Self, expr: &str) -> String {
+        match self {
+            Normalizer::Lowercase => format!("lower({})", expr),
+            Normalizer::Trim => format!("trim({})", expr),
+            Normalizer::Json => format!("{}({})", CANONICAL_JSON_FUNCTION, expr),
+            Normalizer::Truncate(unit) => format!("({} / {millis} * {millis})", epoch_millis(expr), millis = unit.millis())
+        }
+    }
+}
+
+impl FromStr for Normalizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("truncate", unit)) => Ok(Normalizer::Truncate(unit.trim().parse::<TimeUnit>()?)),
+            _ => match s {
+                "lowercase" => Ok(Normalizer::Lowercase),
+                "trim" => Ok(Normalizer::Trim),
+                "json" => Ok(Normalizer::Json),
+                _ => Err(format!("Unknown normalizer '{}', expected one of lowercase, trim, json, or truncate:<unit>", s))
+            }
+        }
+    }
+}
+
+// Convert a timestamp to milliseconds since the epoch. Prisma stores DateTime values
+// in SQLite as milliseconds, but other tools write ISO 8601 strings, which are parsed
+// by julianday().
+fn epoch_millis(expr: &str) -> String {
+    format!(
+        "(CASE WHEN typeof({expr}) IN ('integer', 'real') THEN CAST({expr} AS INTEGER) ELSE CAST(round((julianday({expr}) - 2440587.5) * 86400000) AS INTEGER) END)",
+        expr = expr
+    )
+}
+
+// Convert a value to a canonical form based on the type of the column it belongs to,
+// so eg. 1.0 and 1 in a Float column match. Strings and other types are compared as-is,
+// as are Decimals, since converting them to floating point would lose precision.
+pub fn normalize_type(type_name: &str, expr: &str) -> String {
+    match type_name {
+        "Float" => format!("CAST({} AS REAL)", expr),
+        "Boolean" => format!("(CASE lower(CAST({expr} AS TEXT)) WHEN 'true' THEN 1 WHEN 'false' THEN 0 ELSE CAST({expr} AS INTEGER) END)", expr = expr),
+        "DateTime" => epoch_millis(expr),
+        "Json" => Normalizer::Json.apply(expr),
+        _ => expr.to_string()
+    }
+}
+
+// Apply all the given normalizers to the given SQL expression, in order.
+pub fn normalize(normalizers: &[Normalizer], expr: &str) -> String {
+    normalizers
+        .iter()
+        .fold(expr.to_string(), |expr, normalizer| normalizer.apply(&expr))
+}
+
+// Normalize a value of the given type, then apply the given normalizers.
+pub fn normalize_typed(type_name: &str, normalizers: &[Normalizer], expr: &str) -> String {
+    normalize(normalizers, &normalize_type(type_name, expr))
+}
+
+// Register the SQL functions used by normalizers on the given connection.
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        CANONICAL_JSON_FUNCTION,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            Ok(match ctx.get_raw(0) {
+                ValueRef::Text(text) => {
+                    let text = String::from_utf8_lossy(text);
+
+                    // serde_json sorts object keys, so re-serializing is enough.
+                    match serde_json::from_str::<serde_json::Value>(&text) {
+                        Ok(json) => Value::Text(json.to_string()),
+                        Err(_) => Value::Text(text.to_string())
+                    }
+                }
+
+                value => Value::from(value)
+            })
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::normalize::*;
+
+    fn eval(conn: &Connection, expr: &str) -> Value {
+        conn.query_row(format!("SELECT {}", expr).as_str(), (), |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn applies_normalizers_in_order() {
+        let normalizers = vec![Normalizer::Trim, Normalizer::Lowercase];
+        assert!(normalize(&normalizers, "\"name\"") == "lower(trim(\"name\"))");
+        assert!(normalize(&[], "\"name\"") == "\"name\"");
+    }
+
+    #[test]
+    fn normalizes_types_before_applying_normalizers() {
+        assert!(normalize_typed("Float", &[Normalizer::Trim], "\"price\"") == "trim(CAST(\"price\" AS REAL))");
+        assert!(normalize_typed("String", &[], "\"name\"") == "\"name\"");
+    }
+
+    #[test]
+    fn normalizes_values_by_type() {
+        let conn = Connection::open_in_memory().unwrap();
+        register_functions(&conn).unwrap();
+
+        let same = |type_name: &str, a: &str, b: &str| {
+            eval(&conn, &normalize_type(type_name, a)) == eval(&conn, &normalize_type(type_name, b))
+        };
+
+        assert!(same("Float", "1.0", "'1'"));
+        assert!(same("Boolean", "'true'", "1"));
+        assert!(same("Boolean", "'FALSE'", "0"));
+        assert!(same("DateTime", "'2024-01-02T03:04:05.678Z'", "1704164645678"));
+        assert!(same("DateTime", "'2024-01-02 03:04:05.678'", "1704164645678.0"));
+        assert!(same("Json", "'{\"b\": 1, \"a\": [1, 2]}'", "'{\"a\":[1,2],\"b\":1}'"));
+        assert!(!same("String", "'Woody'", "'woody'"));
+        assert!(!same("Decimal", "'0.10000000000000000001'", "'0.1'"));
+        assert!(eval(&conn, &normalize_type("DateTime", "NULL")) == Value::Null);
+    }
+
+    #[test]
+    fn truncates_timestamps() {
+        let conn = Connection::open_in_memory().unwrap();
+        let truncate = "truncate:second".parse::<Normalizer>().unwrap();
+
+        assert!(truncate == Normalizer::Truncate(TimeUnit::Second));
+        assert!(eval(&conn, &truncate.apply("'2024-01-02T03:04:05.678Z'")) == Value::Integer(1704164645000));
+        assert!(eval(&conn, &Normalizer::Truncate(TimeUnit::Day).apply("1704164645678")) == Value::Integer(1704153600000));
+        assert!("truncate:week".parse::<Normalizer>().is_err());
+    }
+}
@@ -0,0 +1,580 @@
+use rusqlite::Connection;
+use std::{collections::{BTreeMap, HashSet}, fmt};
+
+use crate::data::{Column, Enum, Model, Schema};
+use crate::migrations;
+use crate::utils::quote_literal;
+use crate::verify::{self, ForeignKeyViolation, UniqueViolation};
+
+/* Prismerge trusts the Prisma schema to describe the input databases. If an input
+ * was produced by a different version of the schema, the merge can fail halfway
+ * through, leaving a partially-written output database behind. The preflight check
+ * compares the schema with the actual tables in each input before anything is
+ * written and reports every problem it finds at once.
+ *
+ * Missing tables, missing required columns without default values, and columns
+ * whose declared types can't hold the schema's values, are errors. Missing columns
+ * that can be filled in are warnings. Missing foreign keys and unique indexes are only
+ * warnings, since prismerge reads relations and unique keys from the Prisma schema
+ * rather than the database, eg. inputs created with relationMode = "prisma" have no
+ * foreign keys at all.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub input: String,
+    pub model: String,
+    pub message: String
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+
+        write!(f, "{}: {}: {}: {}", severity, self.input, self.model, self.message)
+    }
+}
+
+// SQLite's type affinities, see https://www.sqlite.org/datatype3.html#type_affinity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric
+}
+
+// Determine the affinity of a declared column type using SQLite's rules.
+fn affinity(declared_type: &str) -> Affinity {
+    let declared_type = declared_type.to_uppercase();
+
+    if declared_type.contains("INT") {
+        Affinity::Integer
+    } else if ["CHAR", "CLOB", "TEXT"].iter().any(|t| declared_type.contains(t)) {
+        Affinity::Text
+    } else if declared_type.is_empty() || declared_type.contains("BLOB") {
+        Affinity::Blob
+    } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| declared_type.contains(t)) {
+        Affinity::Real
+    } else {
+        Affinity::Numeric
+    }
+}
+
+// The affinities able to hold values of the given Prisma scalar type. Returns None for
+// types that aren't scalars, eg. enums, which can be stored in any column.
+fn compatible_affinities(prisma_type: &str) -> Option<&'static [Affinity]> {
+    match prisma_type {
+        "String" => Some(&[Affinity::Text]),
+        "Int" | "BigInt" => Some(&[Affinity::Integer, Affinity::Numeric]),
+        "Float" => Some(&[Affinity::Real, Affinity::Numeric]),
+        "Decimal" => Some(&[Affinity::Numeric, Affinity::Real]),
+        "Boolean" => Some(&[Affinity::Numeric, Affinity::Integer]),
+        "DateTime" => Some(&[Affinity::Numeric, Affinity::Integer, Affinity::Text]),
+        "Json" => Some(&[Affinity::Text, Affinity::Blob]),
+        "Bytes" => Some(&[Affinity::Blob]),
+        _ => None
+    }
+}
+
+// Only scalar columns are stored in the model's table. Relation fields and back
+// relations exist in the Prisma schema only.
+fn is_stored(column: &Column, schema: &Schema) -> bool {
+    !column.ty.collection && !schema.models.contains_key(&column.ty.name)
+}
+
+fn has_column(table_columns: &[Vec<Option<String>>], name: &str) -> bool {
+    table_columns.iter().any(|row| row[0].as_deref() == Some(name))
+}
+
+struct ForeignKey {
+    id: String,
+    table: String,
+    from: Vec<String>,
+    to: Vec<Option<String>>
+}
+
+struct Checker<'a> {
+    schema: &'a Schema,
+    conn: &'a Connection,
+    input: &'a str,
+    problems: Vec<Problem>
+}
+
+impl<'a> Checker<'a> {
+    fn report(self: &mut Self, severity: Severity, model: &Model, message: String) {
+        self.problems.push(Problem {
+            severity,
+            input: self.input.to_string(),
+            model: model.name.clone(),
+            message
+        });
+    }
+
+    fn query_strings(self: &Self, sql: &str, arg: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+        let mut stmt = self.conn.prepare(sql).map_err(|e| e.to_string())?;
+        let column_count = stmt.column_count();
+
+        let rows = stmt
+            .query_map([arg], |row| {
+                (0..column_count)
+                    .map(|idx| row.get::<_, Option<String>>(idx))
+                    .collect::<rusqlite::Result<Vec<Option<String>>>>()
+            })
+            .map_err(|e| e.to_string())?;
+
+        rows.collect::<rusqlite::Result<Vec<Vec<Option<String>>>>>().map_err(|e| e.to_string())
+    }
+
+    fn check_model(self: &mut Self, model: &Model) -> Result<(), String> {
+        let table_columns = self.query_strings(
+            "SELECT name, type FROM pragma_table_info(?1)",
+            &model.name
+        )?;
+
+        // pragma_table_info returns no rows for tables that don't exist.
+        if table_columns.is_empty() {
+            self.report(Severity::Error, model, "table does not exist".to_string());
+            return Ok(());
+        }
+
+        let applied_migrations = migrations::applied_migrations(self.conn)?;
+
+        for column in model.columns.iter().filter(|column| is_stored(column, self.schema) && model.copies(column)) {
+            let declared_type = table_columns
+                .iter()
+                .find(|row| row[0].as_deref() == Some(column.name.as_str()))
+                .map(|row| row[1].clone().unwrap_or_default());
+
+            match declared_type {
+                // Columns missing from inputs created by older versions of the schema
+                // can be read via their old names or replaced by their default values.
+                None => {
+                    let renamed = column.renamed_from
+                        .iter()
+                        .any(|rename| rename.applies_to(applied_migrations.as_ref()) && has_column(&table_columns, &rename.from));
+
+                    if renamed {
+                        continue;
+                    }
+
+                    if column.default.is_some() || column.ty.nullable {
+                        self.report(
+                            Severity::Warning,
+                            model,
+                            format!("column {} does not exist and will be set to {}", column.name, column.default.as_deref().unwrap_or("NULL"))
+                        );
+                    } else {
+                        self.report(
+                            Severity::Error,
+                            model,
+                            format!("column {} does not exist and has no default value", column.name)
+                        );
+                    }
+                }
+
+                Some(declared_type) => {
+                    if let Some(enum_decl) = self.schema.enums.get(&column.ty.name) {
+                        self.check_enum_values(model, column, enum_decl)?;
+                    }
+
+                    if let Some(affinities) = compatible_affinities(&column.ty.name) {
+                        // Columns declared without a type can hold anything.
+                        if !declared_type.is_empty() && !affinities.contains(&affinity(&declared_type)) {
+                            self.report(
+                                Severity::Error,
+                                model,
+                                format!("column {} has type {}, which is incompatible with {}", column.name, declared_type, column.ty.name)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        self.check_foreign_keys(model)?;
+        self.check_unique_indices(model)
+    }
+
+    // Values that aren't in the enum can still be merged, but Prisma refuses to read
+    // them back.
+    fn check_enum_values(self: &mut Self, model: &Model, column: &Column, enum_decl: &Enum) -> Result<(), String> {
+        let values = enum_decl.values
+            .iter()
+            .map(|value| quote_literal(value))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let count: usize = self.conn
+            .query_row(
+                format!("SELECT COUNT(*) FROM \"{}\" WHERE \"{}\" NOT IN ({})", model.name, column.name, values).as_str(),
+                (),
+                |row| row.get(0)
+            )
+            .map_err(|e| e.to_string())?;
+
+        if count > 0 {
+            self.report(
+                Severity::Warning,
+                model,
+                format!("column {} has {} rows with values that aren't in enum {}", column.name, count, enum_decl.name)
+            );
+        }
+
+        Ok(())
+    }
+
+    fn check_foreign_keys(self: &mut Self, model: &Model) -> Result<(), String> {
+        let rows = self.query_strings(
+            "SELECT CAST(id AS TEXT), \"table\", \"from\", \"to\" FROM pragma_foreign_key_list(?1) ORDER BY id, seq",
+            &model.name
+        )?;
+
+        // Group the rows by foreign key id, one row per column.
+        let mut foreign_keys: Vec<ForeignKey> = vec![];
+
+        for row in rows {
+            let id = row[0].clone().unwrap_or_default();
+
+            if foreign_keys.last().map(|fk| fk.id != id).unwrap_or(true) {
+                foreign_keys.push(ForeignKey { id, table: row[1].clone().unwrap_or_default(), from: vec![], to: vec![] });
+            }
+
+            let fk = foreign_keys.last_mut().unwrap();
+            fk.from.push(row[2].clone().unwrap_or_default());
+            fk.to.push(row[3].clone());
+        }
+
+        for column in model.columns.iter() {
+            let Some(relation) = &column.relation else { continue };
+
+            if relation.fields.is_empty() || !self.schema.models.contains_key(&column.ty.name) {
+                continue;
+            }
+
+            let found = foreign_keys.iter().any(|fk| {
+                fk.table.eq_ignore_ascii_case(&column.ty.name) &&
+                    fk.from == relation.fields &&
+                    // A foreign key without target columns references the primary key.
+                    fk.to.iter().zip(relation.references.iter()).all(|(to, reference)| {
+                        to.as_ref().map(|to| to == reference).unwrap_or(true)
+                    })
+            });
+
+            if !found {
+                self.report(
+                    Severity::Warning,
+                    model,
+                    format!(
+                        "no foreign key from ({}) to {}({}) for relation {}",
+                        relation.fields.join(", "),
+                        column.ty.name,
+                        relation.references.join(", "),
+                        column.name
+                    )
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_unique_indices(self: &mut Self, model: &Model) -> Result<(), String> {
+        let mut existing: Vec<HashSet<String>> = vec![];
+
+        let indices = self.query_strings(
+            "SELECT name FROM pragma_index_list(?1) WHERE \"unique\" = 1",
+            &model.name
+        )?;
+
+        for index in indices {
+            let index_name = index[0].clone().unwrap_or_default();
+            let columns = self.query_strings("SELECT name FROM pragma_index_info(?1)", &index_name)?;
+            existing.push(columns.into_iter().filter_map(|row| row[0].clone()).collect());
+        }
+
+        for index in model.indices.iter().filter(|index| index.unique) {
+            let expected: HashSet<String> = index.column_names.iter().cloned().collect();
+
+            if !existing.contains(&expected) {
+                self.report(
+                    Severity::Warning,
+                    model,
+                    format!("no unique index on ({})", index.column_names.join(", "))
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Compare the schema with the tables in the given input database. Skipped models are
+// not checked.
+pub fn check_input(schema: &Schema, conn: &Connection, input: &str) -> Result<Vec<Problem>, String> {
+    let mut checker = Checker { schema, conn, input, problems: vec![] };
+
+    for model in schema.sorted() {
+        if !model.skip {
+            checker.check_model(model)?;
+        }
+    }
+
+    Ok(checker.problems)
+}
+
+// Check the rows in the given input for problems that merging would spread or hide:
+// dangling foreign keys, rows sharing a unique key, and corruption reported by
+// `PRAGMA quick_check`. Problems are summarized per table and are all warnings. If the
+// database is corrupt, the other checks are skipped since their results can't be
+// trusted.
+pub fn check_input_integrity(schema: &Schema, conn: &Connection, input: &str) -> Result<Vec<Problem>, String> {
+    let problem = |model: &str, message: String| Problem {
+        severity: Severity::Warning,
+        input: input.to_string(),
+        model: model.to_string(),
+        message
+    };
+
+    let corruption = verify::quick_check(conn)?;
+
+    if !corruption.is_empty() {
+        return Ok(corruption.into_iter().map(|message| problem("quick_check", message)).collect());
+    }
+
+    let mut problems = vec![];
+
+    for model in schema.sorted() {
+        // Missing tables are reported by check_input().
+        if model.skip || model.existing_columns(conn).is_empty() {
+            continue;
+        }
+
+        let mut dangling: BTreeMap<String, Vec<ForeignKeyViolation>> = BTreeMap::new();
+
+        for violation in verify::foreign_key_violations(conn, Some(&model.name))? {
+            dangling.entry(violation.parent.clone()).or_default().push(violation);
+        }
+
+        for (parent, violations) in dangling {
+            let example = &violations[0];
+
+            problems.push(problem(&model.name, format!(
+                "{} rows have foreign keys to {} rows that don't exist, eg. {} = {}",
+                violations.len(),
+                parent,
+                example.columns.join(", "),
+                example.value.as_deref().unwrap_or("?")
+            )));
+        }
+
+        let mut duplicates: BTreeMap<String, Vec<UniqueViolation>> = BTreeMap::new();
+
+        for violation in verify::unique_violations(conn, model)? {
+            duplicates.entry(violation.columns.join(", ")).or_default().push(violation);
+        }
+
+        for (columns, violations) in duplicates {
+            let example = &violations[0];
+
+            problems.push(problem(&model.name, format!(
+                "{} values of unique key ({}) are shared by several rows, eg. ({}) in {} rows",
+                violations.len(),
+                columns,
+                example.value,
+                example.count
+            )));
+        }
+    }
+
+    Ok(problems)
+}
+
+// Check all inputs, returning every problem found. Fails with a single error listing
+// all the problems if any of them are errors.
+pub fn check_inputs(schema: &Schema, inputs: &[(&str, &Connection)]) -> Result<Vec<Problem>, String> {
+    let mut problems = vec![];
+
+    for (input, conn) in inputs.iter() {
+        problems.append(&mut check_input(schema, conn, input)?);
+    }
+
+    let error_count = problems.iter().filter(|problem| problem.severity == Severity::Error).count();
+
+    if error_count > 0 {
+        let listing = problems
+            .iter()
+            .map(|problem| format!("  {}", problem))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        return Err(format!("The input databases don't match the schema ({} errors):\n{}", error_count, listing));
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::preflight::*;
+    use crate::prisma_parser;
+
+    const SCHEMA: &str = r#"
+        model Owner {
+          id    String @id
+          name  String @unique
+          age   Int?
+          toys  Toy[]
+        }
+
+        model Toy {
+          id      String @id
+          name    String
+          ownerId String
+          owner   Owner  @relation(fields: [ownerId], references: [id])
+        }
+    "#;
+
+    fn messages(problems: &[Problem]) -> Vec<String> {
+        problems.iter().map(|problem| problem.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_matching_inputs() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "age" INTEGER);
+            CREATE UNIQUE INDEX "Owner_name_key" ON "Owner"("name");
+            CREATE TABLE "Toy" (
+                "id" TEXT NOT NULL PRIMARY KEY,
+                "name" TEXT NOT NULL,
+                "ownerId" TEXT NOT NULL,
+                CONSTRAINT "Toy_ownerId_fkey" FOREIGN KEY ("ownerId") REFERENCES "Owner" ("id")
+            );
+        "#).unwrap();
+
+        let problems = check_inputs(&schema, &[("a.db", &conn)]).unwrap();
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn reports_all_errors_at_once() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" BLOB NOT NULL);
+        "#).unwrap();
+
+        let problems = check_input(&schema, &conn, "a.db").unwrap();
+
+        assert!(messages(&problems) == vec![
+            "error: a.db: Owner: column name has type BLOB, which is incompatible with String",
+            "warning: a.db: Owner: column age does not exist and will be set to NULL",
+            "warning: a.db: Owner: no unique index on (name)",
+            "error: a.db: Toy: table does not exist"
+        ]);
+
+        let error = check_inputs(&schema, &[("a.db", &conn)]).unwrap_err();
+        assert!(error.contains("(2 errors)"));
+    }
+
+    #[test]
+    fn warns_about_missing_foreign_keys() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL UNIQUE, "age" INTEGER);
+            CREATE TABLE "Toy" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "ownerId" TEXT NOT NULL);
+        "#).unwrap();
+
+        let problems = check_inputs(&schema, &[("a.db", &conn)]).unwrap();
+        assert!(messages(&problems) == vec!["warning: a.db: Toy: no foreign key from (ownerId) to Owner(id) for relation owner"]);
+    }
+
+    #[test]
+    fn warns_about_values_missing_from_enums() {
+        let schema = prisma_parser::parse(r#"
+            enum Kind {
+              PLUSH
+              PLASTIC @map("plastic")
+            }
+
+            model Toy {
+              id   String @id
+              kind Kind?
+            }
+        "#).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            CREATE TABLE "Toy" ("id" TEXT NOT NULL PRIMARY KEY, "kind" TEXT);
+            INSERT INTO "Toy" VALUES ('t1', 'PLUSH'), ('t2', 'plastic'), ('t3', NULL), ('t4', 'WOODEN'), ('t5', 'PLASTIC');
+        "#).unwrap();
+
+        let problems = check_inputs(&schema, &[("a.db", &conn)]).unwrap();
+        assert!(messages(&problems) == vec!["warning: a.db: Toy: column kind has 2 rows with values that aren't in enum Kind"]);
+    }
+
+    #[test]
+    fn summarizes_integrity_problems() {
+        let schema = prisma_parser::parse(SCHEMA).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            PRAGMA foreign_keys = OFF;
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL, "age" INTEGER);
+            CREATE TABLE "Toy" (
+                "id" TEXT NOT NULL PRIMARY KEY,
+                "name" TEXT NOT NULL,
+                "ownerId" TEXT NOT NULL,
+                CONSTRAINT "Toy_ownerId_fkey" FOREIGN KEY ("ownerId") REFERENCES "Owner" ("id")
+            );
+
+            INSERT INTO "Owner" VALUES ('a', 'Woody', NULL), ('b', 'Woody', NULL);
+            INSERT INTO "Toy" VALUES ('1', 'Rex', 'a'), ('2', 'Slinky', 'gone'), ('3', 'Hamm', 'gone');
+        "#).unwrap();
+
+        let problems = check_input_integrity(&schema, &conn, "a.db").unwrap();
+
+        assert!(messages(&problems) == vec![
+            "warning: a.db: Owner: 1 values of unique key (name) are shared by several rows, eg. ('Woody') in 2 rows",
+            "warning: a.db: Toy: 2 rows have foreign keys to Owner rows that don't exist, eg. ownerId = 'gone'"
+        ]);
+    }
+
+    #[test]
+    fn skips_unique_keys_inputs_predate() {
+        let mut schema = prisma_parser::parse(SCHEMA).unwrap();
+        schema.models.get_mut("Owner").unwrap().add_rename("name", "title").unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+
+        // The input predates both the rename of title to name and the Toy table, so
+        // there's nothing to compare Toy's key with and Owner's is read from title.
+        conn.execute_batch(r#"
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "title" TEXT NOT NULL, "age" INTEGER);
+            INSERT INTO "Owner" VALUES ('a', 'Woody', NULL), ('b', 'Buzz', NULL), ('c', 'Buzz', NULL);
+        "#).unwrap();
+
+        let problems = check_input_integrity(&schema, &conn, "a.db").unwrap();
+
+        assert!(messages(&problems) == vec![
+            "warning: a.db: Owner: 1 values of unique key (name) are shared by several rows, eg. ('Buzz') in 2 rows"
+        ]);
+    }
+}
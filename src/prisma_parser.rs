@@ -1,15 +1,106 @@
 use tree_sitter_prisma_io;
 use tree_sitter::{Node, Parser};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, ops::Range};
 
+use crate::conflicts::{Aggregate, ConflictPolicy};
 use crate::data::{
     Column,
     ColumnType,
+    Datasource,
+    Enum,
+    Generator,
+    IgnorePolicy,
+    Index,
     Model,
+    OrphanPolicy,
+    Passthrough,
     Relation,
     Schema,
-    Unique
+    Unique,
+    Url
 };
+use crate::normalize::Normalizer;
+use crate::utils::quote_literal;
+
+/* A problem found while parsing the schema, along with where it was found. Lines and
+ * columns are 1-based, and columns count characters rather than bytes. The snippet is
+ * the line of source code the problem starts on. The file is only known when parsing
+ * schemas split across several files.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub file: Option<String>,
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String
+}
+
+impl ParseError {
+    pub fn new(message: String, span: Range<usize>, source: &str) -> Self {
+        let line_start = source[..span.start].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = source[span.start..].find('\n').map(|idx| span.start + idx).unwrap_or(source.len());
+
+        ParseError {
+            message,
+            file: None,
+            line: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            snippet: source[line_start..line_end].trim_end_matches('\r').to_string(),
+            span
+        }
+    }
+
+    fn at(node: &Node, source: &str, message: String) -> Self {
+        Self::new(message, node.start_byte()..node.end_byte(), source)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Underline the part of the span on the snippet's line, or at least one
+        // character so zero-width spans (eg. missing tokens) are still visible.
+        let start = self.column - 1;
+        let width = self.snippet.chars().count().saturating_sub(start).min(self.span.len()).max(1);
+
+        let indent: String = self.snippet
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+
+        writeln!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.snippet)?;
+        write!(f, "{} | {}{}", gutter, indent, "^".repeat(width))
+    }
+}
+
+// All the problems found while parsing the schema, in source order.
+#[derive(Debug)]
+pub struct ParseErrors {
+    pub errors: Vec<ParseError>
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.errors.iter().map(|error| error.to_string()).collect();
+        write!(f, "{}", errors.join("\n\n"))
+    }
+}
+
+impl From<ParseErrors> for String {
+    fn from(errors: ParseErrors) -> Self {
+        format!("Unable to parse the schema:\n{}", errors)
+    }
+}
 
 struct Cursor<'a> {
     source: &'a str,
@@ -22,16 +113,29 @@ impl<'a> Cursor<'a> {
         Cursor { source, nodes, idx: 0 }
     }
 
-    fn consume(self: &mut Self, kind: &str) -> Result<(), String> {
+    fn consume(self: &mut Self, kind: &str) -> Result<(), ParseError> {
+        if self.eos() {
+            return Err(self.error(format!("Expected {}, got end of input", kind)));
+        }
+
         if self.current().kind() == kind {
             self.idx += 1;
             Ok(())
         } else {
-            Err(format!("Expected {}, got {}", kind, self.current().kind()))
+            Err(self.error(format!("Expected {}, got {}", kind, self.current().kind())))
+        }
+    }
+
+    // An error at the current node, or at the end of the source if there isn't one.
+    fn error(self: &Self, message: String) -> ParseError {
+        if self.eos() {
+            ParseError::new(message, self.source.len()..self.source.len(), self.source)
+        } else {
+            ParseError::at(self.current(), self.source, message)
         }
     }
 
-    fn consume_all(self: &mut Self, kinds: &[&str]) -> Result<(), String> {
+    fn consume_all(self: &mut Self, kinds: &[&str]) -> Result<(), ParseError> {
         for kind in kinds.iter() {
             self.consume(kind)?;
         }
@@ -40,9 +144,11 @@ impl<'a> Cursor<'a> {
     }
 
     fn try_consume(self: &mut Self, kind: &str) -> bool {
-        match self.consume(kind) {
-            Ok(_) => true,
-            Err(_) => false
+        if !self.eos() && self.current().kind() == kind {
+            self.idx += 1;
+            true
+        } else {
+            false
         }
     }
 
@@ -60,7 +166,14 @@ impl<'a> Cursor<'a> {
         self.idx += 1;
     }
 
-    fn current(self: &Self) -> &Node {
+    // Skip past all the descendants of the given node.
+    fn skip_descendants(self: &mut Self, ancestor: &Node<'a>) {
+        while !self.eos() && is_descendant(self.current(), ancestor) {
+            self.skip();
+        }
+    }
+
+    fn current(self: &Self) -> &Node<'a> {
         &self.nodes[self.idx]
     }
 
@@ -69,54 +182,330 @@ impl<'a> Cursor<'a> {
     }
 }
 
-pub fn parse(schema_str: &str) -> Result<Schema, String> {
+fn is_descendant(node: &Node, ancestor: &Node) -> bool {
+    let mut parent = node.parent();
+
+    while let Some(current) = parent {
+        if current.id() == ancestor.id() {
+            return true;
+        }
+
+        parent = current.parent();
+    }
+
+    false
+}
+
+// A Prisma schema file. The path is only used to locate problems.
+pub struct SchemaFile {
+    pub path: String,
+    pub source: String
+}
+
+// The scalar types built into Prisma. Any other column type must be declared in the
+// schema.
+const SCALAR_TYPES: &[&str] = &["String", "Boolean", "Int", "BigInt", "Float", "Decimal", "DateTime", "Json", "Bytes"];
+
+// A reference to a type by a column, along with the error to report if the type isn't
+// declared anywhere.
+type TypeReference = (String, ParseError);
+
+// Parse the given schema. Syntax errors, and problems with individual models, are
+// collected rather than returned one at a time so they can all be fixed at once.
+pub fn parse(schema_str: &str) -> Result<Schema, ParseErrors> {
+    parse_sources(&[(None, schema_str)])
+}
+
+// Parse a schema split across several files, eg. a prismaSchemaFolder, into a single
+// Schema.
+pub fn parse_files(files: &[SchemaFile]) -> Result<Schema, ParseErrors> {
+    let sources: Vec<(Option<&str>, &str)> = files
+        .iter()
+        .map(|file| (Some(file.path.as_str()), file.source.as_str()))
+        .collect();
+
+    parse_sources(&sources)
+}
+
+fn parse_sources(sources: &[(Option<&str>, &str)]) -> Result<Schema, ParseErrors> {
+    let mut schema = Schema::new();
+    let mut errors: Vec<(usize, ParseError)> = vec![];
+    let mut type_references: Vec<(usize, TypeReference)> = vec![];
+
+    for (idx, (path, source)) in sources.iter().enumerate() {
+        let (file_errors, file_type_references) = parse_source(&mut schema, source);
+        let with_file = |mut error: ParseError| { error.file = path.map(|path| path.to_string()); error };
+
+        errors.extend(file_errors.into_iter().map(|error| (idx, with_file(error))));
+        type_references.extend(file_type_references.into_iter().map(|(name, error)| (idx, (name, with_file(error)))));
+    }
+
+    // Types may be declared in any file, so they can only be resolved once every file
+    // has been parsed.
+    for (idx, (name, error)) in type_references {
+        if !schema.is_declared(&name) {
+            errors.push((idx, error));
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|(idx, error)| (*idx, error.span.start));
+        return Err(ParseErrors { errors: errors.into_iter().map(|(_, error)| error).collect() });
+    }
+
+    Ok(schema)
+}
+
+// Parse a single source file into the given schema, returning the problems found and
+// the types its columns refer to.
+fn parse_source(schema: &mut Schema, schema_str: &str) -> (Vec<ParseError>, Vec<TypeReference>) {
     let mut parser = Parser::new();
     parser.set_language(tree_sitter_prisma_io::language()).expect("Error loading prisma grammar");
 
     let tree = parser.parse(schema_str, None).unwrap();
-    let mut schema = Schema::new();
+    let mut errors = syntax_errors(tree.root_node(), schema_str);
+    let mut type_references: Vec<TypeReference> = vec![];
     let nodes = gather_nodes(tree.root_node());
     let mut cursor = Cursor::new(nodes, schema_str);
 
-    cursor.consume("program")?;
+    cursor.skip();
+
+    // Triple-slash comments immediately preceding a model declaration. These may
+    // contain prismerge directives.
+    let mut doc_comments: Vec<Comment> = vec![];
 
     while !cursor.eos() {
-        match cursor.current().kind() {
-            "model_declaration" => {
-                let model = handle_model_decl(&mut cursor)?;
-                schema.models.insert(model.name.clone(), model);
+        let node = *cursor.current();
+
+        match node.kind() {
+            "model_declaration" | "view_declaration" | "type_declaration" | "enum_declaration" |
+            "datasource_declaration" | "generator_declaration" => {
+                // Declarations containing syntax errors have already been reported. Note
+                // that tree-sitter sometimes flags a declaration as erroneous without an
+                // ERROR or MISSING node to show for it, eg. when the name is missing.
+                let reported = errors
+                    .iter()
+                    .any(|error| error.span.start >= node.start_byte() && error.span.start <= node.end_byte());
+
+                if !reported {
+                    if let Err(error) = handle_declaration(&mut cursor, schema, &doc_comments, &mut type_references) {
+                        errors.push(error);
+                    }
+                }
+
+                if !cursor.eos() && cursor.current().id() == node.id() {
+                    cursor.skip();
+                }
+
+                cursor.skip_descendants(&node);
+                doc_comments.clear();
             }
 
-            _ => cursor.skip()
+            "comment" => doc_comments.push(handle_comment(&mut cursor)),
+
+            _ => {
+                doc_comments.clear();
+                cursor.skip();
+            }
         }
     }
 
-    Ok(schema)
+    (errors, type_references)
 }
 
-fn handle_model_decl(cursor: &mut Cursor) -> Result<Model, String> {
-    cursor.consume("model_declaration")?;
-    cursor.consume("model")?;
+// Report the ERROR and MISSING nodes tree-sitter inserts when it recovers from syntax
+// errors. Only the outermost of any nested ERROR nodes is reported.
+fn syntax_errors(root: Node, source: &str) -> Vec<ParseError> {
+    let mut errors = vec![];
+
+    if !root.has_error() {
+        return errors;
+    }
+
+    let mut cursor = Cursor::new(gather_nodes(root), source);
+
+    while !cursor.eos() {
+        let node = *cursor.current();
+        cursor.skip();
+
+        if node.is_error() && (is_fractional_part(&node, source) || is_in_enum_block(&node)) {
+            cursor.skip_descendants(&node);
+        } else if node.is_error() {
+            // Point at the first unexpected token rather than the whole node.
+            let mut token = node;
 
-    let mut columns = vec![];
+            while let Some(child) = token.child(0) {
+                token = child;
+            }
+
+            let text = &source[token.start_byte()..token.end_byte()];
+            errors.push(ParseError::at(&node, source, format!("Unexpected {}", text)));
+            cursor.skip_descendants(&node);
+        } else if node.is_missing() {
+            errors.push(ParseError::at(&node, source, format!("Missing {}", node.kind())));
+        }
+    }
+
+    errors
+}
+
+// The grammar doesn't support float literals, eg. @default(1.5) parses as the number 1
+// followed by an ERROR node containing ".5". These aren't real syntax errors.
+fn is_fractional_part(node: &Node, source: &str) -> bool {
+    let text = &source[node.start_byte()..node.end_byte()];
+
+    node.parent().is_some_and(|parent| parent.kind() == "arguments") &&
+        text.strip_prefix('.').is_some_and(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+}
+
+// Enum blocks are parsed from their source text, see handle_enum_decl().
+fn is_in_enum_block(node: &Node) -> bool {
+    node.parent().is_some_and(|parent| parent.kind() == "enum_block")
+}
+
+fn handle_declaration(cursor: &mut Cursor, schema: &mut Schema, doc_comments: &[Comment], type_references: &mut Vec<TypeReference>) -> Result<(), ParseError> {
+    let node = *cursor.current();
+
+    // Models, enums, views, and types share a namespace.
+    let check_name = |schema: &Schema, name: &str| {
+        if schema.is_declared(name) {
+            Err(ParseError::at(&node, cursor.source, format!("{} is declared more than once", name)))
+        } else {
+            Ok(())
+        }
+    };
+
+    match node.kind() {
+        "model_declaration" => {
+            let model = handle_model_decl(cursor, doc_comments, type_references)?;
+            check_name(schema, &model.name)?;
+            schema.models.insert(model.name.clone(), model);
+        }
+
+        "view_declaration" => {
+            let view = handle_model_decl(cursor, doc_comments, type_references)?;
+            check_name(schema, &view.name)?;
+            schema.views.insert(view.name.clone(), view);
+        }
+
+        "type_declaration" => {
+            let ty = handle_model_decl(cursor, doc_comments, type_references)?;
+            check_name(schema, &ty.name)?;
+            schema.types.insert(ty.name.clone(), ty);
+        }
+
+        "enum_declaration" => {
+            let enum_decl = handle_enum_decl(cursor)?;
+            check_name(schema, &enum_decl.name)?;
+            schema.enums.insert(enum_decl.name.clone(), enum_decl);
+        }
+
+        "datasource_declaration" => {
+            if schema.datasource.is_some() {
+                return Err(cursor.error("Only one datasource is allowed".to_string()));
+            }
+
+            schema.datasource = Some(handle_datasource_decl(cursor)?);
+        }
+
+        "generator_declaration" => schema.generators.push(handle_generator_decl(cursor)?),
+        _ => ()
+    }
+
+    Ok(())
+}
+
+// Models, views, and composite types are all declared the same way, eg. `view Foo {}`.
+fn handle_model_decl(cursor: &mut Cursor, doc_comments: &[Comment], type_references: &mut Vec<TypeReference>) -> Result<Model, ParseError> {
+    let node = *cursor.current();
+    let keyword = node.kind().trim_end_matches("_declaration");
+
+    cursor.consume(node.kind())?;
+    cursor.consume(keyword)?;
+
+    let mut columns: Vec<Column> = vec![];
     let mut unique: Option<Unique> = None;
+    let mut indices: Vec<Index> = vec![];
+    let mut ignored = false;
     let name = handle_identifier(cursor)?;
 
-    if cursor.current().kind() == "statement_block" {
+    if name.is_empty() {
+        return Err(ParseError::at(&node, cursor.source, format!("Expected a {} name", keyword)));
+    }
+
+    // Triple-slash comments preceding the current column, and the line the most recent
+    // column ended on. Comments on that same line belong to the most recent column.
+    let mut column_comments: Vec<Comment> = vec![];
+    let mut last_column_row: Option<usize> = None;
+
+    if !cursor.eos() && cursor.current().kind() == "statement_block" {
         cursor.consume("statement_block")?;
         cursor.consume("{")?;
 
         loop {
+            if cursor.eos() {
+                return Err(cursor.error(format!("Expected }} to close model {}", name)));
+            }
+
             match cursor.current().kind() {
-                "column_declaration" => columns.push(handle_column_decl(cursor)?),
+                "column_declaration" => {
+                    let column_node = *cursor.current();
+                    last_column_row = Some(column_node.end_position().row);
+
+                    let mut column = handle_column_decl(cursor)?;
+
+                    if !SCALAR_TYPES.contains(&column.ty.name.as_str()) {
+                        let message = if column.has_relation() {
+                            format!("Relation {} points to {}, which isn't declared in any schema file", column.name, column.ty.name)
+                        } else {
+                            format!("Type {} of column {} isn't declared in any schema file", column.ty.name, column.name)
+                        };
+
+                        type_references.push((column.ty.name.clone(), ParseError::at(&column_node, cursor.source, message)));
+                    }
+
+                    apply_column_directives(&mut column, &column_comments, cursor.source)?;
+                    column_comments.clear();
+                    columns.push(column);
+                }
+
+                "comment" => {
+                    let row = cursor.current().start_position().row;
+                    let comment = handle_comment(cursor);
+
+                    match columns.last_mut() {
+                        Some(column) if last_column_row == Some(row) => {
+                            apply_column_directives(column, &[comment], cursor.source)?;
+                        }
+
+                        _ => column_comments.push(comment)
+                    }
+                }
+
                 "block_attribute_declaration" => {
                     cursor.consume("block_attribute_declaration")?;
 
                     if cursor.try_consume_all(&["@@", "call_expression"]) {
                         let method_name = handle_identifier(cursor)?;
 
-                        if method_name == "unique" {
-                            unique = Some(handle_unique(cursor)?);
+                        match method_name.as_str() {
+                            "unique" => {
+                                let block_unique = handle_unique(cursor)?;
+                                indices.push(Index { column_names: block_unique.column_names.clone(), unique: true });
+                                unique = Some(block_unique);
+                            }
+
+                            "index" => {
+                                let column_names = handle_args(cursor)?.remove("fields").unwrap_or_default();
+                                indices.push(Index { column_names, unique: false });
+                            }
+
+                            _ => ()
+                        }
+                    } else if !cursor.eos() && cursor.current().kind() == "identifier" {
+                        // Attributes without arguments, eg. @@ignore, have no call expression.
+                        if handle_identifier(cursor)? == "ignore" {
+                            ignored = true;
                         }
                     }
                 }
@@ -136,23 +525,329 @@ fn handle_model_decl(cursor: &mut Cursor) -> Result<Model, String> {
         }
     }
 
-    Ok(Model::new(name, columns, unique))
+    // Single-column unique indices come before the ones declared at the model level.
+    let column_indices = columns
+        .iter()
+        .filter(|column| column.unique)
+        .map(|column| Index { column_names: vec![column.name.clone()], unique: true })
+        .collect::<Vec<Index>>();
+
+    let mut model = Model::new(name, columns, unique);
+    model.indices = column_indices.into_iter().chain(indices).collect();
+    model.ignored = ignored;
+    apply_model_directives(&mut model, doc_comments, cursor.source)?;
+    Ok(model)
+}
+
+// The grammar doesn't support attributes on enum values, eg. @map("plush"), so the
+// values are read from the source text of the enum's block instead.
+fn handle_enum_decl(cursor: &mut Cursor) -> Result<Enum, ParseError> {
+    let node = *cursor.current();
+
+    cursor.consume_all(&["enum_declaration", "enum"])?;
+    let name = handle_identifier(cursor)?;
+
+    let block = *cursor.current();
+    cursor.consume("enum_block")?;
+
+    let mut values = vec![];
+    let mut offset = block.start_byte() + 1;
+
+    for line in cursor.source[block.start_byte() + 1..block.end_byte() - 1].split('\n') {
+        let line_offset = offset;
+        offset += line.len() + 1;
+
+        let code = line.split("//").next().unwrap_or_default().trim();
+
+        // Skip blank lines, comments, and block attributes, eg. @@map("kinds").
+        if code.is_empty() || code.starts_with("@@") {
+            continue;
+        }
+
+        let (value, attributes) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+
+        if !value.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            let start = line_offset + line.find(value).unwrap_or(0);
+            return Err(ParseError::new(format!("Invalid value {} in enum {}", value, name), start..start + value.len(), cursor.source));
+        }
+
+        let mapped = attributes
+            .trim()
+            .strip_prefix("@map(")
+            .and_then(|args| args.trim_end().strip_suffix(')'))
+            .and_then(|args| unquote(args.trim()));
+
+        values.push(mapped.unwrap_or(value.to_string()));
+    }
+
+    cursor.skip_descendants(&node);
+    Ok(Enum { name, values })
 }
 
-fn handle_unique(cursor: &mut Cursor) -> Result<Unique, String> {
+// Return the keys and value nodes of the assignments in a datasource or generator
+// block, eg. provider = "sqlite".
+fn handle_assignments<'a>(cursor: &mut Cursor<'a>) -> Result<Vec<(String, Node<'a>)>, ParseError> {
+    let mut assignments = vec![];
+
+    cursor.consume_all(&["statement_block", "{"])?;
+
+    while !cursor.eos() && cursor.current().kind() != "}" {
+        let node = *cursor.current();
+
+        if cursor.try_consume("assignment_expression") {
+            let key = *cursor.current();
+            cursor.consume("variable")?;
+            cursor.consume("=")?;
+            assignments.push((cursor.source[key.start_byte()..key.end_byte()].to_string(), *cursor.current()));
+            cursor.skip_descendants(&node);
+        } else {
+            cursor.skip();
+        }
+    }
+
+    Ok(assignments)
+}
+
+// Interpret a string literal, or a call to env() for datasource urls.
+fn string_value(node: &Node, source: &str) -> Option<Url> {
+    let text = &source[node.start_byte()..node.end_byte()];
+
+    match node.kind() {
+        "string" => unquote(text).map(Url::Literal),
+
+        "call_expression" => text
+            .strip_prefix("env(")
+            .and_then(|args| args.strip_suffix(')'))
+            .and_then(|args| unquote(args.trim()))
+            .map(Url::Env),
+
+        _ => None
+    }
+}
+
+fn handle_datasource_decl(cursor: &mut Cursor) -> Result<Datasource, ParseError> {
+    let node = *cursor.current();
+
+    cursor.consume_all(&["datasource_declaration", "datasource"])?;
+    let name = handle_identifier(cursor)?;
+    let mut datasource = Datasource { name, ..Default::default() };
+
+    for (key, value) in handle_assignments(cursor)? {
+        match key.as_str() {
+            "provider" => {
+                let Some(Url::Literal(provider)) = string_value(&value, cursor.source) else {
+                    return Err(ParseError::at(&value, cursor.source, "Expected the datasource provider to be a string".to_string()));
+                };
+
+                if provider != "sqlite" {
+                    return Err(ParseError::at(
+                        &value,
+                        cursor.source,
+                        format!("Unsupported datasource provider \"{}\", prismerge only works with sqlite databases", provider)
+                    ));
+                }
+
+                datasource.provider = provider;
+            }
+
+            "url" => datasource.url = string_value(&value, cursor.source),
+            _ => ()
+        }
+    }
+
+    if datasource.provider.is_empty() {
+        return Err(ParseError::at(&node, cursor.source, format!("Expected a provider in datasource {}", datasource.name)));
+    }
+
+    Ok(datasource)
+}
+
+fn handle_generator_decl(cursor: &mut Cursor) -> Result<Generator, ParseError> {
+    cursor.consume_all(&["generator_declaration", "generator"])?;
+    let name = handle_identifier(cursor)?;
+    let mut generator = Generator { name, provider: None };
+
+    for (key, value) in handle_assignments(cursor)? {
+        if key == "provider" {
+            if let Some(Url::Literal(provider)) = string_value(&value, cursor.source) {
+                generator.provider = Some(provider);
+            }
+        }
+    }
+
+    Ok(generator)
+}
+
+// A comment along with its node, so problems with the directives it contains can be
+// located.
+struct Comment<'a> {
+    text: String,
+    node: Node<'a>
+}
+
+fn handle_comment<'a>(cursor: &mut Cursor<'a>) -> Comment<'a> {
+    let node = *cursor.current();
+    cursor.skip();
+    Comment { text: cursor.source[node.start_byte()..node.end_byte()].to_string(), node }
+}
+
+// A prismerge directive, eg. @prismerge.dedupeBy([a, b]), found in a triple-slash
+// comment. The args are the raw text between the parentheses, if any.
+#[derive(Debug, PartialEq)]
+struct Directive {
+    name: String,
+    args: Option<String>
+}
+
+const DIRECTIVE_PREFIX: &str = "@prismerge.";
+
+// Directives are only read from triple-slash comments. Regular comments are left
+// alone, so commenting out a directive with // disables it.
+fn parse_directives(comment: &str) -> Result<Vec<Directive>, String> {
+    let mut directives = vec![];
+
+    let mut rest = match comment.trim_start().strip_prefix("///") {
+        Some(rest) => rest,
+        None => return Ok(directives)
+    };
+
+    while let Some(start) = rest.find(DIRECTIVE_PREFIX) {
+        rest = &rest[start + DIRECTIVE_PREFIX.len()..];
+
+        let name_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+
+        let name = rest[..name_len].to_string();
+        let mut args: Option<String> = None;
+        rest = &rest[name_len..];
+
+        if rest.starts_with('(') {
+            let end = rest
+                .find(')')
+                .ok_or_else(|| format!("Expected ) to close arguments of {}{}", DIRECTIVE_PREFIX, name))?;
+
+            args = Some(rest[1..end].trim().to_string());
+            rest = &rest[end + 1..];
+        }
+
+        directives.push(Directive { name, args });
+    }
+
+    Ok(directives)
+}
+
+fn directive_args(directive: &Directive) -> Result<&str, String> {
+    directive
+        .args
+        .as_deref()
+        .ok_or_else(|| format!("Expected arguments for {}{}", DIRECTIVE_PREFIX, directive.name))
+}
+
+// Split a comma-separated list of directive arguments, eg. "[a, b]" or "lowercase, trim".
+fn directive_list(args: &str) -> Vec<String> {
+    args
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn apply_model_directives(model: &mut Model, comments: &[Comment], source: &str) -> Result<(), ParseError> {
+    for comment in comments {
+        apply_model_directive(model, &comment.text)
+            .map_err(|message| ParseError::at(&comment.node, source, message))?;
+    }
+
+    Ok(())
+}
+
+fn apply_model_directive(model: &mut Model, comment: &str) -> Result<(), String> {
+    for directive in parse_directives(comment)? {
+        match directive.name.as_str() {
+            "skip" => model.skip = true,
+
+            "dedupeBy" => {
+                model.set_dedupe_by(directive_list(directive_args(&directive)?))?;
+            }
+
+            "dedupeByHash" => model.set_dedupe_by_hash(true)?,
+
+            "passthrough" => {
+                let passthrough = directive_args(&directive)?
+                    .replace(char::is_whitespace, "")
+                    .parse::<Passthrough>()?;
+
+                model.set_passthrough(passthrough)?;
+            }
+
+            "conflict" => {
+                let policy = directive_args(&directive)?
+                    .replace(char::is_whitespace, "")
+                    .parse::<ConflictPolicy>()?;
+
+                model.set_conflict_policy(policy)?;
+            }
+
+            "ignore" => {
+                model.ignore_policy = directive_args(&directive)?.trim().parse::<IgnorePolicy>()?;
+            }
+
+            _ => return Err(format!("Unknown directive {}{} on model {}", DIRECTIVE_PREFIX, directive.name, model.name))
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_column_directives(column: &mut Column, comments: &[Comment], source: &str) -> Result<(), ParseError> {
+    for comment in comments {
+        apply_column_directive(column, &comment.text)
+            .map_err(|message| ParseError::at(&comment.node, source, message))?;
+    }
+
+    Ok(())
+}
+
+fn apply_column_directive(column: &mut Column, comment: &str) -> Result<(), String> {
+    for directive in parse_directives(comment)? {
+        match directive.name.as_str() {
+            "conflict" => {
+                column.aggregate = Some(directive_args(&directive)?.parse::<Aggregate>()?);
+            }
+
+            "normalize" => {
+                for normalizer in directive_list(directive_args(&directive)?) {
+                    column.normalizers.push(normalizer.parse::<Normalizer>()?);
+                }
+            }
+
+            "orphans" => {
+                column.set_orphan_policy(directive_args(&directive)?.trim().parse::<OrphanPolicy>()?)?;
+            }
+
+            _ => return Err(format!("Unknown directive {}{} on column {}", DIRECTIVE_PREFIX, directive.name, column.name))
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_unique(cursor: &mut Cursor) -> Result<Unique, ParseError> {
     let mut args = handle_args(cursor)?;
-    let column_names = args.remove("fields").unwrap_or_else(|| vec![]);
+    let column_names = args.remove("fields").unwrap_or_default();
     Ok(Unique { column_names })
 }
 
-fn handle_identifier(cursor: &mut Cursor) -> Result<String, String> {
-    let current = cursor.current();
-    let identifier = &cursor.source[current.start_byte()..current.end_byte()];
+fn handle_identifier(cursor: &mut Cursor) -> Result<String, ParseError> {
     cursor.consume("identifier")?;
-    Ok(identifier.to_string())
+    let current = cursor.nodes[cursor.idx - 1];
+    Ok(cursor.source[current.start_byte()..current.end_byte()].to_string())
 }
 
-fn handle_column_type(cursor: &mut Cursor) -> Result<ColumnType, String> {
+fn handle_column_type(cursor: &mut Cursor) -> Result<ColumnType, ParseError> {
     cursor.consume("column_type")?;
 
     let name = handle_identifier(cursor)?;
@@ -176,7 +871,7 @@ fn handle_column_type(cursor: &mut Cursor) -> Result<ColumnType, String> {
     Ok(ColumnType { name, collection, nullable })
 }
 
-fn handle_column_decl(cursor: &mut Cursor) -> Result<Column, String> {
+fn handle_column_decl(cursor: &mut Cursor) -> Result<Column, ParseError> {
     cursor.consume("column_declaration")?;
 
     let name = handle_identifier(cursor)?;
@@ -185,13 +880,30 @@ fn handle_column_decl(cursor: &mut Cursor) -> Result<Column, String> {
     let mut unique = false;
     let mut primary_key = false;
 
-    if cursor.try_consume_all(&["attribute", "@"]) {
+    let mut default: Option<String> = None;
+    let mut default_function: Option<String> = None;
+    let mut updated_at = false;
+    let mut ignored = false;
+
+    while cursor.current().kind() == "attribute" {
+        let attribute = *cursor.current();
+        cursor.consume_all(&["attribute", "@"])?;
+
         match cursor.current().kind() {
             "call_expression" => {
                 cursor.consume("call_expression")?;
 
-                if handle_identifier(cursor)? == "relation" {
-                    relation = Some(handle_relation(cursor)?);
+                // Attributes like @db.VarChar(255) have a member expression instead of
+                // an identifier. They're skipped along with any other unknown attributes.
+                if cursor.current().kind() == "identifier" {
+                    match handle_identifier(cursor)?.as_str() {
+                        "relation" => relation = Some(handle_relation(cursor)?),
+                        "default" => {
+                            default = handle_default(cursor);
+                            default_function = handle_default_function(cursor);
+                        },
+                        _ => ()
+                    }
                 }
             }
 
@@ -199,25 +911,92 @@ fn handle_column_decl(cursor: &mut Cursor) -> Result<Column, String> {
                 match handle_identifier(cursor)?.as_str() {
                     "unique" => unique = true,
                     "id" => primary_key = true,
+                    "updatedAt" => updated_at = true,
+                    "ignore" => ignored = true,
                     _ => ()
                 }
             },
 
             _ => ()
         }
+
+        cursor.skip_descendants(&attribute);
+
+        if cursor.eos() {
+            break;
+        }
+    }
+
+    Ok(Column { name, ty, relation, unique, primary_key, default, default_function, updated_at, ignored, ..Default::default() })
+}
+
+// Convert the argument of a @default attribute into an SQL literal. Returns None for
+// defaults generated by functions, eg. now() or uuid(), since they have no constant
+// value. The attribute's nodes are left for the caller to skip.
+fn handle_default(cursor: &mut Cursor) -> Option<String> {
+    let arguments = cursor.current();
+
+    if arguments.kind() != "arguments" {
+        return None;
+    }
+
+    // Use the raw source text between the parentheses. The grammar doesn't handle
+    // some literals, eg. floats, very well.
+    let value = cursor.source[arguments.start_byte() + 1..arguments.end_byte() - 1].trim();
+
+    if let Some(string) = unquote(value) {
+        return Some(quote_literal(&string));
+    }
+
+    match value {
+        // Prisma stores booleans as integers in SQLite.
+        "true" => Some("1".to_string()),
+        "false" => Some("0".to_string()),
+        _ if value.parse::<f64>().is_ok() => Some(value.to_string()),
+
+        // Enum values are stored as strings.
+        _ if !value.is_empty() && value.chars().all(|c| c.is_alphanumeric() || c == '_') => Some(quote_literal(value)),
+
+        // Functions, lists, etc.
+        _ => None
+    }
+}
+
+// Return the name of the function a @default attribute calls, eg. "uuid" for
+// @default(uuid()). Like handle_default(), leaves the attribute's nodes alone.
+fn handle_default_function(cursor: &mut Cursor) -> Option<String> {
+    let arguments = cursor.current();
+
+    if arguments.kind() != "arguments" {
+        return None;
+    }
+
+    let value = cursor.source[arguments.start_byte() + 1..arguments.end_byte() - 1].trim();
+    let (name, _) = value.split_once('(')?;
+    let name = name.trim();
+
+    // Excludes strings containing parentheses.
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
     }
 
-    Ok(Column { name: name, ty, relation, unique, primary_key })
+    Some(name.to_string())
+}
+
+// Remove the quotes from a string literal and unescape it.
+fn unquote(text: &str) -> Option<String> {
+    let string = text.strip_prefix('"')?.strip_suffix('"')?;
+    Some(string.replace("\\\"", "\"").replace("\\\\", "\\"))
 }
 
-fn handle_relation(cursor: &mut Cursor) -> Result<Relation, String> {
+fn handle_relation(cursor: &mut Cursor) -> Result<Relation, ParseError> {
     let mut args = handle_args(cursor)?;
-    let fields = args.remove("fields").unwrap_or_else(|| vec![]);
-    let references = args.remove("references").unwrap_or_else(|| vec![]);
-    Ok(Relation { fields, references })
+    let fields = args.remove("fields").unwrap_or_default();
+    let references = args.remove("references").unwrap_or_default();
+    Ok(Relation { fields, references, ..Default::default() })
 }
 
-fn handle_args(cursor: &mut Cursor) -> Result<HashMap<String, Vec<String>>, String> {
+fn handle_args(cursor: &mut Cursor) -> Result<HashMap<String, Vec<String>>, ParseError> {
     let mut paren_count = 1;
     let mut args: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -229,15 +1008,18 @@ fn handle_args(cursor: &mut Cursor) -> Result<HashMap<String, Vec<String>>, Stri
     cursor.consume("(")?;
 
     while paren_count > 0 {
-        if cursor.try_consume("type_expression") {
+        // A positional array at the top level, eg. @@unique([a, b]), is shorthand for
+        // the fields argument.
+        if paren_count == 1 && cursor.current().kind() == "array" {
+            args.insert("fields".to_string(), handle_array(cursor)?);
+        } else if cursor.try_consume("type_expression") {
             let key = handle_identifier(cursor)?;
 
             cursor.consume(":")?;
 
-            match cursor.current().kind() {
-                "array" => { args.insert(key, handle_array(cursor)?); },
-                _ => ()
-            };
+            if cursor.current().kind() == "array" {
+                args.insert(key, handle_array(cursor)?);
+            }
         } else if cursor.try_consume("(") {
             paren_count += 1;
         } else if cursor.try_consume(")") {
@@ -250,7 +1032,7 @@ fn handle_args(cursor: &mut Cursor) -> Result<HashMap<String, Vec<String>>, Stri
     Ok(args)
 }
 
-fn handle_array(cursor: &mut Cursor) -> Result<Vec<String>, String> {
+fn handle_array(cursor: &mut Cursor) -> Result<Vec<String>, ParseError> {
     cursor.consume("array")?;
     cursor.consume("[")?;
 
@@ -277,3 +1059,315 @@ fn gather_nodes(root: Node) -> Vec<Node> {
 
     nodes
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::conflicts::{Aggregate, ConflictPolicy};
+    use crate::normalize::Normalizer;
+    use crate::prisma_parser::*;
+
+    const SCHEMA: &str = r#"
+        /// A person who owns toys.
+        /// @prismerge.dedupeBy([name, email])
+        /// @prismerge.conflict(newest: updatedAt)
+        model Owner {
+          id        String   @id
+          /// @prismerge.normalize(trim, lowercase)
+          name      String
+          email     String   // @prismerge.normalize(trim)
+          toyCount  Int      /// @prismerge.conflict(sum)
+          updatedAt DateTime
+        }
+
+        /// @prismerge.skip
+        model AuditLog {
+          id      String @id
+          message String
+        }
+
+        // @prismerge.skip
+        model Toy {
+          id      String @id
+          name    String
+          ownerId String
+          owner   Owner  @relation(fields: [ownerId], references: [id])
+
+          @@unique([name, ownerId])
+        }
+    "#;
+
+    #[test]
+    fn parses_positional_unique_fields() {
+        let schema = parse(SCHEMA).unwrap();
+        let toy = &schema.models["Toy"];
+        assert!(toy.unique.as_ref().unwrap().column_names == vec!["name", "ownerId"]);
+    }
+
+    #[test]
+    fn applies_model_directives() {
+        let schema = parse(SCHEMA).unwrap();
+        let owner = &schema.models["Owner"];
+
+        assert!(owner.unique.as_ref().unwrap().column_names == vec!["name", "email"]);
+        assert!(owner.conflict_policy == ConflictPolicy::Newest("updatedAt".to_string()));
+        assert!(!owner.skip);
+        assert!(schema.models["AuditLog"].skip);
+        assert!(!schema.models["Toy"].skip);
+    }
+
+    #[test]
+    fn applies_column_directives() {
+        let schema = parse(SCHEMA).unwrap();
+        let owner = &schema.models["Owner"];
+
+        assert!(owner.get_col("name").unwrap().normalizers == vec![Normalizer::Trim, Normalizer::Lowercase]);
+        assert!(owner.get_col("toyCount").unwrap().aggregate == Some(Aggregate::Sum));
+        assert!(owner.get_col("email").unwrap().normalizers.is_empty());
+        assert!(owner.get_col("updatedAt").unwrap().aggregate.is_none());
+    }
+
+    #[test]
+    fn parses_orphan_policies() {
+        let schema = parse(r#"
+            model Owner {
+              id String @id
+            }
+
+            model Toy {
+              id       String  @id
+              ownerId  String
+              owner    Owner   @relation(fields: [ownerId], references: [id]) /// @prismerge.orphans(keep)
+              buyerId  String?
+              buyer    Owner?  @relation(fields: [buyerId], references: [id])
+            }
+        "#).unwrap();
+
+        let toy = &schema.models["Toy"];
+        assert!(toy.get_col("owner").unwrap().orphan_policy() == OrphanPolicy::Keep);
+        assert!(toy.get_col("buyer").unwrap().orphan_policy() == OrphanPolicy::Null);
+
+        let error = parse(r#"
+            model Owner {
+              id String @id
+            }
+
+            model Toy {
+              id      String @id
+              ownerId String
+              /// @prismerge.orphans(null)
+              owner   Owner  @relation(fields: [ownerId], references: [id])
+            }
+        "#).unwrap_err();
+
+        assert!(error.to_string().contains("Relation owner is required"));
+    }
+
+    #[test]
+    fn parses_defaults_and_multiple_attributes() {
+        let schema = parse(r#"
+            enum Kind {
+              PLUSH
+            }
+
+            model Toy {
+              id       String   @id @default(uuid())
+              name     String   @default("Mr. \"Potato\" Head") @db.VarChar(255)
+              count    Int      @default(-1)
+              price    Float    @default(1.5)
+              fragile  Boolean  @default(true)
+              kind     Kind     @default(PLUSH)
+              label    String   @db.Text @unique
+              madeAt   DateTime @default(now())
+              editedAt DateTime @updatedAt
+            }
+        "#).unwrap();
+
+        let toy = &schema.models["Toy"];
+        let default = |name: &str| toy.get_col(name).unwrap().default.clone();
+
+        assert!(toy.get_col("id").unwrap().primary_key);
+        assert!(default("id").is_none());
+        assert!(default("name").as_deref() == Some("'Mr. \"Potato\" Head'"));
+        assert!(default("count").as_deref() == Some("-1"));
+        assert!(default("price").as_deref() == Some("1.5"));
+        assert!(default("fragile").as_deref() == Some("1"));
+        assert!(default("kind").as_deref() == Some("'PLUSH'"));
+        assert!(default("madeAt").is_none());
+        assert!(toy.get_col("label").unwrap().unique);
+
+        let default_function = |name: &str| toy.get_col(name).unwrap().default_function.clone();
+        assert!(default_function("id").as_deref() == Some("uuid"));
+        assert!(default_function("madeAt").as_deref() == Some("now"));
+        assert!(default_function("name").is_none());
+        assert!(toy.get_col("editedAt").unwrap().updated_at);
+        assert!(!toy.get_col("madeAt").unwrap().updated_at);
+    }
+
+    #[test]
+    fn rejects_unknown_directives() {
+        assert!(parse("/// @prismerge.bogus\nmodel Foo {\n  id String @id\n}").is_err());
+        assert!(parse("/// @prismerge.dedupeBy([nope])\nmodel Foo {\n  id String @id\n}").is_err());
+        assert!(parse("/// @prismerge.dedupeByHash\nmodel Foo {\n  id String @id\n  name String @unique\n}").is_err());
+    }
+
+    #[test]
+    fn parses_ignore_attributes() {
+        let schema = parse(r#"
+            /// @prismerge.ignore(dedup)
+            model Sighting {
+              place String
+              note  String? @ignore
+
+              @@ignore
+            }
+
+            model Toy {
+              id String @id
+            }
+        "#).unwrap();
+
+        let sighting = &schema.models["Sighting"];
+        assert!(sighting.ignored);
+        assert!(sighting.ignore_policy == IgnorePolicy::Dedup);
+        assert!(sighting.get_col("note").unwrap().ignored);
+        assert!(!sighting.get_col("place").unwrap().ignored);
+        assert!(!schema.models["Toy"].ignored);
+    }
+
+    #[test]
+    fn collects_errors_with_locations() {
+        let errors = parse(r#"model Toy {
+  id   String @id
+  name String @default("x"
+}
+
+/// @prismerge.bogus
+model Part {
+  id String @id
+}
+
+model {
+  id String @id
+}
+"#).unwrap_err().errors;
+
+        let locations: Vec<(&str, usize, usize)> = errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.line, error.column))
+            .collect();
+
+        assert!(locations == vec![
+            ("Missing )", 3, 27),
+            ("Unknown directive @prismerge.bogus on model Part", 6, 1),
+            ("Expected a model name", 11, 1)
+        ]);
+
+        assert!(errors[0].snippet == "  name String @default(\"x\"");
+    }
+
+    #[test]
+    fn formats_errors_with_snippets() {
+        let source = "model Toy {\n  id Strin@g @id\n}";
+        let error = ParseError::new("Unexpected @".to_string(), 22..23, source);
+
+        assert!(error.to_string() == "2:11: Unexpected @\n  |\n2 |   id Strin@g @id\n  |           ^");
+    }
+
+    #[test]
+    fn parses_other_declarations() {
+        let schema = parse(r#"
+            datasource db {
+              provider = "sqlite"
+              url      = "file:./dev.db?connection_limit=1"
+            }
+
+            generator client {
+              provider = "prisma-client-js"
+            }
+
+            enum Kind {
+              PLUSH @map("plush") // soft
+              PLASTIC
+
+              @@map("kinds")
+            }
+
+            view ToyCount {
+              ownerId String @unique
+              count   Int
+            }
+
+            type Address {
+              street String
+            }
+
+            model Toy {
+              id   String @id
+              kind Kind
+            }
+        "#).unwrap();
+
+        assert!(schema.models.keys().collect::<Vec<&String>>() == vec!["Toy"]);
+        assert!(schema.enums["Kind"].values == vec!["plush", "PLASTIC"]);
+        assert!(schema.views["ToyCount"].get_col("ownerId").unwrap().unique);
+        assert!(schema.types["Address"].get_col("street").is_some());
+        assert!(schema.generators[0].provider.as_deref() == Some("prisma-client-js"));
+
+        let datasource = schema.datasource.unwrap();
+        assert!(datasource.provider == "sqlite");
+        assert!(datasource.file_path().as_deref() == Some("./dev.db"));
+    }
+
+    #[test]
+    fn rejects_other_providers() {
+        let errors = parse(r#"
+            datasource db {
+              provider = "postgresql"
+              url      = env("DATABASE_URL")
+            }
+        "#).unwrap_err().errors;
+
+        assert!(errors.len() == 1);
+        assert!(errors[0].message == "Unsupported datasource provider \"postgresql\", prismerge only works with sqlite databases");
+    }
+
+    #[test]
+    fn parses_schemas_split_across_files() {
+        let schema = parse_files(&[
+            SchemaFile {
+                path: "schema/owner.prisma".to_string(),
+                source: "model Owner {\n  id String @id\n}".to_string()
+            },
+            SchemaFile {
+                path: "schema/toy.prisma".to_string(),
+                source: "model Toy {\n  id      String @id\n  ownerId String\n  owner   Owner  @relation(fields: [ownerId], references: [id])\n}".to_string()
+            }
+        ]).unwrap();
+
+        assert!(schema.sorted().iter().map(|model| model.name.as_str()).collect::<Vec<&str>>() == vec!["Owner", "Toy"]);
+    }
+
+    #[test]
+    fn reports_duplicates_and_dangling_relations() {
+        let errors = parse_files(&[
+            SchemaFile {
+                path: "a.prisma".to_string(),
+                source: "model Toy {\n  id String @id\n}".to_string()
+            },
+            SchemaFile {
+                path: "b.prisma".to_string(),
+                source: "model Toy {\n  id String @id\n}\n\nmodel Part {\n  id      String @id\n  ownerId String\n  owner   Owner  @relation(fields: [ownerId], references: [id])\n}".to_string()
+            }
+        ]).unwrap_err().errors;
+
+        let locations: Vec<(Option<&str>, &str, usize)> = errors
+            .iter()
+            .map(|error| (error.file.as_deref(), error.message.as_str(), error.line))
+            .collect();
+
+        assert!(locations == vec![
+            (Some("b.prisma"), "Toy is declared more than once", 1),
+            (Some("b.prisma"), "Relation owner points to Owner, which isn't declared in any schema file", 8)
+        ]);
+    }
+}
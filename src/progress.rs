@@ -33,14 +33,14 @@ impl ProgressIndicator {
             ProgressIndicator {
                 progress_type: ProgressType::Bar(pb),
                 model_name: model_name.to_string(),
-                total_rows: total_rows,
+                total_rows,
                 count: 0
             }
         } else {
             ProgressIndicator {
                 progress_type: ProgressType::Console,
                 model_name: model_name.to_string(),
-                total_rows: total_rows,
+                total_rows,
                 count: 0
             }
         }
@@ -0,0 +1,179 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{fs::{File, OpenOptions}, io::Write};
+
+use crate::utils::quote_literal;
+
+pub const DEFAULT_REJECTS_TABLE: &str = "_prismerge_rejects";
+
+/* A row that couldn't be merged, either because it couldn't be read from its input or
+ * because one of the statements that copy it into the merged database failed, eg. on
+ * a constraint violation. Rejected rows are left out of the merged database and
+ * recorded so they can be inspected and fixed up later.
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct Reject {
+    pub input: String,
+    pub model: String,
+
+    // The row's primary key in its input, if it has one and it could be read.
+    pub old_id: Option<String>,
+
+    pub statement: String,
+    pub error: String
+}
+
+// Where rejected rows are written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectSink {
+    // A table in the merged database.
+    Table(String),
+
+    // A file containing one JSON object per line.
+    File(String)
+}
+
+impl Default for RejectSink {
+    fn default() -> Self {
+        RejectSink::Table(DEFAULT_REJECTS_TABLE.to_string())
+    }
+}
+
+impl RejectSink {
+    pub fn describe(self: &Self) -> String {
+        match self {
+            RejectSink::Table(name) => format!("the {} table", name),
+            RejectSink::File(path) => path.clone()
+        }
+    }
+}
+
+/* Records rejected rows as they happen and aborts the merge once more than
+ * `max_errors` of them have been rejected. The table or file is only created once
+ * the first row is rejected.
+ */
+pub struct RejectLog {
+    pub sink: RejectSink,
+    pub max_errors: Option<u64>,
+    pub count: u64,
+    file: Option<File>
+}
+
+impl RejectLog {
+    pub fn new(sink: RejectSink, max_errors: Option<u64>) -> Self {
+        RejectLog { sink, max_errors, count: 0, file: None }
+    }
+
+    pub fn record(self: &mut Self, connection: &Connection, reject: Reject) -> Result<(), String> {
+        match &self.sink {
+            RejectSink::Table(name) => {
+                if self.count == 0 {
+                    connection.execute(
+                        format!(
+                            r#"
+                                CREATE TABLE IF NOT EXISTS "{table}" (
+                                    input TEXT NOT NULL,
+                                    model TEXT NOT NULL,
+                                    old_id TEXT,
+                                    statement TEXT NOT NULL,
+                                    error TEXT NOT NULL
+                                )
+                            "#,
+                            table = name
+                        ).as_str(),
+                        ()
+                    ).map_err(|e| e.to_string())?;
+                }
+
+                connection.execute(
+                    format!(
+                        "INSERT INTO \"{table}\" (input, model, old_id, statement, error) VALUES ({input}, {model}, {old_id}, {statement}, {error})",
+                        table = name,
+                        input = quote_literal(&reject.input),
+                        model = quote_literal(&reject.model),
+                        old_id = reject.old_id.as_deref().map(quote_literal).unwrap_or_else(|| "NULL".to_string()),
+                        statement = quote_literal(&reject.statement),
+                        error = quote_literal(&reject.error)
+                    ).as_str(),
+                    ()
+                ).map_err(|e| e.to_string())?;
+            }
+
+            RejectSink::File(path) => {
+                if self.file.is_none() {
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(path)
+                        .map_err(|e| format!("Unable to create rejects file {}: {}", path, e))?;
+
+                    self.file = Some(file);
+                }
+
+                let line = serde_json::to_string(&reject).map_err(|e| e.to_string())?;
+
+                writeln!(self.file.as_mut().unwrap(), "{}", line)
+                    .map_err(|e| format!("Unable to write to rejects file {}: {}", path, e))?;
+            }
+        }
+
+        self.count += 1;
+
+        match self.max_errors {
+            Some(max_errors) if self.count > max_errors => Err(
+                format!(
+                    "Aborting because {} rows were rejected, which is more than the maximum of {}. The rejected rows were written to {}.",
+                    self.count,
+                    max_errors,
+                    self.sink.describe()
+                )
+            ),
+
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rejects::*;
+
+    fn reject(old_id: &str) -> Reject {
+        Reject {
+            input: "a.db".to_string(),
+            model: "Toy".to_string(),
+            old_id: Some(old_id.to_string()),
+            statement: "INSERT INTO \"Toy\" ...".to_string(),
+            error: "NOT NULL constraint failed: Toy.name".to_string()
+        }
+    }
+
+    #[test]
+    fn writes_rejects_to_a_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut log = RejectLog::new(RejectSink::default(), None);
+
+        log.record(&conn, reject("1")).unwrap();
+        log.record(&conn, reject("2")).unwrap();
+
+        let old_ids: Vec<String> = conn
+            .prepare("SELECT old_id FROM _prismerge_rejects ORDER BY old_id")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .map(|old_id| old_id.unwrap())
+            .collect();
+
+        assert!(old_ids == vec!["1", "2"]);
+    }
+
+    #[test]
+    fn aborts_after_max_errors() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut log = RejectLog::new(RejectSink::default(), Some(1));
+
+        assert!(log.record(&conn, reject("1")).is_ok());
+        assert!(log.record(&conn, reject("2")).is_err());
+    }
+}
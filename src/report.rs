@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::{fs, path::Path, time::{Duration, UNIX_EPOCH}};
+
+use crate::config::Input;
+use crate::utils::format_duration;
+
+/* A machine-readable summary of a merge. The report is assembled as the merge
+ * progresses: each call to `merge_model()` produces a ModelReport, and the
+ * binary fills in the remaining bits (integrity problems, overall elapsed time)
+ * once merging is complete.
+ *
+ * Per-input counts are stored in vectors whose indices line up with the
+ * `inputs` list, i.e. `rows_read[2]` is the number of rows read from the
+ * third input.
+ */
+#[derive(Debug, Default, Serialize)]
+pub struct MergeReport {
+    pub output_path: String,
+    pub inputs: Vec<InputReport>,
+    pub models: Vec<ModelReport>,
+    pub totals: Totals,
+    pub elapsed_ms: u64
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct InputReport {
+    pub path: String,
+    pub label: String,
+    pub size_bytes: Option<u64>,
+    pub modified_at: Option<u64>
+}
+
+impl InputReport {
+    pub fn new(input: &Input) -> Self {
+        let metadata = fs::metadata(Path::new(&input.path)).ok();
+
+        InputReport {
+            path: input.path.clone(),
+            label: input.label.clone(),
+            size_bytes: metadata.as_ref().map(|m| m.len()),
+            modified_at: metadata
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ModelReport {
+    pub name: String,
+    pub rows_read: Vec<u64>,
+    pub rows_inserted: u64,
+    pub rows_deduplicated: u64,
+    pub rows_failed: u64,
+    pub rows_conflicted: u64,
+
+    // Rows with a foreign key that couldn't be translated, and how many of them were
+    // dropped as a result.
+    pub rows_orphaned: u64,
+    pub rows_dropped: u64,
+
+    pub integrity_violations: usize,
+    pub elapsed_ms: u64
+}
+
+impl ModelReport {
+    pub fn new(name: &str, input_count: usize) -> Self {
+        ModelReport {
+            name: name.to_string(),
+            rows_read: vec![0; input_count],
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Totals {
+    pub rows_read: u64,
+    pub rows_inserted: u64,
+    pub rows_deduplicated: u64,
+    pub rows_failed: u64,
+    pub rows_conflicted: u64,
+    pub rows_orphaned: u64,
+    pub rows_dropped: u64,
+    pub integrity_violations: usize
+}
+
+impl MergeReport {
+    pub fn new(output_path: &str, inputs: &[Input]) -> Self {
+        MergeReport {
+            output_path: output_path.to_string(),
+            inputs: inputs.iter().map(InputReport::new).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_model_mut(self: &mut Self, name: &str) -> Option<&mut ModelReport> {
+        self.models.iter_mut().find(|model| model.name == name)
+    }
+
+    // Recompute the totals from the individual model reports.
+    pub fn finish(self: &mut Self, elapsed: &Duration) {
+        let mut totals = Totals::default();
+
+        for model in self.models.iter() {
+            totals.rows_read += model.rows_read.iter().sum::<u64>();
+            totals.rows_inserted += model.rows_inserted;
+            totals.rows_deduplicated += model.rows_deduplicated;
+            totals.rows_failed += model.rows_failed;
+            totals.rows_conflicted += model.rows_conflicted;
+            totals.rows_orphaned += model.rows_orphaned;
+            totals.rows_dropped += model.rows_dropped;
+            totals.integrity_violations += model.integrity_violations;
+        }
+
+        self.totals = totals;
+        self.elapsed_ms = elapsed.as_millis() as u64;
+    }
+
+    pub fn to_json(self: &Self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn write_to(self: &Self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_json()).map_err(|e| format!("Unable to write report to {}: {}", path, e))
+    }
+
+    // Print a short human-readable summary, one line per model.
+    pub fn print_summary(self: &Self) {
+        for model in self.models.iter() {
+            println!(
+                "{}: read {}, inserted {}, deduplicated {} ({} with conflicts), failed {}, orphaned {} ({} dropped) in {}",
+                model.name,
+                model.rows_read.iter().sum::<u64>(),
+                model.rows_inserted,
+                model.rows_deduplicated,
+                model.rows_conflicted,
+                model.rows_failed,
+                model.rows_orphaned,
+                model.rows_dropped,
+                format_duration(&Duration::from_millis(model.elapsed_ms))
+            );
+        }
+
+        println!(
+            "Total: read {}, inserted {}, deduplicated {} ({} with conflicts), failed {}, orphaned {} ({} dropped), {} integrity problems",
+            self.totals.rows_read,
+            self.totals.rows_inserted,
+            self.totals.rows_deduplicated,
+            self.totals.rows_conflicted,
+            self.totals.rows_failed,
+            self.totals.rows_orphaned,
+            self.totals.rows_dropped,
+            self.totals.integrity_violations
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Input;
+    use crate::report::*;
+    use std::time::Duration;
+
+    #[test]
+    fn finish_computes_totals() {
+        let mut report = MergeReport::new("merged.db", &[Input::from_path("a.db"), Input::from_path("b.db")]);
+
+        report.models.push(ModelReport {
+            rows_read: vec![3, 2],
+            rows_inserted: 4,
+            rows_deduplicated: 1,
+            integrity_violations: 2,
+            ..ModelReport::new("Owner", 2)
+        });
+
+        report.models.push(ModelReport {
+            rows_read: vec![1, 0],
+            rows_inserted: 0,
+            rows_failed: 1,
+            rows_orphaned: 1,
+            rows_dropped: 1,
+            ..ModelReport::new("TodoList", 2)
+        });
+
+        report.finish(&Duration::from_millis(1500));
+
+        assert!(report.totals.rows_read == 6);
+        assert!(report.totals.rows_inserted == 4);
+        assert!(report.totals.rows_deduplicated == 1);
+        assert!(report.totals.rows_failed == 1);
+        assert!(report.totals.rows_orphaned == 1);
+        assert!(report.totals.rows_dropped == 1);
+        assert!(report.totals.integrity_violations == 2);
+        assert!(report.elapsed_ms == 1500);
+    }
+}
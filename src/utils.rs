@@ -13,6 +13,12 @@ pub fn format_duration(duration: &Duration) -> String {
     }
 }
 
+// Wrap the given string in single quotes so it can be interpolated into a SQL
+// statement, escaping any single quotes it contains.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::*;
@@ -42,4 +48,10 @@ mod tests {
         assert!(format_duration(&Duration::from_secs(4201)) == "1h10m01s");
         assert!(format_duration(&Duration::from_secs(4259)) == "1h10m59s");
     }
+
+    #[test]
+    fn quote_literal_escapes_quotes() {
+        assert!(quote_literal("Woody") == "'Woody'");
+        assert!(quote_literal("'Woody'") == "'''Woody'''");
+    }
 }
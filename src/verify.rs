@@ -0,0 +1,357 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt;
+
+use crate::data::{Model, Schema};
+use crate::migrations;
+
+/* Checks a database for problems that SQLite happily stores but that break the
+ * assumptions of the Prisma schema, along with outright corruption:
+ *
+ * Foreign keys:   rows whose foreign keys point at parent rows that don't exist, as
+ *                 reported by `pragma_foreign_key_check`.
+ * Unique keys:    sets of rows that share the values of one of the model's unique
+ *                 keys, whether or not the database has a unique index for it.
+ * Integrity:      the messages reported by `PRAGMA integrity_check`, if it finds any
+ *                 problems.
+ */
+#[derive(Debug, Default, Serialize)]
+pub struct Verification {
+    pub foreign_keys: Vec<ForeignKeyViolation>,
+    pub unique_keys: Vec<UniqueViolation>,
+    pub integrity: Vec<String>
+}
+
+impl Verification {
+    pub fn is_ok(self: &Self) -> bool {
+        self.foreign_keys.is_empty() && self.unique_keys.is_empty() && self.integrity.is_empty()
+    }
+}
+
+// A row whose foreign key doesn't match any row of the parent table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+
+    // None for WITHOUT ROWID tables.
+    pub rowid: Option<i64>,
+
+    pub parent: String,
+
+    // The index of the foreign key in `pragma_foreign_key_list`.
+    pub foreign_key_index: i64,
+
+    // The foreign key's columns, and their quoted values in the offending row, joined
+    // with commas.
+    pub columns: Vec<String>,
+    pub value: Option<String>
+}
+
+impl fmt::Display for ForeignKeyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = match self.rowid {
+            Some(rowid) => format!("row {}", rowid),
+            None => "a row".to_string()
+        };
+
+        write!(
+            f,
+            "{}: {} points to a missing {} via foreign key {} ({} = {})",
+            self.table,
+            row,
+            self.parent,
+            self.foreign_key_index,
+            self.columns.join(", "),
+            self.value.as_deref().unwrap_or("?")
+        )
+    }
+}
+
+// A set of rows that share the same unique key.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UniqueViolation {
+    pub table: String,
+    pub columns: Vec<String>,
+
+    // The quoted values of the key's columns.
+    pub value: String,
+    pub count: u64
+}
+
+impl fmt::Display for UniqueViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} rows share the unique key ({}) = ({})",
+            self.table,
+            self.count,
+            self.columns.join(", "),
+            self.value
+        )
+    }
+}
+
+// Find the rows of the given table, or of all tables, whose foreign keys don't point at
+// an existing parent row.
+pub fn foreign_key_violations(conn: &Connection, table: Option<&str>) -> Result<Vec<ForeignKeyViolation>, String> {
+    let check_sql = match table {
+        Some(table) => format!("SELECT \"table\", rowid, parent, fkid FROM pragma_foreign_key_check('{}')", table),
+        None => "SELECT \"table\", rowid, parent, fkid FROM pragma_foreign_key_check".to_string()
+    };
+
+    let mut stmt = conn.prepare(check_sql.as_str()).map_err(|e| e.to_string())?;
+
+    let checks = stmt
+        .query_map((), |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, String>(2)?, row.get::<_, i64>(3)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<(String, Option<i64>, String, i64)>>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut violations = vec![];
+
+    for (table, rowid, parent, foreign_key_index) in checks {
+        let columns: Vec<String> = conn
+            .prepare(format!("SELECT \"from\" FROM pragma_foreign_key_list('{}') WHERE id = ?1 ORDER BY seq", table).as_str())
+            .and_then(|mut stmt| {
+                stmt.query_map([foreign_key_index], |row| row.get(0))?.collect()
+            })
+            .map_err(|e| e.to_string())?;
+
+        // Read the dangling value from the offending row.
+        let value = match rowid {
+            Some(rowid) => {
+                let values_sql = format!(
+                    "SELECT {} FROM \"{}\" WHERE rowid = ?1",
+                    columns
+                        .iter()
+                        .map(|column| format!("quote(\"{}\")", column))
+                        .collect::<Vec<String>>()
+                        .join(" || ', ' || "),
+                    table
+                );
+
+                conn.query_row(values_sql.as_str(), [rowid], |row| row.get(0)).ok()
+            }
+
+            None => None
+        };
+
+        violations.push(ForeignKeyViolation { table, rowid, parent, foreign_key_index, columns, value });
+    }
+
+    Ok(violations)
+}
+
+// Find the sets of rows that share the values of one of the model's unique keys, i.e.
+// its unique indices and the key used to detect duplicates. Rows with NULLs in the key
+// don't count, since NULLs are distinct from each other. Columns the table doesn't
+// have are read from their old names if they were renamed. Keys with columns that
+// can't be found either way are skipped, since the table predates them.
+pub fn unique_violations(conn: &Connection, model: &Model) -> Result<Vec<UniqueViolation>, String> {
+    let existing_columns = model.existing_columns(conn);
+    let applied_migrations = migrations::applied_migrations(conn)?;
+
+    let mut keys: Vec<&Vec<String>> = model.indices
+        .iter()
+        .filter(|index| index.unique)
+        .map(|index| &index.column_names)
+        .collect();
+
+    if let Some(unique) = &model.unique {
+        keys.push(&unique.column_names);
+    }
+
+    keys.sort();
+    keys.dedup();
+
+    let mut violations = vec![];
+
+    for columns in keys {
+        let existing_names: Option<Vec<&str>> = columns
+            .iter()
+            .map(|name| {
+                if existing_columns.contains(name) {
+                    return Some(name.as_str());
+                }
+
+                model.get_col(name)?.old_name_in(&existing_columns, applied_migrations.as_ref())
+            })
+            .collect();
+
+        let Some(existing_names) = existing_names else { continue };
+
+        let quoted_columns: Vec<String> = existing_names
+            .iter()
+            .map(|name| format!("\"{}\".\"{}\"", model.name, name))
+            .collect();
+
+        let duplicates_sql = format!(
+            "SELECT {values}, COUNT(*) FROM \"{table}\" WHERE {not_null} GROUP BY {columns} HAVING COUNT(*) > 1",
+            values = quoted_columns
+                .iter()
+                .map(|column| format!("quote({})", column))
+                .collect::<Vec<String>>()
+                .join(" || ', ' || "),
+            table = model.name,
+            not_null = quoted_columns
+                .iter()
+                .map(|column| format!("{} IS NOT NULL", column))
+                .collect::<Vec<String>>()
+                .join(" AND "),
+            columns = quoted_columns.join(", ")
+        );
+
+        let mut stmt = conn.prepare(duplicates_sql.as_str()).map_err(|e| e.to_string())?;
+
+        let duplicates = stmt
+            .query_map((), |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<rusqlite::Result<Vec<(String, u64)>>>()
+            .map_err(|e| e.to_string())?;
+
+        for (value, count) in duplicates {
+            violations.push(UniqueViolation { table: model.name.clone(), columns: columns.clone(), value, count });
+        }
+    }
+
+    Ok(violations)
+}
+
+// Run `PRAGMA integrity_check`, returning the problems it reports, if any.
+pub fn integrity_check(conn: &Connection) -> Result<Vec<String>, String> {
+    check_pragma(conn, "integrity_check")
+}
+
+// Like integrity_check(), but skips the slower checks, eg. that indices match their
+// tables.
+pub fn quick_check(conn: &Connection) -> Result<Vec<String>, String> {
+    check_pragma(conn, "quick_check")
+}
+
+fn check_pragma(conn: &Connection, pragma: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare(format!("PRAGMA {}", pragma).as_str()).map_err(|e| e.to_string())?;
+
+    let messages = stmt
+        .query_map((), |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<rusqlite::Result<Vec<String>>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(messages.into_iter().filter(|message| message != "ok").collect())
+}
+
+// Run all the checks on the given database. Unique keys are only checked if there's a
+// schema, and only for the models whose tables exist.
+pub fn verify(conn: &Connection, schema: Option<&Schema>) -> Result<Verification, String> {
+    let mut verification = Verification {
+        foreign_keys: foreign_key_violations(conn, None)?,
+        integrity: integrity_check(conn)?,
+        ..Default::default()
+    };
+
+    if let Some(schema) = schema {
+        let mut models: Vec<&Model> = schema.models.values().collect();
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for model in models {
+            if !model.existing_columns(conn).is_empty() {
+                verification.unique_keys.extend(unique_violations(conn, model)?);
+            }
+        }
+    }
+
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prisma_parser;
+    use crate::verify::*;
+
+    fn create_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(r#"
+            PRAGMA foreign_keys = OFF;
+            CREATE TABLE "Owner" ("id" TEXT NOT NULL PRIMARY KEY, "name" TEXT NOT NULL);
+            CREATE TABLE "Toy" (
+                "id" TEXT NOT NULL PRIMARY KEY,
+                "name" TEXT NOT NULL,
+                "ownerId" TEXT NOT NULL REFERENCES "Owner" ("id")
+            );
+
+            INSERT INTO "Owner" VALUES ('a', 'Woody'), ('b', 'Woody');
+            INSERT INTO "Toy" VALUES ('1', 'Rex', 'a'), ('2', 'Slinky', 'missing');
+        "#).unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn finds_dangling_foreign_keys() {
+        let conn = create_database();
+        let violations = foreign_key_violations(&conn, Some("Toy")).unwrap();
+
+        assert!(violations.len() == 1);
+        assert!(violations[0].rowid == Some(2));
+        assert!(violations[0].parent == "Owner");
+        assert!(violations[0].columns == vec!["ownerId"]);
+        assert!(violations[0].value.as_deref() == Some("'missing'"));
+    }
+
+    #[test]
+    fn finds_duplicate_unique_keys() {
+        let conn = create_database();
+
+        let schema = prisma_parser::parse(r#"
+            model Owner {
+              id   String @id
+              name String @unique
+            }
+        "#).unwrap();
+
+        let verification = verify(&conn, Some(&schema)).unwrap();
+
+        assert!(!verification.is_ok());
+        assert!(verification.unique_keys.len() == 1);
+        assert!(verification.unique_keys[0].value == "'Woody'");
+        assert!(verification.unique_keys[0].count == 2);
+        assert!(verification.foreign_keys.len() == 1);
+        assert!(verification.integrity.is_empty());
+    }
+
+    #[test]
+    fn checks_unique_keys_the_table_predates() {
+        let conn = create_database();
+
+        // Toy's name used to be called title, and Owner has no email column yet. The old
+        // names are checked instead, and keys that can't be found are skipped rather
+        // than compared as string literals.
+        conn.execute_batch(r#"
+            ALTER TABLE "Toy" RENAME COLUMN "name" TO "title";
+            INSERT INTO "Toy" VALUES ('3', 'Rex', 'a');
+        "#).unwrap();
+
+        let mut schema = prisma_parser::parse(r#"
+            model Owner {
+              id    String @id
+              email String @unique
+            }
+
+            model Toy {
+              id      String @id
+              name    String @unique
+              ownerId String
+            }
+        "#).unwrap();
+
+        schema.models.get_mut("Toy").unwrap().add_rename("name", "title").unwrap();
+
+        let verification = verify(&conn, Some(&schema)).unwrap();
+
+        assert!(verification.unique_keys.len() == 1);
+        assert!(verification.unique_keys[0].table == "Toy");
+        assert!(verification.unique_keys[0].columns == vec!["name"]);
+        assert!(verification.unique_keys[0].value == "'Rex'");
+    }
+}